@@ -0,0 +1,371 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Display;
+use std::io::{Read, Write};
+use std::ops::Bound;
+use std::str::FromStr;
+
+use integer_encoding::{VarIntReader, VarIntWriter};
+use serde::{Deserialize, Serialize};
+
+use crate::server_entry::ServerId;
+
+/// A property of a server reported by a status ping or derived by the
+/// pipeline. The discriminant is the id used on disk and on the wire,
+/// so variants must never be renumbered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(u8)]
+pub enum Attribute {
+    ProtocolVersion = 0,
+    Version = 1,
+    OnlineMode = 2,
+    Modloader = 3,
+    Country = 4,
+    /// Players online at the latest status ping.
+    Online = 5,
+    /// Hash of the server icon, e.g. the hex SHA-256 of the PNG.
+    FaviconHash = 6,
+    /// Id of the network the server belongs to, see
+    /// `network::network_id`. Set by `network::store_networks`.
+    Network = 7,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttrKind {
+    Bool,
+    Int,
+    Str,
+}
+
+impl Attribute {
+    pub const ALL: [Attribute; 8] = [
+        Attribute::ProtocolVersion,
+        Attribute::Version,
+        Attribute::OnlineMode,
+        Attribute::Modloader,
+        Attribute::Country,
+        Attribute::Online,
+        Attribute::FaviconHash,
+        Attribute::Network,
+    ];
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.get(id as usize).copied()
+    }
+
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Attribute::ProtocolVersion => "protocol_version",
+            Attribute::Version => "version",
+            Attribute::OnlineMode => "online_mode",
+            Attribute::Modloader => "modloader",
+            Attribute::Country => "country",
+            Attribute::Online => "online",
+            Attribute::FaviconHash => "favicon_hash",
+            Attribute::Network => "network",
+        }
+    }
+
+    pub fn kind(self) -> AttrKind {
+        match self {
+            Attribute::ProtocolVersion | Attribute::Online | Attribute::Network => AttrKind::Int,
+            Attribute::OnlineMode => AttrKind::Bool,
+            Attribute::Version
+            | Attribute::Modloader
+            | Attribute::Country
+            | Attribute::FaviconHash => AttrKind::Str,
+        }
+    }
+
+    /// Parses a value of this attribute from its text form, e.g. on the
+    /// command line.
+    pub fn parse_value(self, s: &str) -> Result<AttrValue, Box<dyn Error + Send + Sync>> {
+        Ok(match self.kind() {
+            AttrKind::Bool => AttrValue::Bool(s.parse()?),
+            AttrKind::Int => AttrValue::Int(s.parse()?),
+            AttrKind::Str => AttrValue::Str(s.to_string()),
+        })
+    }
+}
+
+impl FromStr for Attribute {
+    type Err = Box<dyn Error + Send + Sync>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Attribute::ALL
+            .into_iter()
+            .find(|attribute| attribute.name() == s)
+            .ok_or_else(|| format!("unknown attribute {s}").into())
+    }
+}
+
+impl Display for Attribute {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Values of one attribute all have the attribute's kind, so they order
+/// naturally; strings compare bytewise.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AttrValue {
+    Bool(bool),
+    Int(i64),
+    Str(String),
+}
+
+/// Longest string attribute value accepted.
+pub const MAX_ATTR_STR_LEN: usize = 256;
+
+impl AttrValue {
+    pub fn kind(&self) -> AttrKind {
+        match self {
+            AttrValue::Bool(_) => AttrKind::Bool,
+            AttrValue::Int(_) => AttrKind::Int,
+            AttrValue::Str(_) => AttrKind::Str,
+        }
+    }
+
+    /*--- Attribute Value ------------------------------|
+    | kind          | type              | size          |
+    |---------------------------------------------------|
+    | bool          | u8                | 1 byte        |
+    | int           | zigzag varint     | variable size |
+    | str           | varint + string   | variable size |
+    |--------------------------------------------------*/
+    // The kind is not written; it follows from the attribute.
+    pub fn serialize(&self, buf: &mut impl Write) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match self {
+            AttrValue::Bool(value) => buf.write_all(&[*value as u8])?,
+            AttrValue::Int(value) => {
+                buf.write_varint(*value)?;
+            }
+            AttrValue::Str(value) => {
+                buf.write_varint(value.len())?;
+                buf.write_all(value.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn deserialize(
+        buf: &mut impl Read,
+        kind: AttrKind,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Ok(match kind {
+            AttrKind::Bool => {
+                let mut value = [0u8; 1];
+                buf.read_exact(&mut value)?;
+                AttrValue::Bool(value[0] != 0)
+            }
+            AttrKind::Int => AttrValue::Int(buf.read_varint()?),
+            AttrKind::Str => {
+                let len: usize = buf.read_varint()?;
+                if len > MAX_ATTR_STR_LEN {
+                    return Err(format!("attribute value is {len} bytes long").into());
+                }
+                let mut value = vec![0u8; len];
+                buf.read_exact(&mut value)?;
+                AttrValue::Str(String::from_utf8(value)?)
+            }
+        })
+    }
+}
+
+impl Display for AttrValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AttrValue::Bool(value) => write!(f, "{value}"),
+            AttrValue::Int(value) => write!(f, "{value}"),
+            AttrValue::Str(value) => f.write_str(value),
+        }
+    }
+}
+
+/// Checks that `value` fits `attribute`.
+pub fn check_value(
+    attribute: Attribute,
+    value: &AttrValue,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if value.kind() != attribute.kind() {
+        return Err(format!("{value:?} is not a valid {attribute}").into());
+    }
+    if let AttrValue::Str(value) = value {
+        if value.len() > MAX_ATTR_STR_LEN {
+            return Err(format!("{attribute} is {} bytes long", value.len()).into());
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttrPredicate {
+    Eq(AttrValue),
+    /// Inclusive bounds; `None` is unbounded.
+    Range {
+        min: Option<AttrValue>,
+        max: Option<AttrValue>,
+    },
+    /// String values starting with the prefix, e.g. `1.20` for every
+    /// 1.20.x version.
+    Prefix(String),
+}
+
+impl AttrPredicate {
+    pub fn matches(&self, value: &AttrValue) -> bool {
+        match self {
+            AttrPredicate::Eq(expected) => value == expected,
+            AttrPredicate::Range { min, max } => {
+                min.as_ref().is_none_or(|min| value >= min)
+                    && max.as_ref().is_none_or(|max| value <= max)
+            }
+            AttrPredicate::Prefix(prefix) => {
+                matches!(value, AttrValue::Str(value) if value.starts_with(prefix.as_str()))
+            }
+        }
+    }
+}
+
+/// Secondary index of one attribute: the servers holding each value.
+#[derive(Debug, Default, Clone)]
+pub struct AttributeIndex {
+    /// Sorted server ids per value.
+    values: BTreeMap<AttrValue, Vec<ServerId>>,
+}
+
+impl AttributeIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, value: AttrValue, server_id: ServerId) {
+        let server_ids = self.values.entry(value).or_default();
+        if let Err(index) = server_ids.binary_search(&server_id) {
+            server_ids.insert(index, server_id);
+        }
+    }
+
+    pub fn remove(&mut self, value: &AttrValue, server_id: ServerId) {
+        let Some(server_ids) = self.values.get_mut(value) else {
+            return;
+        };
+        if let Ok(index) = server_ids.binary_search(&server_id) {
+            server_ids.remove(index);
+        }
+        if server_ids.is_empty() {
+            self.values.remove(value);
+        }
+    }
+
+    /// Ids of the servers matching `predicate`, in ascending order.
+    pub fn find(&self, predicate: &AttrPredicate) -> Vec<ServerId> {
+        let mut res = match predicate {
+            AttrPredicate::Eq(value) => return self.values.get(value).cloned().unwrap_or_default(),
+            AttrPredicate::Range { min, max } => {
+                if let (Some(min), Some(max)) = (min, max) {
+                    if min > max {
+                        return vec![];
+                    }
+                }
+                let start = min.clone().map_or(Bound::Unbounded, Bound::Included);
+                let end = max.clone().map_or(Bound::Unbounded, Bound::Included);
+                self.values
+                    .range((start, end))
+                    .flat_map(|(_, server_ids)| server_ids.iter().copied())
+                    .collect::<Vec<ServerId>>()
+            }
+            AttrPredicate::Prefix(prefix) => self
+                .values
+                .range(AttrValue::Str(prefix.clone())..)
+                .take_while(|(value, _)| predicate.matches(value))
+                .flat_map(|(_, server_ids)| server_ids.iter().copied())
+                .collect(),
+        };
+        res.sort_unstable();
+        res
+    }
+
+    /// Number of servers holding each value, in value order.
+    pub fn counts(&self) -> impl Iterator<Item = (&AttrValue, usize)> {
+        self.values
+            .iter()
+            .map(|(value, server_ids)| (value, server_ids.len()))
+    }
+
+    /// Number of distinct values.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equality_and_range() {
+        let mut index = AttributeIndex::new();
+        index.insert(AttrValue::Int(763), 2);
+        index.insert(AttrValue::Int(763), 0);
+        index.insert(AttrValue::Int(47), 1);
+        index.insert(AttrValue::Int(765), 3);
+
+        assert_eq!(
+            index.find(&AttrPredicate::Eq(AttrValue::Int(763))),
+            vec![0, 2]
+        );
+        let range = AttrPredicate::Range {
+            min: Some(AttrValue::Int(700)),
+            max: None,
+        };
+        assert_eq!(index.find(&range), vec![0, 2, 3]);
+        assert!(range.matches(&AttrValue::Int(765)));
+        assert!(!range.matches(&AttrValue::Int(47)));
+        let empty = AttrPredicate::Range {
+            min: Some(AttrValue::Int(800)),
+            max: Some(AttrValue::Int(700)),
+        };
+        assert!(index.find(&empty).is_empty());
+
+        index.remove(&AttrValue::Int(47), 1);
+        assert_eq!(index.len(), 2);
+
+        let mut versions = AttributeIndex::new();
+        for (server_id, version) in ["1.19.4", "1.20", "1.20.1", "1.20.4", "1.21"]
+            .iter()
+            .enumerate()
+        {
+            versions.insert(AttrValue::Str(version.to_string()), server_id as ServerId);
+        }
+        assert_eq!(
+            versions.find(&AttrPredicate::Prefix("1.20".to_string())),
+            vec![1, 2, 3]
+        );
+
+        let mut buf = vec![];
+        AttrValue::Int(-5).serialize(&mut buf).unwrap();
+        assert_eq!(
+            AttrValue::deserialize(&mut &buf[..], AttrKind::Int).unwrap(),
+            AttrValue::Int(-5)
+        );
+        assert_eq!(
+            "online_mode"
+                .parse::<Attribute>()
+                .unwrap()
+                .parse_value("true")
+                .unwrap(),
+            AttrValue::Bool(true)
+        );
+        assert!(check_value(Attribute::Country, &AttrValue::Int(1)).is_err());
+    }
+}
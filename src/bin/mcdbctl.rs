@@ -0,0 +1,505 @@
+use std::error::Error;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use integer_encoding::VarIntReader;
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use uuid::Uuid;
+
+use mcdb::cidr::Cidr;
+use mcdb::export::{JsonPlayerRef, JsonRecord};
+use mcdb::protocol::{self, RecentRequest, ServerScan, MAX_PAGE_LEN};
+use mcdb::recent::RecentInsert;
+use mcdb::server_entry::Server;
+
+const USAGE: &str = "usage: mcdbctl [--addr <host:port>] [--json] <command>
+
+commands:
+  server <addr>                    show a server and its players
+  player <uuid>                    show every record of a player
+  scan <cidr> [--limit <n>]        list the servers in a range
+  snapshot                         write a snapshot now
+  stats                            show server statistics
+  delete server <addr>             delete a server and its links
+  delete player <uuid>             delete a player and its links
+  tail [--limit <n>] [--follow]    show the latest sightings";
+
+/// Default for `--limit` of `scan` and `tail`.
+const DEFAULT_LIMIT: usize = 100;
+
+/// Time between polls of `tail --follow`.
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    async fn connect(addr: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let stream = TcpStream::connect(addr)
+            .await
+            .map_err(|err| format!("could not connect to {addr}: {err}"))?;
+        Ok(Client { stream })
+    }
+
+    /// Sends a request and returns the body of its response.
+    async fn request(
+        &mut self,
+        opcode: u8,
+        body: &[u8],
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let mut buf = Vec::with_capacity(1 + body.len());
+        buf.push(opcode);
+        buf.extend_from_slice(body);
+        self.stream.write_all(&buf).await?;
+        self.stream.flush().await?;
+        protocol::read_response(&mut self.stream).await
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    Table,
+    Json,
+}
+
+impl Output {
+    fn json(self, value: &impl Serialize) -> Result<(), Box<dyn Error + Send + Sync>> {
+        println!("{}", serde_json::to_string_pretty(value)?);
+        Ok(())
+    }
+}
+
+/// Prints `rows` as left-aligned columns under `header`.
+fn print_table(header: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = header.iter().map(|name| name.len()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let line = |cells: Vec<&str>| {
+        let mut line = String::new();
+        for (i, (cell, width)) in cells.iter().zip(&widths).enumerate() {
+            if i + 1 == cells.len() {
+                line.push_str(cell);
+            } else {
+                line.push_str(&format!("{cell:width$}  "));
+            }
+        }
+        println!("{}", line.trim_end());
+    };
+    line(header.to_vec());
+    for row in rows {
+        line(row.iter().map(String::as_str).collect());
+    }
+}
+
+fn print_fields(fields: &[(&str, String)]) {
+    let width = fields.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    for (name, value) in fields {
+        println!("{name:width$}  {value}");
+    }
+}
+
+/// Parses `--limit <n>` and `--follow` out of `args`.
+fn parse_options(args: &[String]) -> Result<(usize, bool), Box<dyn Error + Send + Sync>> {
+    let mut limit = DEFAULT_LIMIT;
+    let mut follow = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--limit" => limit = args.next().ok_or("--limit needs a value")?.parse()?,
+            "--follow" => follow = true,
+            _ => return Err(format!("unexpected argument {arg}").into()),
+        }
+    }
+    Ok((limit, follow))
+}
+
+fn arg<'a>(args: &'a [String], what: &str) -> Result<&'a str, Box<dyn Error + Send + Sync>> {
+    match args {
+        [arg] => Ok(arg),
+        [] => Err(format!("missing {what}").into()),
+        [_, extra, ..] => Err(format!("unexpected argument {extra}").into()),
+    }
+}
+
+async fn server(
+    client: &mut Client,
+    output: Output,
+    addr: SocketAddr,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let body = client
+        .request(
+            protocol::OP_GET_SERVER,
+            &Server::new(addr).serialize_pointer()?,
+        )
+        .await?;
+    let (server, players) = protocol::read_server_info(&mut &body[..])?;
+    let players: Vec<JsonPlayerRef> = players
+        .into_iter()
+        .map(|player| JsonPlayerRef {
+            name: player.name,
+            uuid: player.uuid,
+        })
+        .collect();
+    if output == Output::Json {
+        return output.json(&JsonRecord::Server {
+            addr: server.addr,
+            first_seen: server.first_seen,
+            last_seen: server.last_seen,
+            sources: server.sources,
+            hostnames: server.hostnames,
+            srv_targets: server.srv_targets,
+            motd: server.motd,
+            attributes: server.attributes,
+            players,
+        });
+    }
+    let attributes: Vec<String> = server
+        .attributes
+        .iter()
+        .map(|(attribute, value)| format!("{attribute}={value}"))
+        .collect();
+    print_fields(&[
+        ("address", server.addr.to_string()),
+        ("first seen", server.first_seen.to_string()),
+        ("last seen", server.last_seen.to_string()),
+        ("motd", server.motd),
+        ("hostnames", server.hostnames.join(", ")),
+        ("srv targets", server.srv_targets.join(", ")),
+        ("sources", server.sources.join(", ")),
+        ("attributes", attributes.join(", ")),
+        ("players", players.len().to_string()),
+    ]);
+    if !players.is_empty() {
+        println!();
+        let rows: Vec<Vec<String>> = players
+            .iter()
+            .map(|player| vec![player.name.clone(), player.uuid.to_string()])
+            .collect();
+        print_table(&["NAME", "UUID"], &rows);
+    }
+    Ok(())
+}
+
+async fn player(
+    client: &mut Client,
+    output: Output,
+    uuid: Uuid,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let body = client
+        .request(protocol::OP_GET_PLAYER, uuid.as_bytes())
+        .await?;
+    let records = protocol::read_player_records(&mut &body[..])?;
+    if output == Output::Json {
+        let records: Vec<JsonRecord> = records
+            .into_iter()
+            .map(|(player, servers)| JsonRecord::Player {
+                name: player.name,
+                uuid: player.uuid,
+                servers,
+            })
+            .collect();
+        return output.json(&records);
+    }
+    let mut rows = vec![];
+    for (player, servers) in records {
+        if servers.is_empty() {
+            rows.push(vec![
+                player.name.clone(),
+                player.uuid.to_string(),
+                String::new(),
+            ]);
+        }
+        for addr in servers {
+            rows.push(vec![
+                player.name.clone(),
+                player.uuid.to_string(),
+                addr.to_string(),
+            ]);
+        }
+    }
+    print_table(&["NAME", "UUID", "SERVER"], &rows);
+    Ok(())
+}
+
+async fn scan(
+    client: &mut Client,
+    output: Output,
+    cidr: Cidr,
+    limit: usize,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut servers = vec![];
+    let mut after = None;
+    while servers.len() < limit {
+        let request = protocol::serialize_server_scan(&ServerScan {
+            cidr,
+            after,
+            limit: (limit - servers.len()).min(MAX_PAGE_LEN),
+        })?;
+        let body = client.request(protocol::OP_SCAN_SERVERS, &request).await?;
+        let (next, page) = protocol::read_server_scan_page(&mut &body[..]).await?;
+        servers.extend(page);
+        match next {
+            Some(next) => after = Some(next),
+            None => break,
+        }
+    }
+    match output {
+        Output::Json => output.json(&servers),
+        Output::Table => {
+            let rows: Vec<Vec<String>> =
+                servers.iter().map(|addr| vec![addr.to_string()]).collect();
+            print_table(&["ADDRESS"], &rows);
+            Ok(())
+        }
+    }
+}
+
+async fn snapshot(client: &mut Client, output: Output) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let body = client.request(protocol::OP_SNAPSHOT, &[]).await?;
+    let summary = protocol::read_snapshot_summary(&mut &body[..]).await?;
+    match output {
+        Output::Json => output.json(&summary),
+        Output::Table => {
+            print_fields(&[
+                ("bytes", summary.bytes.to_string()),
+                ("elapsed ms", summary.elapsed_ms.to_string()),
+            ]);
+            Ok(())
+        }
+    }
+}
+
+async fn stats(client: &mut Client, output: Output) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let body = client.request(protocol::OP_STATS, &[]).await?;
+    let report = protocol::read_stats_report(&mut &body[..]).await?;
+    if output == Output::Json {
+        return output.json(&report);
+    }
+    print_fields(&[
+        ("servers", report.servers.to_string()),
+        ("players", report.players.to_string()),
+        ("uuids", report.uuids.to_string()),
+        ("links", report.links.to_string()),
+        ("seen last day", report.seen_last_day.to_string()),
+        ("seen last week", report.seen_last_week.to_string()),
+        ("seen last month", report.seen_last_month.to_string()),
+    ]);
+    let sections: [(&str, Vec<(String, usize)>); 4] = [
+        (
+            "/8",
+            report
+                .per_slash8
+                .iter()
+                .map(|(octet, count)| (format!("{octet}.0.0.0/8"), *count))
+                .collect(),
+        ),
+        (
+            "PORT",
+            report
+                .per_port
+                .iter()
+                .map(|(port, count)| (port.to_string(), *count))
+                .collect(),
+        ),
+        (
+            "VERSION",
+            report
+                .per_version
+                .iter()
+                .map(|(version, count)| (version.clone(), *count))
+                .collect(),
+        ),
+        (
+            "PROTOCOL",
+            report
+                .per_protocol
+                .iter()
+                .map(|(protocol, count)| (protocol.to_string(), *count))
+                .collect(),
+        ),
+    ];
+    for (name, mut counts) in sections {
+        if counts.is_empty() {
+            continue;
+        }
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let rows: Vec<Vec<String>> = counts
+            .into_iter()
+            .map(|(key, count)| vec![key, count.to_string()])
+            .collect();
+        println!();
+        print_table(&[name, "SERVERS"], &rows);
+    }
+    Ok(())
+}
+
+async fn delete(
+    client: &mut Client,
+    output: Output,
+    args: &[String],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (kind, key, deleted) = match args.first().map(String::as_str) {
+        Some("server") => {
+            let addr: SocketAddr = arg(&args[1..], "server address")?.parse()?;
+            let body = client
+                .request(
+                    protocol::OP_DELETE_SERVER,
+                    &Server::new(addr).serialize_pointer()?,
+                )
+                .await?;
+            (
+                "server",
+                addr.to_string(),
+                *body.first().unwrap_or(&0) as usize,
+            )
+        }
+        Some("player") => {
+            let uuid: Uuid = arg(&args[1..], "player uuid")?.parse()?;
+            let body = client
+                .request(protocol::OP_DELETE_PLAYER, uuid.as_bytes())
+                .await?;
+            ("player", uuid.to_string(), (&body[..]).read_varint()?)
+        }
+        _ => return Err("delete needs server <addr> or player <uuid>".into()),
+    };
+    match output {
+        Output::Json => output.json(&serde_json::json!({ kind: key, "deleted": deleted })),
+        Output::Table if deleted == 0 => Err(format!("unknown {kind} {key}").into()),
+        Output::Table => {
+            println!("deleted {kind} {key}");
+            Ok(())
+        }
+    }
+}
+
+async fn tail(
+    client: &mut Client,
+    output: Output,
+    limit: usize,
+    follow: bool,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let print =
+        |entries: &[RecentInsert], header: bool| -> Result<(), Box<dyn Error + Send + Sync>> {
+            match output {
+                // one object per line, so a follower can stream them
+                Output::Json => {
+                    for entry in entries {
+                        println!("{}", serde_json::to_string(entry)?);
+                    }
+                }
+                Output::Table => {
+                    let rows: Vec<Vec<String>> = entries
+                        .iter()
+                        .map(|entry| {
+                            vec![
+                                entry.seq.to_string(),
+                                entry.time.to_string(),
+                                entry.addr.to_string(),
+                                entry.players.to_string(),
+                            ]
+                        })
+                        .collect();
+                    if header {
+                        print_table(&["SEQ", "TIME", "ADDRESS", "PLAYERS"], &rows);
+                    } else {
+                        for row in rows {
+                            println!("{}", row.join("  "));
+                        }
+                    }
+                }
+            }
+            Ok(())
+        };
+
+    let mut request = RecentRequest {
+        after: None,
+        limit: limit.min(MAX_PAGE_LEN),
+    };
+    let mut header = true;
+    loop {
+        let body = client
+            .request(
+                protocol::OP_RECENT_INSERTS,
+                &protocol::serialize_recent_request(&request)?,
+            )
+            .await?;
+        let (latest, entries) = protocol::read_recent_page(&mut &body[..]).await?;
+        if let (Some(after), Some(first)) = (request.after, entries.first()) {
+            if first.seq > after + 1 {
+                eprintln!("mcdbctl: skipped {} sightings", first.seq - after - 1);
+            }
+        }
+        if !entries.is_empty() || header {
+            print(&entries, header)?;
+            header = false;
+        }
+        if !follow {
+            return Ok(());
+        }
+        request.after = Some(entries.last().map_or(latest, |entry| entry.seq));
+        if entries.len() < request.limit {
+            tokio::time::sleep(FOLLOW_INTERVAL).await;
+        }
+    }
+}
+
+async fn run(args: &[String]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut addr = "127.0.0.1:38282".to_string();
+    let mut output = Output::Table;
+    let mut args = args;
+    while let Some(arg) = args.first() {
+        match arg.as_str() {
+            "--addr" => {
+                addr = args.get(1).ok_or("--addr needs a value")?.clone();
+                args = &args[2..];
+            }
+            "--json" => {
+                output = Output::Json;
+                args = &args[1..];
+            }
+            "--help" | "-h" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            _ => break,
+        }
+    }
+    let (command, args) = args.split_first().ok_or(USAGE)?;
+
+    let mut client = Client::connect(&addr).await?;
+    match command.as_str() {
+        "server" => server(&mut client, output, arg(args, "server address")?.parse()?).await,
+        "player" => player(&mut client, output, arg(args, "player uuid")?.parse()?).await,
+        "scan" => {
+            let (cidr, options) = args.split_first().ok_or("missing cidr")?;
+            let (limit, follow) = parse_options(options)?;
+            if follow {
+                return Err("scan does not take --follow".into());
+            }
+            scan(&mut client, output, cidr.parse()?, limit).await
+        }
+        "snapshot" => snapshot(&mut client, output).await,
+        "stats" => stats(&mut client, output).await,
+        "delete" => delete(&mut client, output, args).await,
+        "tail" => {
+            let (limit, follow) = parse_options(args)?;
+            tail(&mut client, output, limit, follow).await
+        }
+        _ => Err(format!("unknown command {command}\n\n{USAGE}").into()),
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(err) = run(&args).await {
+        eprintln!("mcdbctl: {err}");
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,102 @@
+use std::error::Error;
+use std::fmt::Display;
+use std::net::Ipv4Addr;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+/// An IPv4 network in CIDR notation, e.g. `10.0.0.0/8`. A bare address
+/// parses as a /32.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    network: u32,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Every IPv4 address.
+    pub const ALL: Cidr = Cidr {
+        network: 0,
+        prefix_len: 0,
+    };
+
+    pub fn new(addr: Ipv4Addr, prefix_len: u8) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        if prefix_len > 32 {
+            return Err(format!("invalid prefix length /{prefix_len}").into());
+        }
+        let mut res = Cidr {
+            network: 0,
+            prefix_len,
+        };
+        res.network = u32::from(addr) & res.mask();
+        Ok(res)
+    }
+
+    pub fn network(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.network)
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    pub fn mask(&self) -> u32 {
+        u32::MAX
+            .checked_shl(32 - self.prefix_len as u32)
+            .unwrap_or(0)
+    }
+
+    pub fn contains(&self, addr: Ipv4Addr) -> bool {
+        u32::from(addr) & self.mask() == self.network
+    }
+
+    /// The `ServerMap::server_array` keys (the first two octets) this
+    /// network overlaps.
+    pub fn shards(&self) -> RangeInclusive<u16> {
+        let first = (self.network >> 16) as u16;
+        let last = ((self.network | !self.mask()) >> 16) as u16;
+        first..=last
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = Box<dyn Error + Send + Sync>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('/') {
+            Some((addr, prefix_len)) => Cidr::new(addr.parse()?, prefix_len.parse()?),
+            None => Cidr::new(s.parse()?, 32),
+        }
+    }
+}
+
+impl Display for Cidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.network(), self.prefix_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_contains() {
+        let cidr: Cidr = "10.1.2.3/16".parse().unwrap();
+        assert_eq!(cidr.to_string(), "10.1.0.0/16");
+        assert!(cidr.contains(Ipv4Addr::new(10, 1, 255, 1)));
+        assert!(!cidr.contains(Ipv4Addr::new(10, 2, 0, 1)));
+        assert_eq!(cidr.shards(), 0x0a01..=0x0a01);
+
+        let cidr: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert_eq!(cidr.shards(), 0x0a00..=0x0aff);
+
+        assert!(Cidr::ALL.contains(Ipv4Addr::new(1, 2, 3, 4)));
+        assert_eq!(Cidr::ALL.shards(), 0..=u16::MAX);
+
+        let cidr: Cidr = "1.2.3.4".parse().unwrap();
+        assert!(cidr.contains(Ipv4Addr::new(1, 2, 3, 4)));
+        assert!(!cidr.contains(Ipv4Addr::new(1, 2, 3, 5)));
+
+        assert!("1.2.3.4/33".parse::<Cidr>().is_err());
+    }
+}
@@ -0,0 +1,282 @@
+use std::error::Error;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::EnvFilter;
+
+use crate::logging::LogFormat;
+use crate::server_map::ServerMap;
+
+/// Prefix of the environment variables overriding the config file, e.g.
+/// `MCDB_DATA_DIR` for `data_dir`.
+const ENV_PREFIX: &str = "MCDB_";
+
+/// Settings of the server and the offline commands. Every key can be set
+/// in a TOML file, overridden by an `MCDB_<KEY>` environment variable,
+/// which is in turn overridden by a `--<key>` flag, with `-` in place of
+/// `_`. Lists are comma-separated outside the file, and an empty value
+/// clears an optional key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Addresses of the binary protocol.
+    pub listen: Vec<SocketAddr>,
+    /// Address of the Prometheus endpoint, which is off when unset.
+    pub metrics_listen: Option<SocketAddr>,
+    /// Directory of the snapshot. `mcdb import`, `export` and `fsck` read
+    /// and rewrite it directly, so they must not be run against a
+    /// directory a running server is using.
+    pub data_dir: PathBuf,
+    /// Write a snapshot right after loading the previous one.
+    pub snapshot_on_start: bool,
+    /// Seconds between periodic snapshots, or 0 to turn them off.
+    pub snapshot_interval_secs: u64,
+    /// Seconds to wait on a SIGINT or SIGTERM for open connections to
+    /// finish their request, and then again for the final snapshot.
+    pub shutdown_timeout_secs: u64,
+    /// Threads writing the server files of a snapshot.
+    pub snapshot_workers: usize,
+    /// Threads of the async runtime, or 0 for one per core.
+    pub worker_threads: usize,
+    /// Most servers held, or 0 for no limit.
+    pub max_servers: usize,
+    /// Most player records held, or 0 for no limit.
+    pub max_players: usize,
+    /// See `ServerMap::pre_reserve`.
+    pub pre_reserve: bool,
+    /// An `EnvFilter` directive; `RUST_LOG` overrides it.
+    pub log_level: String,
+    pub log_format: LogFormat,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: vec![SocketAddr::from(([127, 0, 0, 1], 38282))],
+            metrics_listen: None,
+            data_dir: PathBuf::from("./data_bin"),
+            snapshot_on_start: true,
+            snapshot_interval_secs: 0,
+            shutdown_timeout_secs: 30,
+            snapshot_workers: 256,
+            worker_threads: 0,
+            max_servers: 0,
+            max_players: 0,
+            pre_reserve: false,
+            log_level: "info".to_string(),
+            log_format: LogFormat::Text,
+        }
+    }
+}
+
+/// The result of `Config::load`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Loaded {
+    pub config: Config,
+    /// `--print-config` was given.
+    pub print_config: bool,
+    /// The command and its arguments, following the global flags.
+    pub command: Vec<String>,
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Ok(toml::from_str(text)?)
+    }
+
+    /// The config as a TOML document, as read by `parse`.
+    pub fn to_toml(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        Ok(toml::to_string(self)?)
+    }
+
+    /// Resolves the config from the file named by `--config` or
+    /// `MCDB_CONFIG`, the environment and the flags leading `args`.
+    pub fn load(args: &[String]) -> Result<Loaded, Box<dyn Error + Send + Sync>> {
+        let mut path = std::env::var(format!("{ENV_PREFIX}CONFIG")).ok();
+        let mut print_config = false;
+        let mut flags = vec![];
+        let mut args = args.iter();
+        let mut command = vec![];
+        while let Some(arg) = args.next() {
+            let Some(key) = arg.strip_prefix("--") else {
+                command.push(arg.clone());
+                command.extend(args.cloned());
+                break;
+            };
+            if key == "print-config" {
+                print_config = true;
+                continue;
+            }
+            let value = args.next().ok_or(format!("{arg} needs a value"))?;
+            match key {
+                "config" => path = Some(value.clone()),
+                _ => flags.push((key.replace('-', "_"), value.clone())),
+            }
+        }
+
+        let mut config = match path.filter(|path| !path.is_empty()) {
+            Some(path) => {
+                let text =
+                    std::fs::read_to_string(&path).map_err(|err| format!("{path}: {err}"))?;
+                Self::parse(&text).map_err(|err| format!("{path}: {err}"))?
+            }
+            None => Self::default(),
+        };
+        config.apply_env(std::env::vars())?;
+        for (key, value) in flags {
+            config
+                .set(&key, &value)
+                .map_err(|err| format!("--{}: {err}", key.replace('_', "-")))?;
+        }
+        config.validate()?;
+        Ok(Loaded {
+            config,
+            print_config,
+            command,
+        })
+    }
+
+    /// Applies every `MCDB_<KEY>` variable of `vars` but `MCDB_CONFIG`.
+    pub fn apply_env(
+        &mut self,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        for (name, value) in vars {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            if key == "CONFIG" {
+                continue;
+            }
+            self.set(&key.to_ascii_lowercase(), &value)
+                .map_err(|err| format!("{name}: {err}"))?;
+        }
+        Ok(())
+    }
+
+    /// Sets one key from its textual form.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match key {
+            "listen" => {
+                self.listen = value
+                    .split(',')
+                    .filter(|addr| !addr.trim().is_empty())
+                    .map(|addr| addr.trim().parse())
+                    .collect::<Result<_, _>>()?
+            }
+            "metrics_listen" => {
+                self.metrics_listen = match value {
+                    "" => None,
+                    _ => Some(value.parse()?),
+                }
+            }
+            "data_dir" => self.data_dir = PathBuf::from(value),
+            "snapshot_on_start" => self.snapshot_on_start = value.parse()?,
+            "snapshot_interval_secs" => self.snapshot_interval_secs = value.parse()?,
+            "shutdown_timeout_secs" => self.shutdown_timeout_secs = value.parse()?,
+            "snapshot_workers" => self.snapshot_workers = value.parse()?,
+            "worker_threads" => self.worker_threads = value.parse()?,
+            "max_servers" => self.max_servers = value.parse()?,
+            "max_players" => self.max_players = value.parse()?,
+            "pre_reserve" => self.pre_reserve = value.parse()?,
+            "log_level" => self.log_level = value.to_string(),
+            "log_format" => self.log_format = value.parse()?,
+            _ => return Err(format!("unknown option {key}").into()),
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.listen.is_empty() {
+            return Err("listen needs at least one address".into());
+        }
+        let mut addrs: Vec<SocketAddr> = self
+            .listen
+            .iter()
+            .chain(&self.metrics_listen)
+            .copied()
+            .collect();
+        addrs.sort();
+        if let Some(pair) = addrs.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(format!("{} is listed twice", pair[0]).into());
+        }
+        if self.data_dir.as_os_str().is_empty() {
+            return Err("data_dir must not be empty".into());
+        }
+        if !(1..=4096).contains(&self.snapshot_workers) {
+            return Err("snapshot_workers must be between 1 and 4096".into());
+        }
+        if self.worker_threads > 4096 {
+            return Err("worker_threads must be at most 4096".into());
+        }
+        EnvFilter::try_new(&self.log_level)
+            .map_err(|err| format!("invalid log_level {:?}: {err}", self.log_level))?;
+        Ok(())
+    }
+
+    /// An empty map set up with the limits of the config.
+    pub fn server_map(&self) -> ServerMap {
+        let mut map = ServerMap::new();
+        map.pre_reserve = self.pre_reserve;
+        map.max_servers = self.max_servers;
+        map.max_players = self.max_players;
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_env_and_flags_override_in_order() {
+        let mut config = Config::parse(
+            "listen = [\"0.0.0.0:1\", \"0.0.0.0:2\"]\n\
+             data_dir = \"/var/lib/mcdb\"\n\
+             max_servers = 10\n\
+             log_format = \"json\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.listen.len(), 2);
+        assert_eq!(config.log_format, LogFormat::Json);
+        assert_eq!(config.snapshot_workers, 256);
+
+        config
+            .apply_env([
+                ("MCDB_MAX_SERVERS".to_string(), "20".to_string()),
+                ("MCDB_CONFIG".to_string(), "ignored.toml".to_string()),
+                ("HOME".to_string(), "/root".to_string()),
+            ])
+            .unwrap();
+        assert_eq!(config.max_servers, 20);
+        config.set("metrics_listen", "127.0.0.1:9100").unwrap();
+        config.set("listen", "127.0.0.1:1").unwrap();
+        config.validate().unwrap();
+
+        let reparsed = Config::parse(&config.to_toml().unwrap()).unwrap();
+        assert_eq!(reparsed, config);
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        assert!(Config::parse("lsiten = []").is_err());
+        assert!(Config::default().set("snapshot_workers", "many").is_err());
+        assert!(Config::default().set("colour", "1").is_err());
+        assert!(Config::default()
+            .apply_env([("MCDB_PRE_RESERVE".to_string(), "yes".to_string())])
+            .is_err());
+
+        let invalid = [
+            ("listen", ""),
+            ("snapshot_workers", "0"),
+            ("log_level", "mcdb=loud"),
+            ("metrics_listen", "127.0.0.1:38282"),
+        ];
+        for (key, value) in invalid {
+            let mut config = Config::default();
+            config.set(key, value).unwrap();
+            assert!(config.validate().is_err(), "{key} = {value:?}");
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::error::Error;
+use std::fmt::Display;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use integer_encoding::{VarIntReader, VarIntWriter};
+use uuid::Uuid;
+
+use crate::player_entry::Player;
+use crate::server_entry::Server;
+
+/// Position in a scan, just past the last row returned. Cursors hold
+/// keys rather than offsets or ids, so a scan resumes at the right place
+/// after a reconnect or a restart, and rows inserted behind the cursor
+/// are skipped while rows inserted ahead of it are returned.
+///
+/// Clients should treat the encoded form as opaque.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cursor {
+    /// Servers in address order, i.e. by /16, then /24 and address, then
+    /// port.
+    Server(SocketAddr),
+    /// Players in `ServerMap::player_array` order, or in
+    /// `ServerMap::name_index` order for a name search.
+    Player(String, Uuid),
+}
+
+const KIND_SERVER: u8 = 1;
+const KIND_PLAYER: u8 = 2;
+
+impl Cursor {
+    /*--- Cursor ---------------------------------------|
+    | field name    | type              | size          |
+    |---------------------------------------------------|
+    | kind          | u8                | 1 byte        |
+    | key           | depends on kind   | variable size |
+    |--------------------------------------------------*/
+    // kind 1: ServerPointer
+    // kind 2: varint name length, name, uuid
+    pub fn encode(&self) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        match self {
+            Cursor::Server(addr) => {
+                let mut res = vec![KIND_SERVER];
+                res.write_all(&Server::new(*addr).serialize_pointer()?)?;
+                Ok(res)
+            }
+            Cursor::Player(name, uuid) => {
+                let mut res = vec![KIND_PLAYER];
+                res.write_varint(name.len())?;
+                res.write_all(name.as_bytes())?;
+                res.write_all(uuid.as_bytes())?;
+                Ok(res)
+            }
+        }
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let (kind, mut buf) = buf.split_first().ok_or("empty cursor")?;
+        let cursor = match *kind {
+            KIND_SERVER => Cursor::Server(Server::deserialize_pointer(&mut buf)?),
+            KIND_PLAYER => {
+                let name_len: usize = buf.read_varint()?;
+                if name_len > buf.len() {
+                    return Err("truncated cursor".into());
+                }
+                let (name, mut rest) = buf.split_at(name_len);
+                let name = String::from_utf8(name.to_vec())?;
+                let uuid = Player::deserialize_pointer(&mut rest)?;
+                buf = rest;
+                Cursor::Player(name, uuid)
+            }
+            kind => return Err(format!("unknown cursor kind {kind}").into()),
+        };
+        if !buf.is_empty() {
+            return Err("trailing bytes in cursor".into());
+        }
+        Ok(cursor)
+    }
+}
+
+/// Hex of the encoded cursor, for command lines and logs.
+impl Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bytes = self.encode().map_err(|_| std::fmt::Error)?;
+        for byte in bytes {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = Box<dyn Error + Send + Sync>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.len().is_multiple_of(2) || !s.is_ascii() {
+            return Err("malformed cursor".into());
+        }
+        let bytes = (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| "malformed cursor")?;
+        Cursor::decode(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_round_trip() {
+        for cursor in [
+            Cursor::Server("10.1.2.3:25565".parse().unwrap()),
+            Cursor::Player("Notch".to_string(), Uuid::from_u128(7)),
+        ] {
+            assert_eq!(Cursor::decode(&cursor.encode().unwrap()).unwrap(), cursor);
+            assert_eq!(cursor.to_string().parse::<Cursor>().unwrap(), cursor);
+        }
+        assert!(Cursor::decode(&[]).is_err());
+        assert!(Cursor::decode(&[KIND_SERVER, 1, 2]).is_err());
+        assert!(Cursor::decode(&[KIND_PLAYER, 200]).is_err());
+        assert!("0g".parse::<Cursor>().is_err());
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::{BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::attributes::{AttrValue, Attribute};
+use crate::query::Query;
+use crate::server_map::ServerMap;
+use crate::servers_dat::{format_host_port, write_servers_dat, ServerListEntry};
+use crate::tables::{TableFilter, Tables};
+
+/// One line of a JSON Lines dump. Every server lists its players and
+/// every player lists its servers, so either half is enough to rebuild
+/// the links, and players that were never seen on a server survive.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum JsonRecord {
+    Server {
+        addr: SocketAddr,
+        #[serde(default)]
+        first_seen: u64,
+        #[serde(default)]
+        last_seen: u64,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        sources: Vec<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        hostnames: Vec<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        srv_targets: Vec<String>,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        motd: String,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        attributes: BTreeMap<Attribute, AttrValue>,
+        players: Vec<JsonPlayerRef>,
+    },
+    Player {
+        name: String,
+        uuid: Uuid,
+        servers: Vec<SocketAddr>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JsonPlayerRef {
+    pub name: String,
+    pub uuid: Uuid,
+}
+
+/// `mcdb export --format jsonl [--output <file>]`
+/// `mcdb export --format sqlite --output <file>`
+/// `mcdb export --format csv|parquet --output <dir> [--cidr <cidr>]...
+///     [--seen-after <unix time>] [--seen-before <unix time>]`
+/// `mcdb export --format servers-dat --output <file> [--cidr <cidr>]...
+///     [--seen-after <unix time>] [--seen-before <unix time>]`
+/// `mcdb export --format servers-dat --output <file> --query <query>`
+///
+/// Dumps the data directory to stdout or `--output` as JSON Lines, as a
+/// SQLite database, as normalized `servers`, `players` and
+/// `server_players` tables, or as a client `servers.dat` listing the
+/// matching servers or the result of a `Query`. Works on
+/// `Config::data_dir` directly, see there.
+pub async fn run(
+    config: &crate::config::Config,
+    args: &[String],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut format = None;
+    let mut output = None;
+    let mut filter = TableFilter::default();
+    let mut query = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => format = Some(args.next().ok_or("--format needs a value")?.clone()),
+            "--output" => output = Some(args.next().ok_or("--output needs a value")?.clone()),
+            "--cidr" => filter
+                .cidrs
+                .push(args.next().ok_or("--cidr needs a value")?.parse()?),
+            "--seen-after" => {
+                filter.seen_after = Some(args.next().ok_or("--seen-after needs a value")?.parse()?)
+            }
+            "--seen-before" => {
+                filter.seen_before =
+                    Some(args.next().ok_or("--seen-before needs a value")?.parse()?)
+            }
+            "--query" => query = Some(args.next().ok_or("--query needs a value")?.parse()?),
+            _ => return Err(format!("unexpected argument {arg}").into()),
+        }
+    }
+    if query.is_some() && format.as_deref() != Some("servers-dat") {
+        return Err("--query is only supported by the servers-dat format".into());
+    }
+    if query.is_some() && !filter.is_empty() {
+        return Err("--query can't be combined with filters, write them into the query".into());
+    }
+
+    let map = crate::snapshot::deserialize_all(config).await?;
+
+    match format.as_deref() {
+        Some("csv") => {
+            let output = output.ok_or("--format csv needs an --output directory")?;
+            return Tables::collect(&map, &filter).write_csv(Path::new(&output));
+        }
+        Some("servers-dat") => {
+            let output = output.ok_or("--format servers-dat needs an --output file")?;
+            let entries = servers_dat_entries(&map, &filter, query.as_ref())?;
+            let mut writer = BufWriter::new(std::fs::File::create(output)?);
+            write_servers_dat(&mut writer, &entries)?;
+            writer.flush()?;
+            return Ok(());
+        }
+        #[cfg(feature = "parquet")]
+        Some("parquet") => {
+            let output = output.ok_or("--format parquet needs an --output directory")?;
+            return Tables::collect(&map, &filter).write_parquet(Path::new(&output));
+        }
+        #[cfg(not(feature = "parquet"))]
+        Some("parquet") => return Err("mcdb was built without the parquet feature".into()),
+        #[cfg(feature = "sqlite")]
+        Some("sqlite") if filter.is_empty() => {
+            let output = output.ok_or("--format sqlite needs an --output file")?;
+            return crate::sqlite::write_sqlite(&map, Path::new(&output));
+        }
+        #[cfg(not(feature = "sqlite"))]
+        Some("sqlite") => return Err("mcdb was built without the sqlite feature".into()),
+        _ if !filter.is_empty() => {
+            return Err(
+                "filters are only supported by the csv, parquet and servers-dat formats".into(),
+            )
+        }
+        _ => {}
+    }
+
+    let writer: Box<dyn Write> = match output {
+        Some(path) => Box::new(std::fs::File::create(path)?),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let mut writer = BufWriter::new(writer);
+
+    match format.as_deref() {
+        Some("jsonl") => write_jsonl(&map, &mut writer)?,
+        Some(format) => return Err(format!("unknown format {format}").into()),
+        None => return Err("missing --format".into()),
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// The servers matching `query`, or `filter` when there is no query, as
+/// `servers.dat` entries named after their address.
+pub fn servers_dat_entries(
+    map: &ServerMap,
+    filter: &TableFilter,
+    query: Option<&Query>,
+) -> Result<Vec<ServerListEntry>, Box<dyn Error + Send + Sync>> {
+    let addrs: Vec<SocketAddr> = match query {
+        Some(query) => query
+            .execute(map, None, usize::MAX)?
+            .servers
+            .iter()
+            .map(|server_id| map.servers[*server_id as usize].addr())
+            .collect(),
+        None => Tables::collect(map, filter)
+            .servers
+            .iter()
+            .map(|row| Ok(SocketAddr::new(row.ip.parse()?, row.port)))
+            .collect::<Result<_, Box<dyn Error + Send + Sync>>>()?,
+    };
+    Ok(addrs
+        .into_iter()
+        .map(|addr| ServerListEntry {
+            name: addr.to_string(),
+            ip: format_host_port(addr),
+        })
+        .collect())
+}
+
+/// Writes every player, then every server, as one JSON object per line.
+pub fn write_jsonl(
+    map: &ServerMap,
+    writer: &mut impl Write,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    for player in &map.players {
+        let (name, server_ids) = {
+            let player = player.lock();
+            (player.name.clone(), player.servers.clone())
+        };
+        let record = JsonRecord::Player {
+            name,
+            uuid: player.uuid(),
+            servers: server_ids
+                .iter()
+                .map(|id| map.servers[*id as usize].addr())
+                .collect(),
+        };
+        serde_json::to_writer(&mut *writer, &record)?;
+        writer.write_all(b"\n")?;
+    }
+
+    for server in &map.servers {
+        let record = server.lock().clone();
+        let mut players = Vec::with_capacity(record.players.len());
+        for id in record.players {
+            let player = &map.players[id as usize];
+            players.push(JsonPlayerRef {
+                name: player.lock().name.clone(),
+                uuid: player.uuid(),
+            });
+        }
+        let record = JsonRecord::Server {
+            addr: record.addr,
+            first_seen: record.first_seen,
+            last_seen: record.last_seen,
+            sources: record.sources,
+            hostnames: record.hostnames,
+            srv_targets: record.srv_targets,
+            motd: record.motd,
+            attributes: record.attributes,
+            players,
+        };
+        serde_json::to_writer(&mut *writer, &record)?;
+        writer.write_all(b"\n")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::read_jsonl;
+    use crate::player_entry::Player;
+
+    fn dump(map: &ServerMap) -> Vec<JsonRecord> {
+        let mut buf = vec![];
+        write_jsonl(map, &mut buf).unwrap();
+        let mut records: Vec<JsonRecord> = buf
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        for record in &mut records {
+            match record {
+                JsonRecord::Server { players, .. } => players.sort_by_key(|player| player.uuid),
+                JsonRecord::Player { servers, .. } => servers.sort(),
+            }
+        }
+        records.sort_by_key(|record| serde_json::to_string(record).unwrap());
+        records
+    }
+
+    #[test]
+    fn jsonl_round_trip() {
+        let mut map = ServerMap::new();
+        let alice = Player::new("alice".to_string(), Uuid::from_u128(1));
+        let bob = Player::new("bob".to_string(), Uuid::from_u128(2));
+        map.insert("10.0.0.1:25565".parse().unwrap(), [alice.clone(), bob])
+            .unwrap();
+        map.insert("10.0.1.1:25566".parse().unwrap(), [alice])
+            .unwrap();
+        map.insert("10.0.2.1:25565".parse().unwrap(), []).unwrap();
+        map.insert_player(Player::new("carol".to_string(), Uuid::from_u128(3)))
+            .unwrap();
+
+        let mut buf = vec![];
+        write_jsonl(&map, &mut buf).unwrap();
+        let mut imported = ServerMap::new();
+        read_jsonl(&mut imported, &mut &buf[..]).unwrap();
+
+        assert_eq!(imported.servers.len(), 3);
+        assert_eq!(imported.players.len(), 3);
+        assert_eq!(dump(&imported), dump(&map));
+
+        let query: Query = "player alice and port = 25566".parse().unwrap();
+        let entries = servers_dat_entries(&map, &TableFilter::default(), Some(&query)).unwrap();
+        assert_eq!(
+            entries,
+            [ServerListEntry {
+                name: "10.0.1.1:25566".to_string(),
+                ip: "10.0.1.1:25566".to_string(),
+            }]
+        );
+    }
+}
@@ -0,0 +1,592 @@
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fmt::Display;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use integer_encoding::VarInt;
+use parking_lot::Mutex;
+use uuid::Uuid;
+
+use crate::attributes::{AttrKind, Attribute};
+use crate::config::Config;
+use crate::player_entry::{Player, PlayerId};
+use crate::server_entry::{Server, ServerId};
+use crate::server_map::{unix_now, ServerMap};
+
+/// A problem found by `check`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    /// Path of the file, relative to the data directory.
+    pub path: PathBuf,
+    /// Byte offset of the broken record or field.
+    pub offset: usize,
+    pub message: String,
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}",
+            self.path.display(),
+            self.offset,
+            self.message
+        )
+    }
+}
+
+/// Bytes left out of the repaired directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quarantined {
+    pub path: PathBuf,
+    pub offset: usize,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub issues: Vec<Issue>,
+    pub quarantined: Vec<Quarantined>,
+    /// Every record that passed, with dangling and one-sided links
+    /// dropped. Links are taken from each player record, as the loader
+    /// does. This is what `repair` writes.
+    pub map: ServerMap,
+}
+
+impl Report {
+    fn issue(&mut self, path: &Path, offset: usize, message: impl Into<String>) {
+        self.issues.push(Issue {
+            path: path.to_path_buf(),
+            offset,
+            message: message.into(),
+        });
+    }
+
+    fn quarantine(&mut self, path: &Path, offset: usize, bytes: &[u8]) {
+        self.quarantined.push(Quarantined {
+            path: path.to_path_buf(),
+            offset,
+            bytes: bytes.to_vec(),
+        });
+    }
+}
+
+/// Walks the framing of a snapshot file without interpreting it, so a
+/// record whose contents are broken can be skipped on its own.
+struct Frame<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl Frame<'_> {
+    fn varint(&mut self) -> Result<u64, String> {
+        let (value, len) = u64::decode_var(&self.buf[self.pos..])
+            .ok_or_else(|| "truncated or overlong varint".to_string())?;
+        self.pos += len;
+        Ok(value)
+    }
+
+    /// Reads a count of items at least `item_len` bytes each, checking
+    /// they fit in the rest of the file.
+    fn count(&mut self, item_len: usize) -> Result<usize, String> {
+        let count = self.varint()?;
+        let remaining = (self.buf.len() - self.pos) as u64;
+        if count.saturating_mul(item_len.max(1) as u64) > remaining {
+            return Err(format!("count {count} runs past the end of the file"));
+        }
+        Ok(count as usize)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), String> {
+        if len > self.buf.len() - self.pos {
+            return Err(format!("{len} bytes run past the end of the file"));
+        }
+        self.pos += len;
+        Ok(())
+    }
+
+    fn string(&mut self) -> Result<(), String> {
+        let len = self.count(1)?;
+        self.skip(len)
+    }
+
+    /// Skips a `Player` record.
+    fn player(&mut self) -> Result<(), String> {
+        self.string()?;
+        self.skip(16)?;
+        let num_servers = self.count(6)?;
+        self.skip(num_servers * 6)
+    }
+
+    /// Skips a `Server` record.
+    fn server(&mut self) -> Result<(), String> {
+        self.skip(6)?;
+        self.varint()?;
+        self.varint()?;
+        for _ in 0..3 {
+            let len = self.count(1)?;
+            for _ in 0..len {
+                self.string()?;
+            }
+        }
+        self.string()?;
+        let num_attributes = self.count(2)?;
+        for _ in 0..num_attributes {
+            let id = *self
+                .buf
+                .get(self.pos)
+                .ok_or_else(|| "truncated attribute".to_string())?;
+            self.pos += 1;
+            let attribute =
+                Attribute::from_id(id).ok_or_else(|| format!("unknown attribute id {id}"))?;
+            match attribute.kind() {
+                AttrKind::Bool => self.skip(1)?,
+                AttrKind::Int => {
+                    self.varint()?;
+                }
+                AttrKind::Str => self.string()?,
+            }
+        }
+        let num_players = self.count(16)?;
+        self.skip(num_players * 16)
+    }
+}
+
+/// Reads the records of a snapshot file, calling `on_record` with the
+/// offset and bytes of each. Returns once the framing breaks, after
+/// quarantining the rest of the file.
+fn walk<'a>(
+    report: &mut Report,
+    path: &Path,
+    buf: &'a [u8],
+    skip: fn(&mut Frame<'a>) -> Result<(), String>,
+    mut on_record: impl FnMut(&mut Report, usize, &[u8]),
+) {
+    let mut frame = Frame { buf, pos: 0 };
+    let count = match frame.count(1) {
+        Ok(count) => count,
+        Err(err) => {
+            report.issue(path, 0, format!("bad record count: {err}"));
+            report.quarantine(path, 0, buf);
+            return;
+        }
+    };
+    for _ in 0..count {
+        let start = frame.pos;
+        if let Err(err) = skip(&mut frame) {
+            report.issue(path, start, format!("bad framing: {err}"));
+            report.quarantine(path, start, &buf[start..]);
+            return;
+        }
+        on_record(report, start, &buf[start..frame.pos]);
+    }
+    if frame.pos < buf.len() {
+        report.issue(
+            path,
+            frame.pos,
+            format!("{} trailing bytes", buf.len() - frame.pos),
+        );
+        report.quarantine(path, frame.pos, &buf[frame.pos..]);
+    }
+}
+
+fn valid_addr(addr: SocketAddr) -> bool {
+    !addr.ip().is_unspecified() && addr.port() != 0
+}
+
+/// Checks the snapshot in `data_dir` and rebuilds a map from the records
+/// that pass. Reads `servers/` in a fixed order, so reports are stable.
+pub fn check(data_dir: &Path) -> Result<Report, Box<dyn Error + Send + Sync>> {
+    let mut report = Report::default();
+    let players_path = Path::new("players.bin");
+    let players_buf = match std::fs::read(data_dir.join(players_path)) {
+        Ok(buf) => buf,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            if data_dir.join("servers").exists() {
+                report.issue(players_path, 0, "missing");
+            }
+            return check_servers(data_dir, report, HashSet::new(), vec![]);
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut uuids: HashSet<Uuid> = HashSet::new();
+    let mut player_servers: Vec<(PlayerId, usize, Vec<SocketAddr>)> = vec![];
+    walk(
+        &mut report,
+        players_path,
+        &players_buf,
+        Frame::player,
+        |report, offset, record| {
+            let (player, servers) = match Player::deserialize(&mut &record[..]) {
+                Ok(player) => player,
+                Err(err) => {
+                    report.issue(players_path, offset, format!("bad player: {err}"));
+                    report.quarantine(players_path, offset, record);
+                    return;
+                }
+            };
+            if player.uuid.is_nil() {
+                report.issue(players_path, offset, "player has the nil uuid");
+                report.quarantine(players_path, offset, record);
+                return;
+            }
+            let key = (player.name.clone(), player.uuid);
+            if report.map.player_array.contains_key(&key) {
+                report.issue(
+                    players_path,
+                    offset,
+                    format!("duplicate player {} {}", player.name, player.uuid),
+                );
+                report.quarantine(players_path, offset, record);
+                return;
+            }
+            let uuid = player.uuid;
+            match report.map.insert_player(player) {
+                Ok(player_id) => {
+                    uuids.insert(uuid);
+                    player_servers.push((player_id, offset, servers));
+                }
+                Err(err) => {
+                    report.issue(players_path, offset, format!("bad player: {err}"));
+                    report.quarantine(players_path, offset, record);
+                }
+            }
+        },
+    );
+    check_servers(data_dir, report, uuids, player_servers)
+}
+
+fn check_servers(
+    data_dir: &Path,
+    mut report: Report,
+    uuids: HashSet<Uuid>,
+    player_servers: Vec<(PlayerId, usize, Vec<SocketAddr>)>,
+) -> Result<Report, Box<dyn Error + Send + Sync>> {
+    let servers_dir = data_dir.join("servers");
+    let mut files = vec![];
+    if servers_dir.exists() {
+        let mut segments_a: Vec<_> = std::fs::read_dir(&servers_dir)?.collect::<Result<_, _>>()?;
+        segments_a.sort_by_key(|entry| entry.file_name());
+        for segment_a in segments_a {
+            let name_a = segment_a.file_name().to_string_lossy().into_owned();
+            let path_a = Path::new("servers").join(&name_a);
+            let a = match name_a.parse::<u8>() {
+                Ok(a) if segment_a.file_type()?.is_dir() => a,
+                _ => {
+                    report.issue(&path_a, 0, "unexpected entry");
+                    quarantine_entry(&mut report, &segment_a.path(), &path_a)?;
+                    continue;
+                }
+            };
+            let mut segments_b: Vec<_> =
+                std::fs::read_dir(segment_a.path())?.collect::<Result<_, _>>()?;
+            segments_b.sort_by_key(|entry| entry.file_name());
+            for segment_b in segments_b {
+                let name_b = segment_b.file_name().to_string_lossy().into_owned();
+                let path_b = path_a.join(&name_b);
+                let b = name_b
+                    .strip_suffix(".bin")
+                    .and_then(|b| b.parse::<u8>().ok());
+                match b {
+                    Some(b) if segment_b.file_type()?.is_file() => {
+                        files.push(([a, b], path_b, std::fs::read(segment_b.path())?))
+                    }
+                    _ => {
+                        report.issue(&path_b, 0, "unexpected entry");
+                        quarantine_entry(&mut report, &segment_b.path(), &path_b)?;
+                    }
+                }
+            }
+        }
+    }
+
+    // server to where it was read and the players it lists
+    let mut server_uuids: BTreeMap<ServerId, (PathBuf, usize, Vec<Uuid>)> = BTreeMap::new();
+    for (segment, path, buf) in files {
+        walk(
+            &mut report,
+            &path,
+            &buf,
+            Frame::server,
+            |report, offset, record| {
+                let (server, uuids) = match Server::deserialize(&mut &record[..]) {
+                    Ok(server) => server,
+                    Err(err) => {
+                        report.issue(&path, offset, format!("bad server: {err}"));
+                        report.quarantine(&path, offset, record);
+                        return;
+                    }
+                };
+                let addr = server.addr;
+                if !valid_addr(addr) {
+                    report.issue(&path, offset, format!("invalid address {addr}"));
+                    report.quarantine(&path, offset, record);
+                    return;
+                }
+                if matches!(report.map.find_id(addr), Ok(Some(_))) {
+                    report.issue(&path, offset, format!("duplicate server {addr}"));
+                    report.quarantine(&path, offset, record);
+                    return;
+                }
+                let server_id = match report.map.merge_server(&server) {
+                    Ok(server_id) => server_id,
+                    Err(err) => {
+                        report.issue(&path, offset, format!("bad server: {err}"));
+                        report.quarantine(&path, offset, record);
+                        return;
+                    }
+                };
+                if let IpAddr::V4(ip) = addr.ip() {
+                    let octets = ip.octets();
+                    if octets[..2] != segment {
+                        report.issue(
+                            &path,
+                            offset,
+                            format!(
+                                "server {addr} belongs in servers/{}/{}.bin",
+                                octets[0], octets[1]
+                            ),
+                        );
+                    }
+                }
+                server_uuids.insert(server_id, (path.clone(), offset, uuids));
+            },
+        );
+    }
+
+    let players_path = Path::new("players.bin");
+    for (player_id, offset, servers) in player_servers {
+        let uuid = report.map.players[player_id as usize].uuid();
+        for addr in servers {
+            let server = report.map.find_id(addr)?.and_then(|server_id| {
+                let (_, _, listed) = server_uuids.get_mut(&server_id)?;
+                Some((server_id, listed))
+            });
+            let Some((server_id, listed)) = server else {
+                report.issue(
+                    players_path,
+                    offset,
+                    format!("player {uuid} points to unknown server {addr}"),
+                );
+                continue;
+            };
+            match listed.iter().position(|listed| *listed == uuid) {
+                Some(index) => {
+                    listed.swap_remove(index);
+                    report.map.link(server_id, player_id);
+                }
+                None => report.issue(
+                    players_path,
+                    offset,
+                    format!("player {uuid} points to server {addr}, which does not point back"),
+                ),
+            }
+        }
+    }
+    // whatever a server lists that no player record claimed
+    for (server_id, (path, offset, listed)) in server_uuids {
+        let addr = report.map.servers[server_id as usize].addr();
+        for uuid in listed {
+            let message = if uuids.contains(&uuid) {
+                format!("server {addr} points to player {uuid}, which does not point back")
+            } else {
+                format!("server {addr} points to unknown player {uuid}")
+            };
+            report.issue(&path, offset, message);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Quarantines a whole file, or every file under a directory.
+fn quarantine_entry(
+    report: &mut Report,
+    path: &Path,
+    relative: &Path,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if path.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            let entry = entry?;
+            quarantine_entry(report, &entry.path(), &relative.join(entry.file_name()))?;
+        }
+    } else {
+        report.quarantine(relative, 0, &std::fs::read(path)?);
+    }
+    Ok(())
+}
+
+/// Replaces the snapshot in `config.data_dir` with the map of `report`,
+/// and writes every quarantined piece to `quarantine/<unix time>/` as
+/// `<path>.<offset>`. The repaired snapshot is swapped in the same way
+/// as any other, so a crash leaves either the old or the new one.
+pub async fn repair(
+    config: &Config,
+    report: Report,
+) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+    let quarantine_dir = config
+        .data_dir
+        .join("quarantine")
+        .join(unix_now().to_string());
+    for piece in &report.quarantined {
+        let mut name = piece.path.clone().into_os_string();
+        name.push(format!(".{}", piece.offset));
+        let path = quarantine_dir.join(name);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, &piece.bytes)?;
+    }
+    crate::snapshot::serialize_all(Arc::new(Mutex::new(report.map)), config).await?;
+    Ok(quarantine_dir)
+}
+
+/// `mcdb fsck [--repair]`
+///
+/// Checks the framing and contents of every record in the data
+/// directory, and that the links between servers and players resolve in
+/// both directions. With `--repair`, rewrites the directory from the
+/// records that passed and quarantines the rest. Works on
+/// `Config::data_dir` directly, see there.
+pub async fn run(config: &Config, args: &[String]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut fix = false;
+    for arg in args {
+        match arg.as_str() {
+            "--repair" => fix = true,
+            _ => return Err(format!("unexpected argument {arg}").into()),
+        }
+    }
+
+    if crate::snapshot::finish_snapshot(&config.data_dir)? {
+        println!("fsck: finished an interrupted snapshot");
+    }
+    let report = check(&config.data_dir)?;
+    for issue in &report.issues {
+        println!("{issue}");
+    }
+    println!(
+        "fsck: {} players, {} servers, {} issues, {} pieces to quarantine",
+        report.map.players.len(),
+        report.map.servers.len(),
+        report.issues.len(),
+        report.quarantined.len()
+    );
+    if report.issues.is_empty() {
+        return Ok(());
+    }
+    if !fix {
+        return Err("data directory has issues, run with --repair to fix them".into());
+    }
+    let quarantined = !report.quarantined.is_empty();
+    let quarantine_dir = repair(config, report).await?;
+    if quarantined {
+        println!(
+            "fsck: repaired, quarantined to {}",
+            quarantine_dir.display()
+        );
+    } else {
+        println!("fsck: repaired");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use integer_encoding::VarIntWriter;
+
+    use super::*;
+
+    fn write_file(path: &Path, records: &[Vec<u8>], trailing: &[u8]) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut buf = vec![];
+        buf.write_varint(records.len()).unwrap();
+        for record in records {
+            buf.extend_from_slice(record);
+        }
+        buf.extend_from_slice(trailing);
+        std::fs::write(path, buf).unwrap();
+    }
+
+    #[tokio::test]
+    async fn finds_and_repairs_broken_records() {
+        let data_dir = std::env::temp_dir().join(format!("mcdb-fsck-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&data_dir);
+        let mut map = ServerMap::new();
+        let alice = Player::new("alice".to_string(), Uuid::from_u128(1));
+        let bob = Player::new("bob".to_string(), Uuid::from_u128(2));
+        map.insert("10.0.0.1:25565".parse().unwrap(), [alice.clone(), bob])
+            .unwrap();
+        map.insert("10.0.0.2:25565".parse().unwrap(), [alice])
+            .unwrap();
+        map.insert("10.1.0.1:25565".parse().unwrap(), []).unwrap();
+        // alice renamed, seen on another server under the same uuid
+        let renamed = Player::new("alice2".to_string(), Uuid::from_u128(1));
+        map.insert("10.0.0.3:25565".parse().unwrap(), [renamed])
+            .unwrap();
+        let server = |id: usize| map.servers[id].lock().serialize(&map.players).unwrap();
+        let player = |id: usize| map.players[id].lock().serialize(&map.servers).unwrap();
+
+        let mut bad_name = player(1);
+        bad_name[1] = 0xff;
+        write_file(
+            &data_dir.join("players.bin"),
+            &[player(0), bad_name, player(2)],
+            &[],
+        );
+        write_file(
+            &data_dir.join("servers/10/0.bin"),
+            // server 2 is in the wrong file, and the last record is cut short
+            &[server(0), server(1), server(3), server(2)],
+            &server(0)[..3],
+        );
+        let mut truncated = server(2);
+        truncated.truncate(4);
+        write_file(&data_dir.join("servers/10/1.bin"), &[truncated], &[]);
+        std::fs::write(data_dir.join("servers/10/notes.txt"), b"hi").unwrap();
+
+        let report = check(&data_dir).unwrap();
+        let messages: Vec<String> = report
+            .issues
+            .iter()
+            .map(|issue| format!("{}:{}", issue.path.display(), issue.message))
+            .collect();
+        let expected = [
+            "players.bin:bad player: invalid utf-8 sequence of 1 bytes from index 0",
+            "servers/10/notes.txt:unexpected entry",
+            "servers/10/0.bin:server 10.1.0.1:25565 belongs in servers/10/1.bin",
+            "servers/10/0.bin:3 trailing bytes",
+            "servers/10/1.bin:bad framing: 6 bytes run past the end of the file",
+            "servers/10/0.bin:server 10.0.0.1:25565 points to unknown player 00000000-0000-0000-0000-000000000002",
+        ];
+        assert_eq!(messages, expected);
+        assert_eq!(report.quarantined.len(), 4);
+        assert_eq!((report.map.servers.len(), report.map.players.len()), (4, 2));
+
+        let config = Config {
+            data_dir: data_dir.clone(),
+            ..Config::default()
+        };
+        let quarantine_dir = repair(&config, report).await.unwrap();
+        assert!(quarantine_dir.join("servers/10/notes.txt.0").exists());
+        assert!(check(&data_dir).unwrap().issues.is_empty());
+        let map = crate::snapshot::deserialize_all(&config).await.unwrap();
+        assert_eq!((map.servers.len(), map.players.len()), (4, 2));
+        let servers_of = |name: &str| -> Vec<String> {
+            let player_id = map.player_array[&(name.to_string(), Uuid::from_u128(1))];
+            let player = map.players[player_id as usize].lock();
+            let mut addrs: Vec<String> = player
+                .servers
+                .iter()
+                .map(|id| map.servers[*id as usize].addr().to_string())
+                .collect();
+            addrs.sort_unstable();
+            addrs
+        };
+        assert_eq!(servers_of("alice"), ["10.0.0.1:25565", "10.0.0.2:25565"]);
+        assert_eq!(servers_of("alice2"), ["10.0.0.3:25565"]);
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+}
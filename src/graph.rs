@@ -0,0 +1,476 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+
+use uuid::Uuid;
+
+use crate::player_entry::PlayerId;
+use crate::server_entry::{Server, ServerId};
+use crate::server_map::ServerMap;
+
+/// Inclusive range of unix times.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeWindow {
+    pub from: u64,
+    pub to: u64,
+}
+
+impl TimeWindow {
+    /// Fraction of the window during which `server` was being sighted,
+    /// from its first to its last sighting. Sightings are not timestamped
+    /// per player, so this is the best estimate of when two players of
+    /// the server overlapped.
+    pub fn overlap(&self, server: &Server) -> f64 {
+        if server.last_seen == 0 {
+            return 0.0;
+        }
+        let from = self.from.max(server.first_seen);
+        let to = self.to.min(server.last_seen);
+        if from > to {
+            return 0.0;
+        }
+        (to - from + 1) as f64 / (self.to - self.from + 1) as f64
+    }
+}
+
+/// A player or server related to the one searched for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Related<T> {
+    pub id: T,
+    /// Number of servers (for players) or players (for servers) the two
+    /// have in common.
+    pub shared: usize,
+    /// `shared` weighted by the time window, or equal to it without one.
+    pub score: f64,
+}
+
+/// Players seen on the same servers as the player with `uuid`, most
+/// related first. Records of one player under different names are
+/// merged. Each shared server counts once, weighted by how much of
+/// `window` it was active for; servers outside the window are ignored.
+pub fn related_players(
+    map: &ServerMap,
+    uuid: Uuid,
+    window: Option<TimeWindow>,
+    limit: usize,
+) -> Vec<Related<Uuid>> {
+    let mut server_ids: Vec<ServerId> = map
+        .find_player(uuid)
+        .iter()
+        .flat_map(|player_id| map.players[*player_id as usize].lock().servers.clone())
+        .collect();
+    server_ids.sort_unstable();
+    server_ids.dedup();
+
+    let mut res = HashMap::new();
+    for server_id in server_ids {
+        let server = map.servers[server_id as usize].lock();
+        let weight = window.map_or(1.0, |window| window.overlap(&server));
+        if weight == 0.0 {
+            continue;
+        }
+        let mut others: Vec<Uuid> = server
+            .players
+            .iter()
+            .map(|player_id| map.players[*player_id as usize].uuid())
+            .filter(|other| *other != uuid)
+            .collect();
+        others.sort_unstable();
+        others.dedup();
+        for other in others {
+            add(&mut res, other, weight);
+        }
+    }
+    rank(res, limit)
+}
+
+/// Servers sharing players with `server_id`, most related first. Records
+/// of one player under different names are merged. Each shared player
+/// counts once, weighted by how much of `window` the other server was
+/// active for.
+pub fn related_servers(
+    map: &ServerMap,
+    server_id: ServerId,
+    window: Option<TimeWindow>,
+    limit: usize,
+) -> Vec<Related<ServerId>> {
+    let mut uuids: Vec<Uuid> = map.servers[server_id as usize]
+        .lock()
+        .players
+        .iter()
+        .map(|player_id| map.players[*player_id as usize].uuid())
+        .collect();
+    uuids.sort_unstable();
+    uuids.dedup();
+    let mut shared: HashMap<ServerId, usize> = HashMap::new();
+    for uuid in uuids {
+        let mut others: Vec<ServerId> = map
+            .find_player(uuid)
+            .iter()
+            .flat_map(|player_id| map.players[*player_id as usize].lock().servers.clone())
+            .filter(|other| *other != server_id)
+            .collect();
+        others.sort_unstable();
+        others.dedup();
+        for other in others {
+            *shared.entry(other).or_default() += 1;
+        }
+    }
+    let mut res = HashMap::new();
+    for (other, shared) in shared {
+        let weight = window.map_or(1.0, |window| {
+            window.overlap(&map.servers[other as usize].lock())
+        });
+        if weight > 0.0 {
+            res.insert(
+                other,
+                Related {
+                    id: other,
+                    shared,
+                    score: shared as f64 * weight,
+                },
+            );
+        }
+    }
+    rank(res, limit)
+}
+
+/// Deepest traversal accepted, in edges.
+pub const MAX_DEPTH: u8 = 32;
+
+/// A vertex of the bipartite graph linking servers to the players seen
+/// on them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Node {
+    Player(PlayerId),
+    Server(ServerId),
+}
+
+impl Node {
+    fn neighbours(self, map: &ServerMap) -> Vec<Node> {
+        match self {
+            Node::Player(player_id) => map.players[player_id as usize]
+                .lock()
+                .servers
+                .iter()
+                .map(|server_id| Node::Server(*server_id))
+                .collect(),
+            Node::Server(server_id) => map.servers[server_id as usize]
+                .lock()
+                .players
+                .iter()
+                .map(|player_id| Node::Player(*player_id))
+                .collect(),
+        }
+    }
+}
+
+/// Breadth-first expansion from `start`, returning every node within
+/// `max_depth` edges with its depth, nearest first. Stops after `limit`
+/// nodes, start nodes included.
+pub fn expand(map: &ServerMap, start: &[Node], max_depth: u8, limit: usize) -> Vec<(Node, u8)> {
+    let mut visited: HashSet<Node> = HashSet::new();
+    let mut queue: VecDeque<(Node, u8)> = VecDeque::new();
+    let mut res = vec![];
+    for node in start {
+        if visited.insert(*node) {
+            queue.push_back((*node, 0));
+        }
+    }
+    while let Some((node, depth)) = queue.pop_front() {
+        if res.len() == limit {
+            break;
+        }
+        res.push((node, depth));
+        if depth == max_depth {
+            continue;
+        }
+        for next in node.neighbours(map) {
+            if visited.insert(next) {
+                queue.push_back((next, depth + 1));
+            }
+        }
+    }
+    res
+}
+
+/// Shortest chain of players and servers from any of `from` to any of
+/// `to`, both ends included, or `None` if there is none within
+/// `max_depth` edges. Players alternate with the servers they share.
+pub fn shortest_path(
+    map: &ServerMap,
+    from: &[PlayerId],
+    to: &[PlayerId],
+    max_depth: u8,
+) -> Option<Vec<Node>> {
+    let to: HashSet<Node> = to
+        .iter()
+        .map(|player_id| Node::Player(*player_id))
+        .collect();
+    let mut parents: HashMap<Node, Option<Node>> = HashMap::new();
+    let mut queue: VecDeque<(Node, u8)> = VecDeque::new();
+    for player_id in from {
+        let node = Node::Player(*player_id);
+        if parents.insert(node, None).is_none() {
+            queue.push_back((node, 0));
+        }
+    }
+    while let Some((node, depth)) = queue.pop_front() {
+        if to.contains(&node) {
+            let mut path = vec![node];
+            while let Some(Some(parent)) = parents.get(path.last().unwrap()) {
+                path.push(*parent);
+            }
+            path.reverse();
+            return Some(path);
+        }
+        if depth == max_depth {
+            continue;
+        }
+        for next in node.neighbours(map) {
+            if let Entry::Vacant(entry) = parents.entry(next) {
+                entry.insert(Some(node));
+                queue.push_back((next, depth + 1));
+            }
+        }
+    }
+    None
+}
+
+/// A connected component of the graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Component {
+    /// The lowest node of the component, players before servers.
+    pub root: Node,
+    pub players: usize,
+    pub servers: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Components {
+    /// Number of components, including lone servers with no players.
+    pub count: usize,
+    /// The largest components by number of nodes, largest first.
+    pub largest: Vec<Component>,
+}
+
+/// Summarizes the connected components of the whole graph with a
+/// union-find over every link, keeping the `limit` largest.
+pub fn components(map: &ServerMap, limit: usize) -> Components {
+    let num_players = map.players.len();
+    // players first, then servers, so roots are the lowest node
+    let mut sets = DisjointSet::new(num_players + map.servers.len());
+    for (server_id, server) in map.servers.iter().enumerate() {
+        for player_id in &server.lock().players {
+            sets.union(num_players + server_id, *player_id as usize);
+        }
+    }
+
+    let mut sizes: HashMap<usize, (usize, usize)> = HashMap::new();
+    for i in 0..num_players + map.servers.len() {
+        let size = sizes.entry(sets.find(i)).or_default();
+        if i < num_players {
+            size.0 += 1;
+        } else {
+            size.1 += 1;
+        }
+    }
+    let mut largest: Vec<Component> = sizes
+        .iter()
+        .map(|(root, (players, servers))| Component {
+            root: match root.checked_sub(num_players) {
+                Some(server_id) => Node::Server(server_id as ServerId),
+                None => Node::Player(*root as PlayerId),
+            },
+            players: *players,
+            servers: *servers,
+        })
+        .collect();
+    largest.sort_by(|a, b| {
+        (b.players + b.servers)
+            .cmp(&(a.players + a.servers))
+            .then(a.root.cmp(&b.root))
+    });
+    largest.truncate(limit);
+    Components {
+        count: sizes.len(),
+        largest,
+    }
+}
+
+/// Union-find over `0..len`. The root of a set is always its lowest
+/// element.
+#[derive(Debug, Clone)]
+pub struct DisjointSet {
+    parents: Vec<usize>,
+}
+
+impl DisjointSet {
+    pub fn new(len: usize) -> Self {
+        Self {
+            parents: (0..len).collect(),
+        }
+    }
+
+    pub fn find(&mut self, mut i: usize) -> usize {
+        while self.parents[i] != i {
+            self.parents[i] = self.parents[self.parents[i]];
+            i = self.parents[i];
+        }
+        i
+    }
+
+    pub fn union(&mut self, a: usize, b: usize) {
+        let a = self.find(a);
+        let b = self.find(b);
+        self.parents[a.max(b)] = a.min(b);
+    }
+}
+
+fn add<T: Copy + Eq + Hash>(res: &mut HashMap<T, Related<T>>, id: T, weight: f64) {
+    let related = res.entry(id).or_insert(Related {
+        id,
+        shared: 0,
+        score: 0.0,
+    });
+    related.shared += 1;
+    related.score += weight;
+}
+
+/// Highest score first, then most shared, then lowest id.
+fn rank<T: Ord>(res: HashMap<T, Related<T>>, limit: usize) -> Vec<Related<T>> {
+    let mut res: Vec<Related<T>> = res.into_values().collect();
+    res.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then(b.shared.cmp(&a.shared))
+            .then(a.id.cmp(&b.id))
+    });
+    res.truncate(limit);
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player_entry::Player;
+
+    #[test]
+    fn traversal() {
+        let mut map = ServerMap::new();
+        let player = |i: u128| Player::new(format!("p{i}"), Uuid::from_u128(i));
+        // p0 - s0 - p1 - s1 - p2, and s2 - p3 apart
+        map.insert("10.0.0.1:25565".parse().unwrap(), [player(0), player(1)])
+            .unwrap();
+        map.insert("10.0.0.2:25565".parse().unwrap(), [player(1), player(2)])
+            .unwrap();
+        map.insert("10.0.0.3:25565".parse().unwrap(), [player(3)])
+            .unwrap();
+        map.insert_server("10.0.0.4:25565".parse().unwrap())
+            .unwrap();
+
+        let nodes = expand(&map, &[Node::Player(0)], 2, 100);
+        assert_eq!(
+            nodes,
+            vec![
+                (Node::Player(0), 0),
+                (Node::Server(0), 1),
+                (Node::Player(1), 2)
+            ]
+        );
+        assert_eq!(expand(&map, &[Node::Player(0)], MAX_DEPTH, 100).len(), 5);
+        assert_eq!(expand(&map, &[Node::Player(0)], MAX_DEPTH, 2).len(), 2);
+
+        assert_eq!(
+            shortest_path(&map, &[0], &[2], MAX_DEPTH),
+            Some(vec![
+                Node::Player(0),
+                Node::Server(0),
+                Node::Player(1),
+                Node::Server(1),
+                Node::Player(2)
+            ])
+        );
+        assert_eq!(shortest_path(&map, &[0], &[2], 3), None);
+        assert_eq!(shortest_path(&map, &[0], &[3], MAX_DEPTH), None);
+        assert_eq!(
+            shortest_path(&map, &[0], &[0], 0),
+            Some(vec![Node::Player(0)])
+        );
+
+        let components = components(&map, 2);
+        assert_eq!(components.count, 3);
+        assert_eq!(
+            components.largest,
+            vec![
+                Component {
+                    root: Node::Player(0),
+                    players: 3,
+                    servers: 2
+                },
+                Component {
+                    root: Node::Player(3),
+                    players: 1,
+                    servers: 1
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn ranks_by_shared_servers() {
+        let mut map = ServerMap::new();
+        let player = |i: u128| Player::new(format!("p{i}"), Uuid::from_u128(i));
+        map.insert(
+            "10.0.0.1:25565".parse().unwrap(),
+            [player(0), player(1), player(2)],
+        )
+        .unwrap();
+        map.insert("10.0.0.2:25565".parse().unwrap(), [player(0), player(1)])
+            .unwrap();
+        map.insert("10.0.0.3:25565".parse().unwrap(), [player(1), player(3)])
+            .unwrap();
+
+        // the same player under a new name
+        map.insert(
+            "10.0.0.4:25565".parse().unwrap(),
+            [
+                player(1),
+                Player::new("renamed".to_string(), Uuid::from_u128(0)),
+            ],
+        )
+        .unwrap();
+        let related = related_players(&map, Uuid::from_u128(0), None, 10);
+        let ids: Vec<(Uuid, usize)> = related.iter().map(|r| (r.id, r.shared)).collect();
+        assert_eq!(ids, vec![(Uuid::from_u128(1), 3), (Uuid::from_u128(2), 1)]);
+        assert_eq!(related_players(&map, Uuid::from_u128(0), None, 1).len(), 1);
+
+        // both records on both servers still count as one shared player
+        for addr in ["10.0.0.1:25565", "10.0.0.2:25565"] {
+            map.insert(
+                addr.parse().unwrap(),
+                [Player::new("renamed".to_string(), Uuid::from_u128(0))],
+            )
+            .unwrap();
+        }
+        let related = related_servers(&map, 0, None, 10);
+        let ids: Vec<(ServerId, usize)> = related.iter().map(|r| (r.id, r.shared)).collect();
+        assert_eq!(ids, vec![(1, 2), (3, 2), (2, 1)]);
+
+        map.servers[3].lock().last_seen = 0;
+        map.servers[1].lock().first_seen = 100;
+        map.servers[1].lock().last_seen = 199;
+        map.servers[0].lock().first_seen = 150;
+        map.servers[0].lock().last_seen = 400;
+        let window = TimeWindow { from: 0, to: 199 };
+        let related = related_players(&map, Uuid::from_u128(0), Some(window), 10);
+        assert_eq!(related[0].id, Uuid::from_u128(1));
+        assert_eq!(related[0].score, 0.5 + 0.25);
+        map.servers[0].lock().first_seen = 300;
+        let related = related_players(&map, Uuid::from_u128(0), Some(window), 10);
+        assert_eq!(
+            related.iter().map(|r| r.id).collect::<Vec<_>>(),
+            vec![Uuid::from_u128(1)]
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, ToSocketAddrs};
+
+use crate::servers_dat::DEFAULT_PORT;
+
+/// Prefix of the SRV record the client looks up for a hostname.
+pub const SRV_PREFIX: &str = "_minecraft._tcp.";
+
+/// Longest hostname accepted, as in DNS.
+pub const MAX_HOSTNAME_LEN: usize = 253;
+
+/// One way a hostname leads to a server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolution {
+    /// The name typed into the client, normalized.
+    pub hostname: String,
+    /// The SRV target the hostname pointed at, if it was resolved
+    /// through `_minecraft._tcp.<hostname>`.
+    pub srv_target: Option<String>,
+    pub addr: SocketAddr,
+}
+
+/// Lowercases `hostname` and drops the trailing dot of a fully
+/// qualified name, so every spelling of a name shares one index entry.
+pub fn normalize_hostname(hostname: &str) -> String {
+    hostname.trim().trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Resolves a hostname the way the client does: without an explicit
+/// port, `_minecraft._tcp.<hostname>` is tried first, then the address
+/// records of the hostname itself on port 25565.
+pub trait Resolver {
+    fn resolve(
+        &self,
+        hostname: &str,
+        port: Option<u16>,
+    ) -> Result<Vec<Resolution>, Box<dyn Error + Send + Sync>>;
+}
+
+/// Uses the system resolver. It only returns address records, so SRV
+/// records are never followed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve(
+        &self,
+        hostname: &str,
+        port: Option<u16>,
+    ) -> Result<Vec<Resolution>, Box<dyn Error + Send + Sync>> {
+        let hostname = normalize_hostname(hostname);
+        let addrs = (hostname.as_str(), port.unwrap_or(DEFAULT_PORT)).to_socket_addrs()?;
+        Ok(addrs
+            .filter(|addr| addr.is_ipv4())
+            .map(|addr| Resolution {
+                hostname: hostname.clone(),
+                srv_target: None,
+                addr,
+            })
+            .collect())
+    }
+}
+
+/// Resolves from records collected ahead of time, e.g. by the scanning
+/// pipeline, without touching the network.
+#[derive(Debug, Clone, Default)]
+pub struct StaticResolver {
+    a: HashMap<String, Vec<Ipv4Addr>>,
+    srv: HashMap<String, Vec<(String, u16)>>,
+}
+
+impl StaticResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_a(&mut self, name: &str, ip: Ipv4Addr) {
+        self.a.entry(normalize_hostname(name)).or_default().push(ip);
+    }
+
+    /// `name` is the full record name, e.g. `_minecraft._tcp.example.com`.
+    pub fn add_srv(&mut self, name: &str, target: &str, port: u16) {
+        self.srv
+            .entry(normalize_hostname(name))
+            .or_default()
+            .push((normalize_hostname(target), port));
+    }
+}
+
+impl Resolver for StaticResolver {
+    fn resolve(
+        &self,
+        hostname: &str,
+        port: Option<u16>,
+    ) -> Result<Vec<Resolution>, Box<dyn Error + Send + Sync>> {
+        let hostname = normalize_hostname(hostname);
+        let mut res = vec![];
+        if port.is_none() {
+            if let Some(targets) = self.srv.get(&format!("{SRV_PREFIX}{hostname}")) {
+                for (target, target_port) in targets {
+                    for ip in self.a.get(target).into_iter().flatten() {
+                        res.push(Resolution {
+                            hostname: hostname.clone(),
+                            srv_target: Some(target.clone()),
+                            addr: SocketAddr::V4(SocketAddrV4::new(*ip, *target_port)),
+                        });
+                    }
+                }
+            }
+            if !res.is_empty() {
+                return Ok(res);
+            }
+        }
+        for ip in self.a.get(&hostname).into_iter().flatten() {
+            res.push(Resolution {
+                hostname: hostname.clone(),
+                srv_target: None,
+                addr: SocketAddr::V4(SocketAddrV4::new(*ip, port.unwrap_or(DEFAULT_PORT))),
+            });
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn static_resolver_prefers_srv() {
+        let mut resolver = StaticResolver::new();
+        resolver.add_a("Example.com.", Ipv4Addr::new(10, 0, 0, 1));
+        resolver.add_a("mc.example.net", Ipv4Addr::new(10, 0, 0, 2));
+        resolver.add_srv("_minecraft._tcp.example.com", "mc.example.net.", 25570);
+
+        let res = resolver.resolve("EXAMPLE.com", None).unwrap();
+        assert_eq!(
+            res,
+            vec![Resolution {
+                hostname: "example.com".to_string(),
+                srv_target: Some("mc.example.net".to_string()),
+                addr: "10.0.0.2:25570".parse().unwrap(),
+            }]
+        );
+
+        // an explicit port skips the SRV lookup
+        let res = resolver.resolve("example.com", Some(25565)).unwrap();
+        assert_eq!(res[0].addr, "10.0.0.1:25565".parse().unwrap());
+        assert_eq!(res[0].srv_target, None);
+
+        assert!(resolver.resolve("unknown.com", None).unwrap().is_empty());
+    }
+}
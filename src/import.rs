@@ -0,0 +1,674 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::export::JsonRecord;
+use crate::hostname::{Resolver, SystemResolver};
+use crate::player_entry::Player;
+use crate::server_entry::Server;
+use crate::server_map::ServerMap;
+use crate::servers_dat::{parse_host_port, read_servers_dat, ServerListEntry, DEFAULT_PORT};
+
+/// Number of addresses inserted under a single lock of the map, and how
+/// often progress is reported.
+const IMPORT_CHUNK_SIZE: usize = 100_000;
+
+/// Longest masscan binary record accepted. Port records are a few dozen
+/// bytes and banners a few kilobytes, so anything longer is corrupt.
+const MAX_MASSCAN_RECORD_LEN: u64 = 64 * 1024;
+
+/// Source tag of servers imported from a `servers.dat` without `--source`.
+const DEFAULT_SERVERS_DAT_SOURCE: &str = "servers.dat";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanFormat {
+    /// `masscan -oJ`
+    MasscanJson,
+    /// `masscan -oL`
+    MasscanList,
+    /// `masscan -oB`
+    MasscanBinary,
+    /// zmap CSV output, with or without a header row
+    ZmapCsv,
+}
+
+impl ScanFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "masscan-json" => Some(ScanFormat::MasscanJson),
+            "masscan-list" => Some(ScanFormat::MasscanList),
+            "masscan-binary" => Some(ScanFormat::MasscanBinary),
+            "zmap-csv" => Some(ScanFormat::ZmapCsv),
+            _ => None,
+        }
+    }
+}
+
+/// `mcdb import --format <format> [--port <port>] [--dry-run] <file>`
+/// `mcdb import --format servers-dat [--source <tag>] [--resolve] [--dry-run] <file>`
+///
+/// Creates a bare server for every open port in a scan output file,
+/// loads a `mcdb export --format jsonl` dump with `--format jsonl`, or
+/// creates a server for every entry of a client `servers.dat`, tagged
+/// with `--source`. Hostnames in a `servers.dat` are looked up among the
+/// ones already known from the pipeline, and only in DNS with
+/// `--resolve`. Works on `Config::data_dir` directly, see there.
+pub async fn run(
+    config: &crate::config::Config,
+    args: &[String],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut format = None;
+    let mut default_port = DEFAULT_PORT;
+    let mut dry_run = false;
+    let mut source = None;
+    let mut resolve = false;
+    let mut path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => format = Some(args.next().ok_or("--format needs a value")?.clone()),
+            "--port" => default_port = args.next().ok_or("--port needs a value")?.parse()?,
+            "--dry-run" => dry_run = true,
+            "--source" => source = Some(args.next().ok_or("--source needs a value")?.clone()),
+            "--resolve" => resolve = true,
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument {arg}").into()),
+        }
+    }
+    let format = format.ok_or("missing --format")?;
+    let path = path.ok_or("missing input file")?;
+    if source.is_some() && format != "servers-dat" {
+        return Err("--source is only supported by --format servers-dat".into());
+    }
+    if resolve && format != "servers-dat" {
+        return Err("--resolve is only supported by --format servers-dat".into());
+    }
+
+    let map = Arc::new(Mutex::new(crate::snapshot::deserialize_all(config).await?));
+
+    if format == "jsonl" {
+        let mut reader = BufReader::new(std::fs::File::open(&path)?);
+        if dry_run {
+            let mut records = 0;
+            for line in reader.lines() {
+                serde_json::from_str::<JsonRecord>(&line?)?;
+                records += 1;
+            }
+            println!("import: {records} records (dry run)");
+            return Ok(());
+        }
+        let records = read_jsonl(&mut map.lock(), &mut reader)?;
+        println!("import: {records} records");
+        crate::snapshot::serialize_all(map, config).await?;
+        return Ok(());
+    }
+    if format == "servers-dat" {
+        let source = source.as_deref().unwrap_or(DEFAULT_SERVERS_DAT_SOURCE);
+        let entries = read_servers_dat(&mut BufReader::new(std::fs::File::open(&path)?))?;
+        let resolver = resolve.then_some(&SystemResolver as &dyn Resolver);
+        let summary = import_server_list(&mut map.lock(), &entries, resolver, source, dry_run)?;
+        for (ip, err) in &summary.unresolved {
+            println!("import: could not resolve {ip}: {err}, skipping");
+        }
+        println!(
+            "import: {} entries, {} addresses, {} new, {} unresolved{}",
+            entries.len(),
+            summary.addresses,
+            summary.new,
+            summary.unresolved.len(),
+            if dry_run { " (dry run)" } else { "" }
+        );
+        if !dry_run {
+            crate::snapshot::serialize_all(map, config).await?;
+        }
+        return Ok(());
+    }
+    let format =
+        ScanFormat::from_name(&format).ok_or_else(|| format!("unknown format {format}"))?;
+
+    let file = std::fs::File::open(&path)?;
+    let total_bytes = file.metadata()?.len();
+    let reader = ProgressReader {
+        inner: file,
+        read: Arc::new(AtomicU64::new(0)),
+    };
+    let read_bytes = reader.read.clone();
+
+    let mut importer = Importer {
+        map: map.clone(),
+        dry_run,
+        pending: vec![],
+        seen: HashSet::new(),
+        total: 0,
+        new: 0,
+    };
+    let mut on_addr = |addr: SocketAddr| -> Result<(), Box<dyn Error + Send + Sync>> {
+        importer.total += 1;
+        importer.pending.push(addr);
+        if importer.pending.len() >= IMPORT_CHUNK_SIZE {
+            importer.flush()?;
+            let read = read_bytes.load(Ordering::Relaxed);
+            println!(
+                "import: {} addresses, {} new ({:.1}%)",
+                importer.total,
+                importer.new,
+                100.0 * read as f64 / total_bytes.max(1) as f64
+            );
+        }
+        Ok(())
+    };
+    let mut reader = BufReader::new(reader);
+    match format {
+        ScanFormat::MasscanJson => read_masscan_json(&mut reader, &mut on_addr)?,
+        ScanFormat::MasscanList => read_masscan_list(&mut reader, &mut on_addr)?,
+        ScanFormat::MasscanBinary => read_masscan_binary(&mut reader, &mut on_addr)?,
+        ScanFormat::ZmapCsv => read_zmap_csv(&mut reader, default_port, &mut on_addr)?,
+    }
+    importer.flush()?;
+
+    println!(
+        "import: {} addresses, {} new, {} already known{}",
+        importer.total,
+        importer.new,
+        importer.total - importer.new,
+        if dry_run { " (dry run)" } else { "" }
+    );
+
+    if !dry_run {
+        crate::snapshot::serialize_all(map, config).await?;
+    }
+
+    Ok(())
+}
+
+struct Importer {
+    map: Arc<Mutex<ServerMap>>,
+    dry_run: bool,
+    pending: Vec<SocketAddr>,
+    /// New addresses seen during a dry run, which are never inserted.
+    seen: HashSet<SocketAddr>,
+    total: usize,
+    new: usize,
+}
+
+impl Importer {
+    fn flush(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let pending = std::mem::take(&mut self.pending);
+        let mut map = self.map.lock();
+        if self.dry_run {
+            for addr in pending {
+                if map.find_id(addr)?.is_none() && self.seen.insert(addr) {
+                    self.new += 1;
+                }
+            }
+        } else {
+            let summary = map.insert_batch(pending.into_iter().map(|addr| (addr, vec![])))?;
+            self.new += summary.new_servers;
+        }
+        Ok(())
+    }
+}
+
+struct ProgressReader<R> {
+    inner: R,
+    read: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+/// Outcome of `import_server_list`.
+#[derive(Debug, Default)]
+pub struct ServerListSummary {
+    /// Number of addresses the entries resolved to.
+    pub addresses: usize,
+    /// Number of those servers that were not known before.
+    pub new: usize,
+    /// Entries that could not be resolved, with the reason.
+    pub unresolved: Vec<(String, String)>,
+}
+
+/// Creates a server for every address the `servers.dat` entries resolve
+/// to, tags it with `source` and records the hostname it was reached
+/// through. Hostnames already in the map resolve to their servers;
+/// others go to `resolver`, or are unresolved without one. A dry run only
+/// counts them.
+pub fn import_server_list(
+    map: &mut ServerMap,
+    entries: &[ServerListEntry],
+    resolver: Option<&dyn Resolver>,
+    source: &str,
+    dry_run: bool,
+) -> Result<ServerListSummary, Box<dyn Error + Send + Sync>> {
+    let mut summary = ServerListSummary::default();
+    let mut new = HashSet::new();
+    for entry in entries {
+        let (host, port) = match parse_host_port(&entry.ip) {
+            Ok(host_port) => host_port,
+            Err(err) => {
+                summary.unresolved.push((entry.ip.clone(), err.to_string()));
+                continue;
+            }
+        };
+        let resolutions = match host.parse::<Ipv4Addr>() {
+            Ok(ip) => vec![(
+                SocketAddr::V4(SocketAddrV4::new(ip, port.unwrap_or(DEFAULT_PORT))),
+                None,
+            )],
+            Err(_) if !map.lookup_hostname(host).is_empty() => map
+                .lookup_hostname(host)
+                .into_iter()
+                .map(|server_id| (map.servers[server_id as usize].addr(), None))
+                .filter(|(addr, _)| port.is_none_or(|port| addr.port() == port))
+                .collect(),
+            Err(_) => match resolver.map(|resolver| resolver.resolve(host, port)) {
+                None => {
+                    summary
+                        .unresolved
+                        .push((entry.ip.clone(), "unknown hostname".to_string()));
+                    continue;
+                }
+                Some(Ok(resolutions)) if resolutions.is_empty() => {
+                    summary
+                        .unresolved
+                        .push((entry.ip.clone(), "no IPv4 address".to_string()));
+                    continue;
+                }
+                Some(Ok(resolutions)) => resolutions
+                    .into_iter()
+                    .map(|resolution| (resolution.addr, Some(resolution)))
+                    .collect(),
+                Some(Err(err)) => {
+                    summary.unresolved.push((entry.ip.clone(), err.to_string()));
+                    continue;
+                }
+            },
+        };
+
+        for (addr, resolution) in resolutions {
+            summary.addresses += 1;
+            if map.find_id(addr)?.is_none() {
+                new.insert(addr);
+            }
+            if dry_run {
+                continue;
+            }
+            let server_id = match resolution {
+                Some(resolution) => map.add_resolution(&resolution)?,
+                None => map.insert_server(addr)?,
+            };
+            map.servers[server_id as usize].lock().add_source(source);
+        }
+    }
+    summary.new = new.len();
+    Ok(summary)
+}
+
+/// Loads a `mcdb export --format jsonl` dump into `map`, returning the
+/// number of records read.
+pub fn read_jsonl(
+    map: &mut ServerMap,
+    reader: &mut impl BufRead,
+) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    let mut records = 0;
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line)? {
+            JsonRecord::Server {
+                addr,
+                first_seen,
+                last_seen,
+                sources,
+                hostnames,
+                srv_targets,
+                motd,
+                attributes,
+                players,
+            } => {
+                let mut server = Server::new(addr);
+                server.first_seen = first_seen;
+                server.last_seen = last_seen;
+                server.sources = sources;
+                server.hostnames = hostnames;
+                server.srv_targets = srv_targets;
+                server.motd = motd;
+                server.attributes = attributes;
+                let server_id = map.merge_server(&server)?;
+                for player in players {
+                    let player_id = map.insert_player(Player::new(player.name, player.uuid))?;
+                    map.link(server_id, player_id);
+                }
+            }
+            JsonRecord::Player {
+                name,
+                uuid,
+                servers,
+            } => {
+                let player_id = map.insert_player(Player::new(name, uuid))?;
+                for addr in servers {
+                    let server_id = map.insert_server(addr)?;
+                    map.link(server_id, player_id);
+                }
+            }
+        }
+        records += 1;
+    }
+    Ok(records)
+}
+
+/// Reads `masscan -oL` output, which has one line per result:
+/// `open tcp 25565 1.2.3.4 1686000000`
+pub fn read_masscan_list(
+    reader: &mut impl BufRead,
+    on_addr: &mut impl FnMut(SocketAddr) -> Result<(), Box<dyn Error + Send + Sync>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 4 {
+            return Err(format!("malformed masscan line: {line}").into());
+        }
+        if fields[0] != "open" || fields[1] != "tcp" {
+            continue;
+        }
+        let port: u16 = fields[2].parse()?;
+        let ip: Ipv4Addr = fields[3].parse()?;
+        on_addr(SocketAddr::V4(SocketAddrV4::new(ip, port)))?;
+    }
+    Ok(())
+}
+
+/// Reads `masscan -oJ` output. masscan writes one object per line,
+/// wrapped in `[` / `]` lines and separated by trailing commas, so the
+/// file is parsed line by line instead of as a whole.
+pub fn read_masscan_json(
+    reader: &mut impl BufRead,
+    on_addr: &mut impl FnMut(SocketAddr) -> Result<(), Box<dyn Error + Send + Sync>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim().trim_end_matches(',');
+        if line.is_empty() || line == "[" || line == "]" {
+            continue;
+        }
+        let record: serde_json::Value = serde_json::from_str(line)?;
+        let ip: Ipv4Addr = record["ip"]
+            .as_str()
+            .ok_or_else(|| format!("masscan record without ip: {line}"))?
+            .parse()?;
+        let ports = record["ports"]
+            .as_array()
+            .ok_or_else(|| format!("masscan record without ports: {line}"))?;
+        for port in ports {
+            if port["proto"].as_str() != Some("tcp") || port["status"].as_str() != Some("open") {
+                continue;
+            }
+            let port = port["port"]
+                .as_u64()
+                .and_then(|port| u16::try_from(port).ok())
+                .ok_or_else(|| format!("masscan record with invalid port: {line}"))?;
+            on_addr(SocketAddr::V4(SocketAddrV4::new(ip, port)))?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads `masscan -oB` output. Every record, including the
+/// `masscan/1.1` file header, is a type and a length (both big-endian
+/// base-128 varints) followed by `length` bytes.
+pub fn read_masscan_binary(
+    reader: &mut impl BufRead,
+    on_addr: &mut impl FnMut(SocketAddr) -> Result<(), Box<dyn Error + Send + Sync>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut magic = [0u8; 11];
+    reader.read_exact(&mut magic)?;
+    if &magic != b"masscan/1.1" {
+        return Err("not a masscan binary file".into());
+    }
+    // the header is padded to 99 bytes, i.e. a 'm' record of length 'a'
+    let mut header = [0u8; 99 - 11];
+    reader.read_exact(&mut header)?;
+
+    while let Some(record_type) = read_masscan_varint(reader)? {
+        let length = read_masscan_varint(reader)?.ok_or("truncated masscan record")?;
+        if length > MAX_MASSCAN_RECORD_LEN {
+            return Err(format!("masscan record is {length} bytes long").into());
+        }
+        let mut record = vec![0u8; length as usize];
+        reader.read_exact(&mut record)?;
+
+        let (ip, port) = match record_type {
+            // open, version 1: timestamp[4] ip[4] port[2] reason ttl
+            1 if record.len() >= 10 => (&record[4..8], &record[8..10]),
+            // open, version 2: timestamp[4] ip[4] ip_proto port[2] reason ttl
+            4 if record.len() >= 11 && record[8] == 6 => (&record[4..8], &record[9..11]),
+            // closed ports, banners and IPv6 results
+            _ => continue,
+        };
+        on_addr(SocketAddr::V4(SocketAddrV4::new(
+            Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3]),
+            u16::from_be_bytes([port[0], port[1]]),
+        )))?;
+    }
+    Ok(())
+}
+
+fn read_masscan_varint(
+    reader: &mut impl BufRead,
+) -> Result<Option<u64>, Box<dyn Error + Send + Sync>> {
+    let mut res = 0u64;
+    let mut first = true;
+    loop {
+        let mut byte = [0u8; 1];
+        if reader.read(&mut byte)? == 0 {
+            if first {
+                return Ok(None);
+            }
+            return Err("truncated masscan record".into());
+        }
+        first = false;
+        res = (res << 7) | (byte[0] & 0x7f) as u64;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(res));
+        }
+    }
+}
+
+/// Reads zmap CSV output. With a header row, `saddr` is required and
+/// `sport` and `success` are used when present; without one, every line
+/// is a bare address on `default_port`.
+pub fn read_zmap_csv(
+    reader: &mut impl BufRead,
+    default_port: u16,
+    on_addr: &mut impl FnMut(SocketAddr) -> Result<(), Box<dyn Error + Send + Sync>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut saddr_column = 0;
+    let mut sport_column = None;
+    let mut success_column = None;
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').collect();
+        if i == 0 && fields.contains(&"saddr") {
+            for (column, field) in fields.iter().enumerate() {
+                match *field {
+                    "saddr" => saddr_column = column,
+                    "sport" => sport_column = Some(column),
+                    "success" => success_column = Some(column),
+                    _ => {}
+                }
+            }
+            continue;
+        }
+
+        if let Some(column) = success_column {
+            if fields.get(column) != Some(&"1") {
+                continue;
+            }
+        }
+        let ip: Ipv4Addr = fields
+            .get(saddr_column)
+            .ok_or_else(|| format!("malformed zmap line: {line}"))?
+            .parse()?;
+        let port = match sport_column {
+            Some(column) => fields
+                .get(column)
+                .ok_or_else(|| format!("malformed zmap line: {line}"))?
+                .parse()?,
+            None => default_port,
+        };
+        on_addr(SocketAddr::V4(SocketAddrV4::new(ip, port)))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(addr: &str) -> SocketAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn masscan_list() {
+        let input =
+            "#masscan\nopen tcp 25565 1.2.3.4 1686000000\nopen udp 53 1.2.3.5 1686000000\n# end\n";
+        let mut addrs = vec![];
+        read_masscan_list(&mut input.as_bytes(), &mut |addr| {
+            addrs.push(addr);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(addrs, vec![addr("1.2.3.4:25565")]);
+    }
+
+    #[test]
+    fn masscan_json() {
+        let input = r#"[
+{   "ip": "1.2.3.4",   "timestamp": "1686000000", "ports": [ {"port": 25565, "proto": "tcp", "status": "open", "reason": "syn-ack", "ttl": 52} ] },
+{   "ip": "1.2.3.5",   "timestamp": "1686000000", "ports": [ {"port": 25566, "proto": "tcp", "status": "open", "reason": "syn-ack", "ttl": 52} ] }
+]
+"#;
+        let mut addrs = vec![];
+        read_masscan_json(&mut input.as_bytes(), &mut |addr| {
+            addrs.push(addr);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(addrs, vec![addr("1.2.3.4:25565"), addr("1.2.3.5:25566")]);
+    }
+
+    #[test]
+    fn masscan_binary() {
+        let mut input = vec![0u8; 99];
+        input[..11].copy_from_slice(b"masscan/1.1");
+        // open, version 1
+        input.extend([1, 12, 0, 0, 0, 0, 1, 2, 3, 4, 0x63, 0xdd, 2, 64]);
+        // open, version 2 over tcp
+        input.extend([4, 13, 0, 0, 0, 0, 1, 2, 3, 5, 6, 0x63, 0xde, 2, 64]);
+        // a banner, which is skipped
+        input.extend([3, 2, 0xff, 0xff]);
+        let mut addrs = vec![];
+        read_masscan_binary(&mut &input[..], &mut |addr| {
+            addrs.push(addr);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(addrs, vec![addr("1.2.3.4:25565"), addr("1.2.3.5:25566")]);
+
+        // a corrupt length is rejected instead of allocated
+        input.extend([1, 0xff, 0xff, 0xff, 0xff, 0x7f]);
+        let err = read_masscan_binary(&mut &input[..], &mut |_| Ok(())).unwrap_err();
+        assert_eq!(err.to_string(), "masscan record is 34359738367 bytes long");
+    }
+
+    #[test]
+    fn zmap_csv() {
+        let input = "saddr,sport,success\n1.2.3.4,25565,1\n1.2.3.5,25566,0\n";
+        let mut addrs = vec![];
+        read_zmap_csv(&mut input.as_bytes(), 1, &mut |addr| {
+            addrs.push(addr);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(addrs, vec![addr("1.2.3.4:25565")]);
+
+        let input = "1.2.3.4\n1.2.3.5\n";
+        addrs.clear();
+        read_zmap_csv(&mut input.as_bytes(), 25565, &mut |addr| {
+            addrs.push(addr);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(addrs, vec![addr("1.2.3.4:25565"), addr("1.2.3.5:25565")]);
+    }
+
+    #[test]
+    fn server_list_resolves_hostnames() {
+        let mut resolver = crate::hostname::StaticResolver::new();
+        resolver.add_a("mc.example.net", Ipv4Addr::new(10, 0, 0, 2));
+        resolver.add_srv("_minecraft._tcp.example.com", "mc.example.net", 25570);
+        let entries: Vec<ServerListEntry> =
+            ["10.0.0.1", "example.com", "10.0.0.1:25565", "nowhere.com"]
+                .into_iter()
+                .map(|ip| ServerListEntry {
+                    name: String::new(),
+                    ip: ip.to_string(),
+                })
+                .collect();
+
+        let mut map = ServerMap::new();
+        let summary =
+            import_server_list(&mut map, &entries, Some(&resolver), "friend", true).unwrap();
+        assert_eq!((summary.addresses, summary.new), (3, 2));
+        assert!(map.servers.is_empty());
+
+        // without a resolver, only addresses and known hostnames resolve
+        let summary = import_server_list(&mut map, &entries, None, "friend", true).unwrap();
+        assert_eq!((summary.addresses, summary.new), (2, 1));
+        assert_eq!(summary.unresolved.len(), 2);
+
+        let summary =
+            import_server_list(&mut map, &entries, Some(&resolver), "friend", false).unwrap();
+        assert_eq!((summary.addresses, summary.new), (3, 2));
+        assert_eq!(summary.unresolved.len(), 1);
+        assert_eq!(map.servers.len(), 2);
+
+        let found = map.lookup_hostname("Example.COM.");
+        assert_eq!(found.len(), 1);
+        let server = map.servers[found[0] as usize].lock().clone();
+        assert_eq!(server.addr, "10.0.0.2:25570".parse().unwrap());
+        assert_eq!(server.srv_targets, vec!["mc.example.net"]);
+        assert_eq!(server.sources, vec!["friend"]);
+        assert_eq!(map.lookup_hostname("mc.example.net"), found);
+
+        let summary = import_server_list(&mut map, &entries, None, "other", false).unwrap();
+        assert_eq!((summary.addresses, summary.new), (3, 0));
+        assert_eq!(summary.unresolved.len(), 1);
+        let server = map.servers[found[0] as usize].lock().clone();
+        assert_eq!(server.sources, vec!["friend", "other"]);
+    }
+}
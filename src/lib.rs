@@ -0,0 +1,27 @@
+pub mod attributes;
+pub mod cidr;
+pub mod config;
+pub mod cursor;
+pub mod export;
+pub mod fsck;
+pub mod graph;
+pub mod hostname;
+pub mod import;
+pub mod logging;
+pub mod metrics;
+pub mod motd;
+pub mod nbt;
+pub mod network;
+pub mod player_entry;
+pub mod protocol;
+pub mod query;
+pub mod recent;
+pub mod server_entry;
+pub mod server_map;
+pub mod servers_dat;
+pub mod shutdown;
+pub mod snapshot;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod stats;
+pub mod tables;
@@ -1,175 +1,595 @@
-pub mod player_entry;
-pub mod server_entry;
-pub mod server_map;
+use mcdb::config::{self, Config};
+use mcdb::cursor::Cursor;
+use mcdb::metrics::{self, METRICS};
+use mcdb::player_entry::Player;
+use mcdb::server_entry::Server;
+use mcdb::server_map::{self, BatchSummary, ServerMap};
+use mcdb::shutdown::{self, Shutdown};
+use mcdb::snapshot::{deserialize_all, serialize_all};
+use mcdb::{export, fsck, graph, import, logging, network, protocol, query};
 
-use player_entry::{Player, PlayerArcWrapper};
-use server_entry::{Server, ServerArcWrapper};
-use server_map::ServerMap;
-
-use std::collections::{BTreeSet, HashMap};
 use std::io::Write;
-use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
-use std::sync::mpsc::channel;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
 use std::{error::Error, sync::Arc};
 
-use integer_encoding::VarIntWriter;
+use integer_encoding::{VarIntAsyncReader, VarIntWriter};
 use parking_lot::Mutex;
-use threadpool::ThreadPool;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::AsyncReadExt;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::spawn;
-use uuid::uuid;
+use tokio::task::JoinSet;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, debug_span, error, info, info_span, trace, warn, Instrument};
+use uuid::Uuid;
+
+/// Number of sightings from an `OP_INSERT_BATCH` request that are
+/// inserted under a single lock of the map.
+const BATCH_CHUNK_SIZE: usize = 4096;
 
+/// Serves requests until the client hangs up or, between requests,
+/// until `shutdown`.
 async fn handle_connection(
     socket: &mut TcpStream,
     map: Arc<Mutex<ServerMap>>,
+    config: &Config,
+    shutdown: &mut Shutdown,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     loop {
-        let mut buf = [0u8; 8];
-        let _ = socket.read_exact(&mut buf).await?;
-        let mut push_bst: BTreeSet<PlayerArcWrapper> = BTreeSet::new();
-        let name = std::str::from_utf8(&buf[6..8])?;
-        push_bst.insert(PlayerArcWrapper::new(Player {
-            name: name.to_string(),
-            uuid: uuid!("F9168C5E-CEB2-4faa-B6BF-329BF39FA1E4"),
-            servers: BTreeSet::new(),
-        }));
+        let res = tokio::select! {
+            res = socket.read_u8() => res,
+            _ = shutdown.wait() => return Ok(()),
+        };
+        let opcode = match res {
+            Ok(opcode) => opcode,
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
 
-        let mut lock = map.lock();
-
-        lock.insert(ServerArcWrapper::new(Server {
-            addr: SocketAddr::V4(SocketAddrV4::new(
-                Ipv4Addr::new(buf[0], buf[1], buf[2], buf[3]),
-                u8s_to_u16(buf[4], buf[5]),
-            )),
-            players: push_bst,
-        }))?;
-
-        let found = lock.find(SocketAddr::V4(SocketAddrV4::new(
-            Ipv4Addr::new(buf[0], buf[1], buf[2], buf[3]),
-            u8s_to_u16(buf[4], buf[5]),
-        )))?;
-
-        // println!("found: {:?}", found.lock());
-
-        match found {
-            Some(found) => {
-                println!(
-                    "found players: {:?}",
-                    found
-                        .lock()
-                        .players
-                        .iter()
-                        .map(|item| { item.lock().name.clone() })
-                        .collect::<Vec<String>>()
+        let op = protocol::op_name(opcode).unwrap_or("unknown");
+        let span = debug_span!("request", op, opcode);
+        let start = Instant::now();
+        let res = dispatch(opcode, socket, &map, config)
+            .instrument(span.clone())
+            .await;
+        let elapsed = start.elapsed();
+        METRICS.request(opcode, elapsed, res.is_err());
+        match res {
+            Ok(body) => {
+                debug!(
+                    parent: &span,
+                    elapsed_us = elapsed.as_micros() as u64,
+                    bytes = body.len(),
+                    "request done"
                 );
+                protocol::write_response(socket, protocol::STATUS_OK, &body).await?
             }
-            None => {
-                println!("Not found");
+            Err(err) => {
+                warn!(parent: &span, error = %err, "request failed");
+                // the rest of the request can't be framed, so give up on the connection
+                protocol::write_response(
+                    socket,
+                    protocol::STATUS_ERROR,
+                    err.to_string().as_bytes(),
+                )
+                .await?;
+                return Err(err);
             }
         }
+    }
+}
 
-        drop(lock);
+async fn dispatch(
+    opcode: u8,
+    socket: &mut TcpStream,
+    map: &Arc<Mutex<ServerMap>>,
+    config: &Config,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    match opcode {
+        protocol::OP_INSERT => handle_insert(socket, map).await,
+        protocol::OP_INSERT_BATCH => handle_insert_batch(socket, map).await,
+        protocol::OP_INSERT_RESOLUTION => handle_insert_resolution(socket, map).await,
+        protocol::OP_LOOKUP_HOSTNAME => handle_lookup_hostname(socket, map).await,
+        protocol::OP_SEARCH_NAMES => handle_search_names(socket, map).await,
+        protocol::OP_SET_MOTD => handle_set_motd(socket, map).await,
+        protocol::OP_SEARCH_MOTD => handle_search_motd(socket, map).await,
+        protocol::OP_SET_ATTRIBUTES => handle_set_attributes(socket, map).await,
+        protocol::OP_FIND_BY_ATTRIBUTE => handle_find_by_attribute(socket, map).await,
+        protocol::OP_QUERY => handle_query(socket, map).await,
+        protocol::OP_SCAN_SERVERS => handle_scan_servers(socket, map).await,
+        protocol::OP_SCAN_PLAYERS => handle_scan_players(socket, map).await,
+        protocol::OP_RELATED_PLAYERS => handle_related_players(socket, map).await,
+        protocol::OP_RELATED_SERVERS => handle_related_servers(socket, map).await,
+        protocol::OP_EXPAND => handle_expand(socket, map).await,
+        protocol::OP_SHORTEST_PATH => handle_shortest_path(socket, map).await,
+        protocol::OP_COMPONENTS => handle_components(socket, map).await,
+        protocol::OP_DETECT_NETWORKS => handle_detect_networks(socket, map).await,
+        protocol::OP_STATS => {
+            protocol::serialize_stats_report(&METRICS.lock_map(map).stats(server_map::unix_now()))
+        }
+        protocol::OP_GET_SERVER => handle_get_server(socket, map).await,
+        protocol::OP_GET_PLAYER => handle_get_player(socket, map).await,
+        protocol::OP_SNAPSHOT => handle_snapshot(map, config).await,
+        protocol::OP_DELETE_SERVER => handle_delete_server(socket, map).await,
+        protocol::OP_DELETE_PLAYER => handle_delete_player(socket, map).await,
+        protocol::OP_RECENT_INSERTS => handle_recent_inserts(socket, map).await,
+        _ => Err(format!("unknown opcode {opcode:#04x}").into()),
     }
 }
 
-async fn serialize_all(map: Arc<Mutex<ServerMap>>) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let lock = map.lock();
-    let player_array = &lock.player_array;
-    let server_array = lock.server_array.to_owned();
+async fn handle_insert(
+    socket: &mut TcpStream,
+    map: &Arc<Mutex<ServerMap>>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let (addr, players) = protocol::read_sighting(socket).await?;
 
-    tokio::fs::create_dir_all("./data_bin/servers/").await?;
+    let mut lock = METRICS.lock_map(map);
 
-    if tokio::fs::try_exists("./data_bin/players.bin.old").await? {
-        tokio::fs::remove_file("./data_bin/players.bin.old").await?;
-    }
-    if tokio::fs::try_exists("./data_bin/players.bin").await? {
-        tokio::fs::rename("./data_bin/players.bin", "./data_bin/players.bin.old").await?;
+    let num_players = players.len();
+    lock.insert(addr, players)?;
+    drop(lock);
+    METRICS.sightings.fetch_add(1, Ordering::Relaxed);
+    trace!(%addr, players = num_players, "inserted sighting");
+
+    Ok(vec![])
+}
+
+/*--- Batch Summary --------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| servers       | varint            | variable size |
+| new servers   | varint            | variable size |
+| new players   | varint            | variable size |
+|--------------------------------------------------*/
+async fn handle_insert_batch(
+    socket: &mut TcpStream,
+    map: &Arc<Mutex<ServerMap>>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let total: usize = socket.read_varint_async().await?;
+    let mut summary = BatchSummary::default();
+    let mut remaining = total;
+    while remaining > 0 {
+        let chunk_len = remaining.min(BATCH_CHUNK_SIZE);
+        let mut chunk = Vec::with_capacity(chunk_len);
+        for _ in 0..chunk_len {
+            chunk.push(protocol::read_sighting(socket).await?);
+        }
+        remaining -= chunk_len;
+
+        let chunk_summary = METRICS.lock_map(map).insert_batch(chunk)?;
+        METRICS
+            .sightings
+            .fetch_add(chunk_summary.servers as u64, Ordering::Relaxed);
+        summary.servers += chunk_summary.servers;
+        summary.new_servers += chunk_summary.new_servers;
+        summary.new_players += chunk_summary.new_players;
     }
 
-    let mut player_buf: Vec<u8> = vec![];
-    player_buf.write_varint(player_array.len())?;
-    for player in player_array {
-        AsyncWriteExt::write_all(&mut player_buf, &player.serialize()?).await?;
+    debug!(
+        servers = summary.servers,
+        new_servers = summary.new_servers,
+        new_players = summary.new_players,
+        "inserted batch"
+    );
+
+    let mut body = vec![];
+    body.write_varint(summary.servers)?;
+    body.write_varint(summary.new_servers)?;
+    body.write_varint(summary.new_players)?;
+    Ok(body)
+}
+
+async fn handle_insert_resolution(
+    socket: &mut TcpStream,
+    map: &Arc<Mutex<ServerMap>>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let resolution = protocol::read_resolution(socket).await?;
+    METRICS.lock_map(map).add_resolution(&resolution)?;
+    Ok(vec![])
+}
+
+async fn handle_lookup_hostname(
+    socket: &mut TcpStream,
+    map: &Arc<Mutex<ServerMap>>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let hostname = protocol::read_hostname(socket).await?;
+    let lock = METRICS.lock_map(map);
+    let server_ids = lock.lookup_hostname(&hostname);
+    let mut body = vec![];
+    body.write_varint(server_ids.len())?;
+    for server_id in server_ids {
+        body.write_all(&lock.servers[server_id as usize].serialize_pointer()?)?;
     }
+    Ok(body)
+}
 
-    println!("player_buf: {player_buf:X?}");
+async fn handle_search_names(
+    socket: &mut TcpStream,
+    map: &Arc<Mutex<ServerMap>>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let search = protocol::read_name_search(socket).await?;
+    let after = match search.after {
+        Some(Cursor::Player(name, uuid)) => Some((name, uuid)),
+        Some(_) => return Err("not a player cursor".into()),
+        None => None,
+    };
+    let lock = METRICS.lock_map(map);
+    let page = lock.search_names(&search.query, search.mode, after, search.limit);
+    let players: Vec<Player> = page
+        .players
+        .iter()
+        .map(|player_id| {
+            let player = &lock.players[*player_id as usize];
+            Player::new(player.lock().name.clone(), player.uuid())
+        })
+        .collect();
+    let next = page.next.map(|(name, uuid)| Cursor::Player(name, uuid));
+    protocol::serialize_name_page(next.as_ref(), &players)
+}
 
-    tokio::fs::write("./data_bin/players.bin", player_buf).await?;
-    tokio::fs::remove_file("./data_bin/players.bin.old").await?;
+async fn handle_set_motd(
+    socket: &mut TcpStream,
+    map: &Arc<Mutex<ServerMap>>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut addr_buf = [0u8; 6];
+    socket.read_exact(&mut addr_buf).await?;
+    let addr = Server::deserialize_pointer(&mut &addr_buf[..])?;
+    let motd = protocol::read_motd(socket).await?;
+    METRICS.lock_map(map).set_motd(addr, &motd)?;
+    Ok(vec![])
+}
 
-    let n_workers = 256;
-    let n_jobs = server_array.len();
-    let pool = ThreadPool::new(n_workers);
+async fn handle_search_motd(
+    socket: &mut TcpStream,
+    map: &Arc<Mutex<ServerMap>>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let search = protocol::read_motd_search(socket).await?;
+    let lock = METRICS.lock_map(map);
+    let mut server_ids = lock.search_motd(&search.query, search.mode);
+    server_ids.truncate(search.limit);
+    let mut body = vec![];
+    body.write_varint(server_ids.len())?;
+    for server_id in server_ids {
+        body.write_all(&lock.servers[server_id as usize].serialize_pointer()?)?;
+    }
+    Ok(body)
+}
 
-    let (tx, rx) = channel();
+async fn handle_set_attributes(
+    socket: &mut TcpStream,
+    map: &Arc<Mutex<ServerMap>>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut addr_buf = [0u8; 6];
+    socket.read_exact(&mut addr_buf).await?;
+    let addr = Server::deserialize_pointer(&mut &addr_buf[..])?;
+    let updates = protocol::read_attribute_updates(socket).await?;
+    let mut lock = METRICS.lock_map(map);
+    let server_id = lock.insert_server(addr)?;
+    for (attribute, value) in updates {
+        lock.set_attribute(server_id, attribute, value)?;
+    }
+    Ok(vec![])
+}
 
-    for (ip_a, server_range) in server_array {
-        let tx = tx.clone();
-        pool.execute(move || match serialize_server_range(ip_a, server_range) {
-            Ok(_res) => {
-                tx.send(None)
-                    .expect("channel will be there waiting for the pool");
-            }
-            Err(err) => {
-                tx.send(Some(err))
-                    .expect("channel will be there waiting for the pool");
-            }
-        });
+async fn handle_find_by_attribute(
+    socket: &mut TcpStream,
+    map: &Arc<Mutex<ServerMap>>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let search = protocol::read_attribute_search(socket).await?;
+    let lock = METRICS.lock_map(map);
+    let mut server_ids = lock.find_by_attribute(search.attribute, &search.predicate)?;
+    server_ids.truncate(search.limit);
+    let mut body = vec![];
+    body.write_varint(server_ids.len())?;
+    for server_id in server_ids {
+        body.write_all(&lock.servers[server_id as usize].serialize_pointer()?)?;
     }
+    Ok(body)
+}
 
-    let success: bool = rx.iter().take(n_jobs).fold(true, |acc, x| {
-        if let Some(err) = &x {
-            println!("Error: {err}");
-        }
-        acc && x.is_none()
-    });
+async fn handle_query(
+    socket: &mut TcpStream,
+    map: &Arc<Mutex<ServerMap>>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let request = protocol::read_query_request(socket).await?;
+    let query: query::Query = request.query.parse()?;
+    let after = match request.after {
+        Some(Cursor::Server(addr)) => Some(addr),
+        Some(_) => return Err("not a server cursor".into()),
+        None => None,
+    };
+    let lock = METRICS.lock_map(map);
+    let page = query.execute(&lock, after, request.limit)?;
+    let servers: Vec<SocketAddr> = page
+        .servers
+        .iter()
+        .map(|server_id| lock.servers[*server_id as usize].addr())
+        .collect();
+    protocol::serialize_server_scan_page(page.next.map(Cursor::Server).as_ref(), &servers)
+}
 
-    println!("success: {success}");
+async fn handle_scan_servers(
+    socket: &mut TcpStream,
+    map: &Arc<Mutex<ServerMap>>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let scan = protocol::read_server_scan(socket).await?;
+    let after = match scan.after {
+        Some(Cursor::Server(addr)) => Some(addr),
+        Some(_) => return Err("not a server scan cursor".into()),
+        None => None,
+    };
+    let lock = METRICS.lock_map(map);
+    let page = lock.scan_servers(scan.cidr, after, scan.limit);
+    let servers: Vec<SocketAddr> = page
+        .servers
+        .iter()
+        .map(|server_id| lock.servers[*server_id as usize].lock().addr)
+        .collect();
+    protocol::serialize_server_scan_page(page.next.map(Cursor::Server).as_ref(), &servers)
+}
 
-    Ok(())
+async fn handle_scan_players(
+    socket: &mut TcpStream,
+    map: &Arc<Mutex<ServerMap>>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let scan = protocol::read_player_scan(socket).await?;
+    let after = match scan.after {
+        Some(Cursor::Player(name, uuid)) => Some((name, uuid)),
+        Some(_) => return Err("not a player scan cursor".into()),
+        None => None,
+    };
+    let lock = METRICS.lock_map(map);
+    let page = lock.scan_players(after, scan.limit);
+    let players: Vec<Player> = page
+        .players
+        .iter()
+        .map(|player_id| {
+            let player = &lock.players[*player_id as usize];
+            Player::new(player.lock().name.clone(), player.uuid())
+        })
+        .collect();
+    let next = page.next.map(|(name, uuid)| Cursor::Player(name, uuid));
+    protocol::serialize_player_scan_page(next.as_ref(), &players)
 }
 
-fn serialize_server_range(
-    ip_a: u16,
-    server_range: Arc<Mutex<HashMap<u16, HashMap<u16, ServerArcWrapper>>>>,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let segment_a: u8;
-    let segment_b: u8;
-    {
-        let segments = ip_a.to_be_bytes();
-        segment_a = segments[0];
-        segment_b = segments[1];
-    }
-    std::fs::create_dir_all(format!("./data_bin/servers/{}/", segment_a))?;
-    for (_ip_b, ip_servers) in server_range.lock().iter() {
-        let mut stack = std::collections::LinkedList::new();
-        for (_port, server) in ip_servers {
-            let val = server.lock().serialize()?;
-            stack.push_back(val);
+async fn handle_related_players(
+    socket: &mut TcpStream,
+    map: &Arc<Mutex<ServerMap>>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut uuid_buf = [0u8; 16];
+    socket.read_exact(&mut uuid_buf).await?;
+    let uuid = Uuid::from_bytes(uuid_buf);
+    let search = protocol::read_related_search(socket).await?;
+    let lock = METRICS.lock_map(map);
+    if lock.find_player(uuid).is_empty() {
+        return Err(format!("unknown player {uuid}").into());
+    }
+    let related = graph::related_players(&lock, uuid, search.window, search.limit);
+    protocol::serialize_related_page(&related, |uuid| Ok(uuid.as_bytes().to_vec()))
+}
+
+async fn handle_related_servers(
+    socket: &mut TcpStream,
+    map: &Arc<Mutex<ServerMap>>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut addr_buf = [0u8; 6];
+    socket.read_exact(&mut addr_buf).await?;
+    let addr = Server::deserialize_pointer(&mut &addr_buf[..])?;
+    let search = protocol::read_related_search(socket).await?;
+    let lock = METRICS.lock_map(map);
+    let server_id = lock
+        .find_id(addr)?
+        .ok_or_else(|| format!("unknown server {addr}"))?;
+    let related = graph::related_servers(&lock, server_id, search.window, search.limit);
+    protocol::serialize_related_page(&related, |server_id| {
+        lock.servers[*server_id as usize].serialize_pointer()
+    })
+}
+
+fn node_entry(map: &ServerMap, node: graph::Node) -> protocol::NodeEntry {
+    match node {
+        graph::Node::Player(player_id) => {
+            let player = &map.players[player_id as usize];
+            protocol::NodeEntry::Player(Player::new(player.lock().name.clone(), player.uuid()))
         }
-        let total_len = stack.len();
-        let mut total_len_buf = vec![];
-        total_len_buf.write_varint(total_len)?;
-        let mut file = std::fs::File::open(format!(
-            "./data_bin/servers/{}/{}.bin",
-            segment_a, segment_b
-        ))?;
-        file.write_all(&total_len_buf)?;
-        for server in stack {
-            file.write_all(&server)?;
+        graph::Node::Server(server_id) => {
+            protocol::NodeEntry::Server(map.servers[server_id as usize].addr())
         }
     }
+}
 
-    Ok(())
+async fn handle_expand(
+    socket: &mut TcpStream,
+    map: &Arc<Mutex<ServerMap>>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let expansion = protocol::read_expansion(socket).await?;
+    let lock = METRICS.lock_map(map);
+    let start: Vec<graph::Node> = match expansion.start {
+        protocol::NodeKey::Player(uuid) => lock
+            .find_player(uuid)
+            .iter()
+            .map(|player_id| graph::Node::Player(*player_id))
+            .collect(),
+        protocol::NodeKey::Server(addr) => lock
+            .find_id(addr)?
+            .map(graph::Node::Server)
+            .into_iter()
+            .collect(),
+    };
+    if start.is_empty() {
+        return Err(format!("unknown node {:?}", expansion.start).into());
+    }
+    let nodes: Vec<(protocol::NodeEntry, u8)> =
+        graph::expand(&lock, &start, expansion.max_depth, expansion.limit)
+            .into_iter()
+            .map(|(node, depth)| (node_entry(&lock, node), depth))
+            .collect();
+    protocol::serialize_expanded_nodes(&nodes)
+}
+
+async fn handle_shortest_path(
+    socket: &mut TcpStream,
+    map: &Arc<Mutex<ServerMap>>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let search = protocol::read_path_search(socket).await?;
+    let lock = METRICS.lock_map(map);
+    for uuid in [search.from, search.to] {
+        if lock.find_player(uuid).is_empty() {
+            return Err(format!("unknown player {uuid}").into());
+        }
+    }
+    let path = graph::shortest_path(
+        &lock,
+        lock.find_player(search.from),
+        lock.find_player(search.to),
+        search.max_depth,
+    )
+    .unwrap_or_default();
+    let path: Vec<protocol::NodeEntry> = path
+        .into_iter()
+        .map(|node| node_entry(&lock, node))
+        .collect();
+    protocol::serialize_path(&path)
+}
+
+async fn handle_components(
+    socket: &mut TcpStream,
+    map: &Arc<Mutex<ServerMap>>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let limit: usize = socket.read_varint_async().await?;
+    let lock = METRICS.lock_map(map);
+    let components = graph::components(&lock, limit.clamp(1, protocol::MAX_PAGE_LEN));
+    let largest: Vec<(graph::Component, protocol::NodeEntry)> = components
+        .largest
+        .into_iter()
+        .map(|component| (component, node_entry(&lock, component.root)))
+        .collect();
+    protocol::serialize_component_summary(components.count, &largest)
+}
+
+async fn handle_detect_networks(
+    socket: &mut TcpStream,
+    map: &Arc<Mutex<ServerMap>>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let limit: usize = socket.read_varint_async().await?;
+    // only the copy and the results are done under the map lock
+    let graph = network::NetworkGraph::new(&METRICS.lock_map(map))?;
+    let networks = tokio::task::spawn_blocking(move || {
+        network::detect_networks(&graph, &network::NetworkOptions::default())
+    })
+    .await?;
+    network::store_networks(&mut METRICS.lock_map(map), &networks)?;
+    protocol::serialize_network_summary(&networks, limit.clamp(1, protocol::MAX_PAGE_LEN))
+}
+
+async fn handle_get_server(
+    socket: &mut TcpStream,
+    map: &Arc<Mutex<ServerMap>>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let addr = protocol::read_server_pointer(socket).await?;
+    let lock = METRICS.lock_map(map);
+    let server_id = lock
+        .find_id(addr)?
+        .ok_or_else(|| format!("unknown server {addr}"))?;
+    let server = lock.servers[server_id as usize].lock().clone();
+    protocol::serialize_server_info(&server, &lock.players)
+}
+
+async fn handle_get_player(
+    socket: &mut TcpStream,
+    map: &Arc<Mutex<ServerMap>>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let uuid = protocol::read_uuid(socket).await?;
+    let lock = METRICS.lock_map(map);
+    let player_ids = lock.find_player(uuid);
+    if player_ids.is_empty() {
+        return Err(format!("unknown player {uuid}").into());
+    }
+    protocol::serialize_player_records(player_ids, &lock.players, &lock.servers)
+}
+
+async fn handle_snapshot(
+    map: &Arc<Mutex<ServerMap>>,
+    config: &Config,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let start = Instant::now();
+    let bytes = serialize_all(map.clone(), config).await?;
+    protocol::serialize_snapshot_summary(&protocol::SnapshotSummary {
+        bytes,
+        elapsed_ms: start.elapsed().as_millis() as u64,
+    })
+}
+
+async fn handle_delete_server(
+    socket: &mut TcpStream,
+    map: &Arc<Mutex<ServerMap>>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let addr = protocol::read_server_pointer(socket).await?;
+    let deleted = METRICS.lock_map(map).delete_server(addr)?;
+    if deleted {
+        info!(%addr, "deleted server");
+    }
+    Ok(vec![deleted as u8])
+}
+
+async fn handle_delete_player(
+    socket: &mut TcpStream,
+    map: &Arc<Mutex<ServerMap>>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let uuid = protocol::read_uuid(socket).await?;
+    let deleted = METRICS.lock_map(map).delete_player(uuid);
+    if deleted != 0 {
+        info!(%uuid, records = deleted, "deleted player");
+    }
+    let mut body = vec![];
+    body.write_varint(deleted)?;
+    Ok(body)
+}
+
+async fn handle_recent_inserts(
+    socket: &mut TcpStream,
+    map: &Arc<Mutex<ServerMap>>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let request = protocol::read_recent_request(socket).await?;
+    let lock = METRICS.lock_map(map);
+    let entries = lock.recent.since(request.after, request.limit);
+    protocol::serialize_recent_page(lock.recent.latest(), &entries)
+}
+
+fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let config::Loaded {
+        config,
+        print_config,
+        command,
+    } = Config::load(&args)?;
+    if print_config {
+        print!("{}", config.to_toml()?);
+        return Ok(());
+    }
+
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    if config.worker_threads != 0 {
+        runtime.worker_threads(config.worker_threads);
+    }
+    runtime.enable_all().build()?.block_on(run(config, command))
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+async fn run(config: Config, command: Vec<String>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    logging::init(&config.log_level, config.log_format)?;
+    match command.first().map(String::as_str) {
+        Some("export") => return export::run(&config, &command[1..]).await,
+        Some("import") => return import::run(&config, &command[1..]).await,
+        Some("fsck") => return fsck::run(&config, &command[1..]).await,
+        Some(command) => return Err(format!("unknown command {command}").into()),
+        None => {}
+    }
+    let config = Arc::new(config);
+
     {
         use parking_lot::deadlock;
         use std::thread;
-        use std::time::Duration;
         // Create a background thread which checks for deadlocks every 10s
         thread::spawn(move || loop {
             thread::sleep(Duration::from_secs(10));
@@ -178,34 +598,154 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 continue;
             }
 
-            println!("{} deadlocks detected", deadlocks.len());
+            error!(deadlocks = deadlocks.len(), "deadlocks detected");
             for (i, threads) in deadlocks.iter().enumerate() {
-                println!("Deadlock #{i}");
                 for t in threads {
-                    println!("Thread Id {:#?}", t.thread_id());
-                    println!("{:#?}", t.backtrace());
+                    error!(
+                        deadlock = i,
+                        thread_id = t.thread_id(),
+                        backtrace = ?t.backtrace(),
+                        "deadlocked thread"
+                    );
                 }
             }
         });
     }
 
-    let map = Arc::new(Mutex::new(ServerMap::new()));
+    let map = Arc::new(Mutex::new(deserialize_all(&config).await?));
 
-    serialize_all(map.clone()).await.unwrap();
+    if config.snapshot_on_start {
+        serialize_all(map.clone(), &config).await?;
+    }
 
-    let listener = TcpListener::bind("127.0.0.1:38282").await?;
-    loop {
-        let (mut socket, _) = listener.accept().await?;
-        let clone_map = map.clone();
-        spawn(async move { handle_connection(&mut socket, clone_map).await.unwrap() });
+    let (trigger, shutdown) = shutdown::channel();
+
+    let mut snapshots = None;
+    if config.snapshot_interval_secs != 0 {
+        let map = map.clone();
+        let config = config.clone();
+        let mut shutdown = shutdown.clone();
+        snapshots = Some(spawn(async move {
+            let period = Duration::from_secs(config.snapshot_interval_secs);
+            let mut interval =
+                tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = shutdown.wait() => return,
+                }
+                if let Err(err) = serialize_all(map.clone(), &config).await {
+                    error!(error = %err, "could not write snapshot");
+                }
+            }
+        }));
     }
-}
 
-fn u8s_to_u16(a: u8, b: u8) -> u16 {
-    ((a as u16) << 8) | b as u16
+    if let Some(addr) = config.metrics_listen {
+        let map = map.clone();
+        spawn(async move {
+            if let Err(err) = metrics::serve(addr, map).await {
+                error!(error = %err, "metrics endpoint stopped");
+            }
+        });
+    }
+
+    let mut listeners = JoinSet::new();
+    for addr in &config.listen {
+        let listener = TcpListener::bind(addr).await?;
+        info!(addr = %listener.local_addr()?, "listening");
+        listeners.spawn(accept_loop(
+            listener,
+            map.clone(),
+            config.clone(),
+            shutdown.clone(),
+        ));
+    }
+
+    // a failed listener shuts the server down as well, so what is in
+    // memory still makes it to disk
+    let mut failure: Option<Box<dyn Error + Send + Sync>> = None;
+    tokio::select! {
+        signal = shutdown::signal() => info!(signal = signal?, "shutting down"),
+        Some(res) = listeners.join_next() => {
+            let err = match res {
+                Ok(Ok(())) => "listener stopped".into(),
+                Ok(Err(err)) => err,
+                Err(err) => err.into(),
+            };
+            error!(error = %err, "listener failed, shutting down");
+            failure = Some(err);
+        }
+    }
+    let _ = trigger.send(true);
+
+    let timeout = Duration::from_secs(config.shutdown_timeout_secs);
+    let drain = async { while listeners.join_next().await.is_some() {} };
+    if tokio::time::timeout(timeout, drain).await.is_err() {
+        warn!(
+            connections = METRICS.connections.load(Ordering::Relaxed),
+            "connections still open after the shutdown timeout, dropping them"
+        );
+        listeners.shutdown().await;
+    }
+    // lets a periodic snapshot in progress finish first. Both get a
+    // timeout of their own, so a stuck snapshot can't keep the process up
+    let final_snapshot = async {
+        if let Some(snapshots) = snapshots {
+            snapshots.await?;
+        }
+        serialize_all(map, &config).await
+    };
+    let res = match tokio::time::timeout(timeout, final_snapshot).await {
+        Ok(res) => res,
+        Err(_) => Err("timed out".into()),
+    };
+    if let Err(err) = res {
+        error!(error = %err, "could not write the final snapshot");
+        return Err(format!("could not write the final snapshot: {err}").into());
+    }
+    info!("shutdown complete");
+    match failure {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
 }
 
-#[allow(unused)]
-fn u16s_to_u32(a: u16, b: u16) -> u32 {
-    ((a as u32) << 16) | b as u32
+/// Accepts connections until `shutdown`, then waits for the open ones to
+/// finish their current request.
+async fn accept_loop(
+    listener: TcpListener,
+    map: Arc<Mutex<ServerMap>>,
+    config: Arc<Config>,
+    mut shutdown: Shutdown,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut connections = JoinSet::new();
+    loop {
+        let (mut socket, peer) = tokio::select! {
+            res = listener.accept() => res?,
+            Some(_) = connections.join_next() => continue,
+            _ = shutdown.wait() => break,
+        };
+        let clone_map = map.clone();
+        let config = config.clone();
+        let mut shutdown = shutdown.clone();
+        METRICS.connections.fetch_add(1, Ordering::Relaxed);
+        let id = METRICS.connections_total.fetch_add(1, Ordering::Relaxed);
+        let span = info_span!("connection", id, %peer);
+        connections.spawn(
+            async move {
+                debug!("connection opened");
+                match handle_connection(&mut socket, clone_map, &config, &mut shutdown).await {
+                    Ok(()) => debug!("connection closed"),
+                    Err(err) => warn!(error = %err, "connection closed with an error"),
+                }
+                METRICS.connections.fetch_sub(1, Ordering::Relaxed);
+            }
+            .instrument(span),
+        );
+    }
+    drop(listener);
+    while connections.join_next().await.is_some() {}
+    Ok(())
 }
@@ -0,0 +1,364 @@
+use std::error::Error;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::{Mutex, MutexGuard};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::warn;
+
+use crate::protocol;
+use crate::server_map::{unix_now, ServerMap};
+
+/// Upper bounds of the histogram buckets, in seconds.
+const BUCKETS: [f64; 12] = [
+    0.00001, 0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 30.0,
+];
+
+/// Longest HTTP request head read from a scraper.
+const MAX_REQUEST_LEN: usize = 8192;
+
+/// Process-wide metrics, exported in the Prometheus text format by
+/// `serve`. Recording is a few relaxed atomic adds, so it stays on even
+/// when nothing scrapes.
+pub static METRICS: Metrics = Metrics::new();
+
+pub struct Histogram {
+    /// Non-cumulative counts per bucket, plus one for `+Inf`.
+    buckets: [AtomicU64; BUCKETS.len() + 1],
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS.len() + 1],
+            sum_nanos: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn count(&self) -> u64 {
+        self.buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Writes the series of the histogram. `labels` is empty or a list
+    /// of `key="value"` pairs without braces.
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let sep = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let bound = BUCKETS.get(i).map_or("+Inf".to_string(), f64::to_string);
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{{labels}{sep}le=\"{bound}\"}} {cumulative}"
+            );
+        }
+        let sum = self.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{labels}}}")
+        };
+        let _ = writeln!(out, "{name}_sum{labels} {sum}");
+        let _ = writeln!(out, "{name}_count{labels} {cumulative}");
+    }
+}
+
+pub struct Metrics {
+    /// Sightings inserted, singly or in batches.
+    pub sightings: AtomicU64,
+    /// Open client connections.
+    pub connections: AtomicU64,
+    pub connections_total: AtomicU64,
+    /// Request latency by opcode, from the opcode to the response.
+    requests: [Histogram; 256],
+    request_errors: [AtomicU64; 256],
+    /// Time spent waiting for the global map lock.
+    pub map_lock_wait: Histogram,
+    /// Time spent waiting for the lock of a /16 shard.
+    pub shard_lock_wait: Histogram,
+    pub snapshots: AtomicU64,
+    pub snapshot_duration: Histogram,
+    /// Size of the latest snapshot in bytes.
+    pub snapshot_bytes: AtomicU64,
+    /// Unix time the latest snapshot finished.
+    pub snapshot_time: AtomicU64,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            sightings: AtomicU64::new(0),
+            connections: AtomicU64::new(0),
+            connections_total: AtomicU64::new(0),
+            requests: [const { Histogram::new() }; 256],
+            request_errors: [const { AtomicU64::new(0) }; 256],
+            map_lock_wait: Histogram::new(),
+            shard_lock_wait: Histogram::new(),
+            snapshots: AtomicU64::new(0),
+            snapshot_duration: Histogram::new(),
+            snapshot_bytes: AtomicU64::new(0),
+            snapshot_time: AtomicU64::new(0),
+        }
+    }
+
+    pub fn request(&self, opcode: u8, duration: Duration, failed: bool) {
+        self.requests[opcode as usize].observe(duration);
+        if failed {
+            self.request_errors[opcode as usize].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self, duration: Duration, bytes: u64) {
+        self.snapshots.fetch_add(1, Ordering::Relaxed);
+        self.snapshot_duration.observe(duration);
+        self.snapshot_bytes.store(bytes, Ordering::Relaxed);
+        self.snapshot_time.store(unix_now(), Ordering::Relaxed);
+    }
+
+    /// Renders every metric in the Prometheus text format. Entity counts
+    /// are read from `map`.
+    pub fn render(&self, map: &Mutex<ServerMap>) -> String {
+        let mut out = String::new();
+        let counter = |out: &mut String, name: &str, help: &str, value: u64| {
+            let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter");
+            let _ = writeln!(out, "{name} {value}");
+        };
+        let gauge = |out: &mut String, name: &str, help: &str, value: u64| {
+            let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} gauge");
+            let _ = writeln!(out, "{name} {value}");
+        };
+
+        counter(
+            &mut out,
+            "mcdb_sightings_total",
+            "Sightings inserted.",
+            self.sightings.load(Ordering::Relaxed),
+        );
+        gauge(
+            &mut out,
+            "mcdb_connections",
+            "Open client connections.",
+            self.connections.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "mcdb_connections_total",
+            "Client connections accepted.",
+            self.connections_total.load(Ordering::Relaxed),
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP mcdb_request_duration_seconds Request latency by opcode.\n\
+             # TYPE mcdb_request_duration_seconds histogram"
+        );
+        for (opcode, histogram) in self.requests.iter().enumerate() {
+            if histogram.count() == 0 {
+                continue;
+            }
+            let op = protocol::op_name(opcode as u8).unwrap_or("unknown");
+            histogram.render(
+                &mut out,
+                "mcdb_request_duration_seconds",
+                &format!("op=\"{op}\",opcode=\"{opcode}\""),
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP mcdb_request_errors_total Failed requests by opcode.\n\
+             # TYPE mcdb_request_errors_total counter"
+        );
+        for (opcode, errors) in self.request_errors.iter().enumerate() {
+            let errors = errors.load(Ordering::Relaxed);
+            if errors == 0 {
+                continue;
+            }
+            let op = protocol::op_name(opcode as u8).unwrap_or("unknown");
+            let _ = writeln!(
+                out,
+                "mcdb_request_errors_total{{op=\"{op}\",opcode=\"{opcode}\"}} {errors}"
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP mcdb_lock_wait_seconds Time spent waiting for a lock.\n\
+             # TYPE mcdb_lock_wait_seconds histogram"
+        );
+        self.map_lock_wait
+            .render(&mut out, "mcdb_lock_wait_seconds", "lock=\"map\"");
+        self.shard_lock_wait
+            .render(&mut out, "mcdb_lock_wait_seconds", "lock=\"shard\"");
+
+        counter(
+            &mut out,
+            "mcdb_snapshots_total",
+            "Snapshots written.",
+            self.snapshots.load(Ordering::Relaxed),
+        );
+        let _ = writeln!(
+            out,
+            "# HELP mcdb_snapshot_duration_seconds Time to write a snapshot.\n\
+             # TYPE mcdb_snapshot_duration_seconds histogram"
+        );
+        self.snapshot_duration
+            .render(&mut out, "mcdb_snapshot_duration_seconds", "");
+        gauge(
+            &mut out,
+            "mcdb_snapshot_bytes",
+            "Size of the latest snapshot.",
+            self.snapshot_bytes.load(Ordering::Relaxed),
+        );
+        gauge(
+            &mut out,
+            "mcdb_snapshot_timestamp_seconds",
+            "Unix time the latest snapshot finished.",
+            self.snapshot_time.load(Ordering::Relaxed),
+        );
+
+        let report = self.lock(map, &self.map_lock_wait).stats(unix_now());
+        for (name, help, value) in [
+            ("mcdb_servers", "Known servers.", report.servers),
+            ("mcdb_players", "Known player records.", report.players),
+            ("mcdb_player_uuids", "Known player uuids.", report.uuids),
+            ("mcdb_links", "Server-player links.", report.links),
+            (
+                "mcdb_servers_seen_last_day",
+                "Servers sighted in the last day.",
+                report.seen_last_day,
+            ),
+        ] {
+            gauge(&mut out, name, help, value as u64);
+        }
+        out
+    }
+
+    /// Locks `mutex`, recording the wait in `histogram`.
+    pub fn lock<'a, T>(&self, mutex: &'a Mutex<T>, histogram: &Histogram) -> MutexGuard<'a, T> {
+        if let Some(guard) = mutex.try_lock() {
+            histogram.observe(Duration::ZERO);
+            return guard;
+        }
+        let start = Instant::now();
+        let guard = mutex.lock();
+        histogram.observe(start.elapsed());
+        guard
+    }
+
+    /// Locks the global map, recording the wait.
+    pub fn lock_map<'a>(&self, map: &'a Mutex<ServerMap>) -> MutexGuard<'a, ServerMap> {
+        self.lock(map, &self.map_lock_wait)
+    }
+}
+
+/// Serves `GET /metrics` over HTTP on `addr`.
+pub async fn serve(
+    addr: SocketAddr,
+    map: Arc<Mutex<ServerMap>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let listener = TcpListener::bind(addr).await?;
+    loop {
+        let (mut socket, _) = listener.accept().await?;
+        let map = map.clone();
+        tokio::spawn(async move {
+            if let Err(err) = respond(&mut socket, &map).await {
+                warn!(error = %err, "metrics request failed");
+            }
+        });
+    }
+}
+
+async fn respond(
+    socket: &mut TcpStream,
+    map: &Mutex<ServerMap>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut head = vec![];
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = socket.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..read]);
+        if head.len() > MAX_REQUEST_LEN {
+            return Err("request head too long".into());
+        }
+    }
+    let (status, body) = match request_path(&head) {
+        Some("/metrics") => ("200 OK", METRICS.render(map)),
+        Some(_) => ("404 Not Found", "not found\n".to_string()),
+        None => ("400 Bad Request", "bad request\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    );
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await?;
+    Ok(())
+}
+
+/// Path of a `GET` request, without the query string.
+fn request_path(head: &[u8]) -> Option<&str> {
+    let line = head.split(|byte| *byte == b'\r').next()?;
+    let mut parts = std::str::from_utf8(line).ok()?.split(' ');
+    if parts.next()? != "GET" {
+        return None;
+    }
+    let target = parts.next()?;
+    parts.next()?.starts_with("HTTP/").then_some(())?;
+    target.split('?').next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_histograms_and_parses_requests() {
+        let histogram = Histogram::new();
+        histogram.observe(Duration::from_micros(300));
+        histogram.observe(Duration::from_secs(60));
+        let mut out = String::new();
+        histogram.render(&mut out, "x", "op=\"insert\"");
+        assert!(out.contains("x_bucket{op=\"insert\",le=\"0.0001\"} 0\n"));
+        assert!(out.contains("x_bucket{op=\"insert\",le=\"0.0005\"} 1\n"));
+        assert!(out.contains("x_bucket{op=\"insert\",le=\"+Inf\"} 2\n"));
+        assert!(out.contains("x_count{op=\"insert\"} 2\n"));
+
+        let metrics = Metrics::new();
+        metrics.request(protocol::OP_QUERY, Duration::from_millis(2), true);
+        let map = Mutex::new(ServerMap::new());
+        let out = metrics.render(&map);
+        assert!(out.contains("mcdb_request_errors_total{op=\"query\",opcode=\"9\"} 1\n"));
+        assert!(out.contains("mcdb_servers 0\n"));
+
+        assert_eq!(
+            request_path(b"GET /metrics?x=1 HTTP/1.1\r\nHost: a\r\n\r\n"),
+            Some("/metrics")
+        );
+        assert_eq!(request_path(b"POST /metrics HTTP/1.1\r\n\r\n"), None);
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+
+use crate::server_entry::ServerId;
+
+/// Longest MOTD kept, in bytes, after formatting codes are stripped.
+pub const MAX_MOTD_LEN: usize = 1024;
+
+/// How `MotdIndex::search` matches a query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotdMatch {
+    /// The MOTD contains the query, ignoring case and formatting.
+    Substring,
+    /// Every word of the query is within `max_distance` edits of a word
+    /// of the MOTD.
+    Fuzzy { max_distance: u8 },
+}
+
+/// Inverted index over server MOTDs. Substring queries are narrowed down
+/// with character trigrams and then checked against the text; fuzzy
+/// queries look words up in a trigram index of the vocabulary.
+///
+/// The index only holds data derived from `Server::motd`, so it is
+/// persisted by the server records in a snapshot and rebuilt on load.
+#[derive(Debug, Default)]
+pub struct MotdIndex {
+    /// Normalized MOTD of every indexed server.
+    texts: HashMap<ServerId, String>,
+    /// Sorted ids of the servers whose MOTD contains each trigram.
+    trigrams: HashMap<String, Vec<ServerId>>,
+    /// Sorted ids of the servers whose MOTD contains each word.
+    words: HashMap<String, Vec<ServerId>>,
+    /// Every word containing each trigram, for fuzzy lookups.
+    word_trigrams: HashMap<String, Vec<String>>,
+}
+
+impl MotdIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the indexed MOTD of `server_id`. An empty MOTD removes
+    /// the server from the index.
+    pub fn set(&mut self, server_id: ServerId, motd: &str) {
+        let text = normalize(motd);
+        if self.texts.get(&server_id) == Some(&text) {
+            return;
+        }
+        if let Some(old) = self.texts.remove(&server_id) {
+            for trigram in trigrams(&old) {
+                remove_posting(&mut self.trigrams, &trigram, server_id);
+            }
+            for word in words(&old) {
+                if remove_posting(&mut self.words, &word, server_id) {
+                    for trigram in trigrams(&pad(&word)) {
+                        if let Some(words) = self.word_trigrams.get_mut(&trigram) {
+                            words.retain(|other| *other != word);
+                            if words.is_empty() {
+                                self.word_trigrams.remove(&trigram);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        if text.is_empty() {
+            return;
+        }
+        for trigram in trigrams(&text) {
+            add_posting(&mut self.trigrams, trigram, server_id);
+        }
+        for word in words(&text) {
+            if !self.words.contains_key(&word) {
+                for trigram in trigrams(&pad(&word)) {
+                    self.word_trigrams
+                        .entry(trigram)
+                        .or_default()
+                        .push(word.clone());
+                }
+            }
+            add_posting(&mut self.words, word, server_id);
+        }
+        self.texts.insert(server_id, text);
+    }
+
+    /// Ids of the servers whose MOTD matches `query`, in ascending order.
+    pub fn search(&self, query: &str, mode: MotdMatch) -> Vec<ServerId> {
+        let query = normalize(query);
+        if query.is_empty() {
+            return vec![];
+        }
+        match mode {
+            MotdMatch::Substring => self.search_substring(&query),
+            MotdMatch::Fuzzy { max_distance } => self.search_fuzzy(&query, max_distance as usize),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.texts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.texts.is_empty()
+    }
+
+    fn search_substring(&self, query: &str) -> Vec<ServerId> {
+        let query_trigrams: Vec<String> = trigrams(query).collect();
+        let candidates = if query_trigrams.is_empty() {
+            // too short for trigrams, check every MOTD
+            let mut all: Vec<ServerId> = self.texts.keys().copied().collect();
+            all.sort_unstable();
+            all
+        } else {
+            let mut postings = vec![];
+            for trigram in &query_trigrams {
+                match self.trigrams.get(trigram) {
+                    Some(posting) => postings.push(posting.as_slice()),
+                    None => return vec![],
+                }
+            }
+            intersect(postings)
+        };
+        candidates
+            .into_iter()
+            .filter(|server_id| self.texts[server_id].contains(query))
+            .collect()
+    }
+
+    fn search_fuzzy(&self, query: &str, max_distance: usize) -> Vec<ServerId> {
+        let mut res: Option<Vec<ServerId>> = None;
+        for query_word in words(query) {
+            let mut matches: Vec<ServerId> = vec![];
+            for word in self.similar_words(&query_word, max_distance) {
+                matches.extend(&self.words[&word]);
+            }
+            matches.sort_unstable();
+            matches.dedup();
+            res = Some(match res {
+                Some(res) => intersect(vec![&res, &matches]),
+                None => matches,
+            });
+        }
+        res.unwrap_or_default()
+    }
+
+    /// Words of the vocabulary within `max_distance` edits of `query`.
+    /// A padded word of n letters has n + 1 trigrams and each edit
+    /// changes at most 3 of them, so when n + 1 > 3 * max_distance every
+    /// match shares a trigram with `query`; shorter queries scan the
+    /// whole vocabulary.
+    fn similar_words(&self, query: &str, max_distance: usize) -> Vec<String> {
+        let query_len = query.chars().count();
+        let candidates: Vec<&String> = if query_len < 3 * max_distance {
+            self.words.keys().collect()
+        } else {
+            let mut candidates: Vec<&String> = trigrams(&pad(query))
+                .filter_map(|trigram| self.word_trigrams.get(&trigram))
+                .flatten()
+                .collect();
+            candidates.sort_unstable();
+            candidates.dedup();
+            candidates
+        };
+        candidates
+            .into_iter()
+            .filter(|word| word.chars().count().abs_diff(query_len) <= max_distance)
+            .filter(|word| edit_distance(query, word) <= max_distance)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Removes `§` formatting codes, e.g. `§6§lSky§rblock` becomes `Skyblock`.
+pub fn strip_formatting(motd: &str) -> String {
+    let mut res = String::with_capacity(motd.len());
+    let mut chars = motd.chars();
+    while let Some(c) = chars.next() {
+        if c == '§' {
+            chars.next();
+        } else {
+            res.push(c);
+        }
+    }
+    res
+}
+
+/// Strips formatting, lowercases and collapses whitespace.
+pub fn normalize(motd: &str) -> String {
+    strip_formatting(motd)
+        .to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+fn words(text: &str) -> impl Iterator<Item = String> {
+    let mut words: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect();
+    words.sort_unstable();
+    words.dedup();
+    words.into_iter()
+}
+
+/// Distinct character trigrams of `text`.
+fn trigrams(text: &str) -> impl Iterator<Item = String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut res: Vec<String> = chars
+        .windows(3)
+        .map(|window| window.iter().collect())
+        .collect();
+    res.sort_unstable();
+    res.dedup();
+    res.into_iter()
+}
+
+/// Marks the word boundaries so short words still have trigrams.
+fn pad(word: &str) -> String {
+    format!("  {word} ")
+}
+
+fn add_posting(index: &mut HashMap<String, Vec<ServerId>>, key: String, server_id: ServerId) {
+    let posting = index.entry(key).or_default();
+    if let Err(i) = posting.binary_search(&server_id) {
+        posting.insert(i, server_id);
+    }
+}
+
+/// Returns `true` if the posting of `key` became empty and was removed.
+fn remove_posting(
+    index: &mut HashMap<String, Vec<ServerId>>,
+    key: &str,
+    server_id: ServerId,
+) -> bool {
+    let Some(posting) = index.get_mut(key) else {
+        return false;
+    };
+    if let Ok(i) = posting.binary_search(&server_id) {
+        posting.remove(i);
+    }
+    if posting.is_empty() {
+        index.remove(key);
+        return true;
+    }
+    false
+}
+
+/// Intersects sorted postings, starting from the shortest.
+fn intersect(mut postings: Vec<&[ServerId]>) -> Vec<ServerId> {
+    postings.sort_by_key(|posting| posting.len());
+    let Some((first, rest)) = postings.split_first() else {
+        return vec![];
+    };
+    first
+        .iter()
+        .copied()
+        .filter(|server_id| {
+            rest.iter()
+                .all(|posting| posting.binary_search(server_id).is_ok())
+        })
+        .collect()
+}
+
+/// Levenshtein distance between `a` and `b`, in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, a_char) in a.chars().enumerate() {
+        cur[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(a_char != *b_char);
+            cur[j + 1] = substitution.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substring_and_fuzzy_search() {
+        let mut index = MotdIndex::new();
+        index.set(0, "§6§lSky§rblock §7| Season 3");
+        index.set(1, "A vanilla survival server");
+        index.set(2, "SkyWars and Bedwars");
+
+        assert_eq!(strip_formatting("§6§lSky§rblock"), "Skyblock");
+        assert_eq!(index.search("SKYBLOCK", MotdMatch::Substring), vec![0]);
+        assert_eq!(index.search("sky", MotdMatch::Substring), vec![0, 2]);
+        assert_eq!(index.search("wa", MotdMatch::Substring), vec![2]);
+        assert!(index.search("creative", MotdMatch::Substring).is_empty());
+
+        let fuzzy = MotdMatch::Fuzzy { max_distance: 1 };
+        assert_eq!(index.search("skyblok", fuzzy), vec![0]);
+        assert_eq!(index.search("survivl servr", fuzzy), vec![1]);
+        assert!(index.search("survivl skyblok", fuzzy).is_empty());
+
+        index.set(0, "Creative plots");
+        assert!(index.search("skyblock", MotdMatch::Substring).is_empty());
+        assert_eq!(index.search("creative", MotdMatch::Substring), vec![0]);
+        index.set(0, "");
+        assert!(index.search("creative", MotdMatch::Substring).is_empty());
+        assert_eq!(index.len(), 2);
+        assert!(index.words.keys().all(|word| !word.starts_with("creat")));
+
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+}
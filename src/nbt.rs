@@ -0,0 +1,261 @@
+use std::error::Error;
+use std::io::{Read, Write};
+
+/// Deepest nesting of lists and compounds accepted by `Tag::read`.
+const MAX_DEPTH: usize = 512;
+
+/// An uncompressed, big-endian NBT tag as written by the Minecraft client.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    /// Element type id and elements. Empty lists keep their type id.
+    List(u8, Vec<Tag>),
+    Compound(Vec<(String, Tag)>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Tag {
+    pub fn id(&self) -> u8 {
+        match self {
+            Tag::Byte(_) => 1,
+            Tag::Short(_) => 2,
+            Tag::Int(_) => 3,
+            Tag::Long(_) => 4,
+            Tag::Float(_) => 5,
+            Tag::Double(_) => 6,
+            Tag::ByteArray(_) => 7,
+            Tag::String(_) => 8,
+            Tag::List(_, _) => 9,
+            Tag::Compound(_) => 10,
+            Tag::IntArray(_) => 11,
+            Tag::LongArray(_) => 12,
+        }
+    }
+
+    /// Looks up `name` if this is a compound.
+    pub fn get(&self, name: &str) -> Option<&Tag> {
+        match self {
+            Tag::Compound(entries) => entries
+                .iter()
+                .find(|(entry_name, _)| entry_name == name)
+                .map(|(_, tag)| tag),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Tag::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Reads a named root tag, returning its name and value.
+    pub fn read_root(buf: &mut impl Read) -> Result<(String, Tag), Box<dyn Error + Send + Sync>> {
+        let id = read_u8(buf)?;
+        if id == 0 {
+            return Err("NBT root is an end tag".into());
+        }
+        let name = read_string(buf)?;
+        Ok((name, Tag::read(buf, id, 0)?))
+    }
+
+    /// Writes this tag as a named root tag.
+    pub fn write_root(
+        &self,
+        buf: &mut impl Write,
+        name: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        buf.write_all(&[self.id()])?;
+        write_string(buf, name)?;
+        self.write(buf)
+    }
+
+    fn read(
+        buf: &mut impl Read,
+        id: u8,
+        depth: usize,
+    ) -> Result<Tag, Box<dyn Error + Send + Sync>> {
+        if depth > MAX_DEPTH {
+            return Err("NBT is nested too deeply".into());
+        }
+        Ok(match id {
+            1 => Tag::Byte(read_u8(buf)? as i8),
+            2 => Tag::Short(i16::from_be_bytes(read_array(buf)?)),
+            3 => Tag::Int(i32::from_be_bytes(read_array(buf)?)),
+            4 => Tag::Long(i64::from_be_bytes(read_array(buf)?)),
+            5 => Tag::Float(f32::from_be_bytes(read_array(buf)?)),
+            6 => Tag::Double(f64::from_be_bytes(read_array(buf)?)),
+            7 => {
+                let len = read_len(buf)?;
+                let mut bytes = vec![0u8; len];
+                buf.read_exact(&mut bytes)?;
+                Tag::ByteArray(bytes.into_iter().map(|byte| byte as i8).collect())
+            }
+            8 => Tag::String(read_string(buf)?),
+            9 => {
+                let element_id = read_u8(buf)?;
+                let len = read_len(buf)?;
+                let mut elements = vec![];
+                for _ in 0..len {
+                    elements.push(Tag::read(buf, element_id, depth + 1)?);
+                }
+                Tag::List(element_id, elements)
+            }
+            10 => {
+                let mut entries = vec![];
+                loop {
+                    let entry_id = read_u8(buf)?;
+                    if entry_id == 0 {
+                        break;
+                    }
+                    let name = read_string(buf)?;
+                    entries.push((name, Tag::read(buf, entry_id, depth + 1)?));
+                }
+                Tag::Compound(entries)
+            }
+            11 => {
+                let len = read_len(buf)?;
+                let mut values = vec![];
+                for _ in 0..len {
+                    values.push(i32::from_be_bytes(read_array(buf)?));
+                }
+                Tag::IntArray(values)
+            }
+            12 => {
+                let len = read_len(buf)?;
+                let mut values = vec![];
+                for _ in 0..len {
+                    values.push(i64::from_be_bytes(read_array(buf)?));
+                }
+                Tag::LongArray(values)
+            }
+            _ => return Err(format!("unknown NBT tag id {id}").into()),
+        })
+    }
+
+    fn write(&self, buf: &mut impl Write) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match self {
+            Tag::Byte(value) => buf.write_all(&value.to_be_bytes())?,
+            Tag::Short(value) => buf.write_all(&value.to_be_bytes())?,
+            Tag::Int(value) => buf.write_all(&value.to_be_bytes())?,
+            Tag::Long(value) => buf.write_all(&value.to_be_bytes())?,
+            Tag::Float(value) => buf.write_all(&value.to_be_bytes())?,
+            Tag::Double(value) => buf.write_all(&value.to_be_bytes())?,
+            Tag::ByteArray(values) => {
+                write_len(buf, values.len())?;
+                let bytes: Vec<u8> = values.iter().map(|value| *value as u8).collect();
+                buf.write_all(&bytes)?;
+            }
+            Tag::String(value) => write_string(buf, value)?,
+            Tag::List(element_id, elements) => {
+                buf.write_all(&[*element_id])?;
+                write_len(buf, elements.len())?;
+                for element in elements {
+                    if element.id() != *element_id {
+                        return Err("NBT list elements must share a type".into());
+                    }
+                    element.write(buf)?;
+                }
+            }
+            Tag::Compound(entries) => {
+                for (name, tag) in entries {
+                    buf.write_all(&[tag.id()])?;
+                    write_string(buf, name)?;
+                    tag.write(buf)?;
+                }
+                buf.write_all(&[0])?;
+            }
+            Tag::IntArray(values) => {
+                write_len(buf, values.len())?;
+                for value in values {
+                    buf.write_all(&value.to_be_bytes())?;
+                }
+            }
+            Tag::LongArray(values) => {
+                write_len(buf, values.len())?;
+                for value in values {
+                    buf.write_all(&value.to_be_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read_u8(buf: &mut impl Read) -> Result<u8, Box<dyn Error + Send + Sync>> {
+    Ok(read_array::<1>(buf)?[0])
+}
+
+fn read_array<const N: usize>(
+    buf: &mut impl Read,
+) -> Result<[u8; N], Box<dyn Error + Send + Sync>> {
+    let mut res = [0u8; N];
+    buf.read_exact(&mut res)?;
+    Ok(res)
+}
+
+fn read_len(buf: &mut impl Read) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    let len = i32::from_be_bytes(read_array(buf)?);
+    usize::try_from(len).map_err(|_| format!("negative NBT length {len}").into())
+}
+
+fn write_len(buf: &mut impl Write, len: usize) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let len = i32::try_from(len).map_err(|_| "NBT array is too long")?;
+    buf.write_all(&len.to_be_bytes())?;
+    Ok(())
+}
+
+/// NBT strings are Java's modified UTF-8, which only differs from UTF-8
+/// for NUL and characters outside the BMP; those are read lossily.
+fn read_string(buf: &mut impl Read) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let len = u16::from_be_bytes(read_array(buf)?) as usize;
+    let mut bytes = vec![0u8; len];
+    buf.read_exact(&mut bytes)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn write_string(buf: &mut impl Write, value: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let len = u16::try_from(value.len()).map_err(|_| "NBT string is too long")?;
+    buf.write_all(&len.to_be_bytes())?;
+    buf.write_all(value.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let root = Tag::Compound(vec![
+            (
+                "servers".to_string(),
+                Tag::List(
+                    10,
+                    vec![Tag::Compound(vec![
+                        ("ip".to_string(), Tag::String("1.2.3.4".to_string())),
+                        ("name".to_string(), Tag::String("A server".to_string())),
+                        ("hidden".to_string(), Tag::Byte(0)),
+                    ])],
+                ),
+            ),
+            ("empty".to_string(), Tag::List(0, vec![])),
+            ("numbers".to_string(), Tag::LongArray(vec![-1, 2])),
+        ]);
+        let mut buf = vec![];
+        root.write_root(&mut buf, "").unwrap();
+        let (name, read) = Tag::read_root(&mut &buf[..]).unwrap();
+        assert_eq!(name, "");
+        assert_eq!(read, root);
+        assert!(Tag::read_root(&mut &buf[..buf.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::{SocketAddr, SocketAddrV4};
+
+use crate::attributes::{AttrValue, Attribute};
+use crate::graph::DisjointSet;
+use crate::motd;
+use crate::player_entry::PlayerId;
+use crate::server_entry::ServerId;
+use crate::server_map::ServerMap;
+
+/// Thresholds of `detect_networks`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NetworkOptions {
+    /// Servers in different /24s are linked when they share at least
+    /// this many players...
+    pub min_shared_players: usize,
+    /// ...making up at least this fraction of the smaller player base.
+    pub min_overlap: f64,
+    /// Players seen on more servers than this are ignored, as they are
+    /// more likely bots or scanners than regulars of a network.
+    pub max_player_servers: usize,
+    /// An MOTD or favicon found on more servers than this is taken to be
+    /// a default, e.g. "A Minecraft Server", and only links servers in
+    /// the same /24.
+    pub max_fingerprint_servers: usize,
+}
+
+impl Default for NetworkOptions {
+    fn default() -> Self {
+        Self {
+            min_shared_players: 3,
+            min_overlap: 0.2,
+            max_player_servers: 50,
+            max_fingerprint_servers: 64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Fingerprint {
+    Motd(String),
+    Favicon(String),
+}
+
+/// What `detect_networks` reads of a server.
+#[derive(Debug, Clone)]
+struct GraphServer {
+    addr: SocketAddrV4,
+    motd: String,
+    favicon: Option<String>,
+    players: Vec<PlayerId>,
+}
+
+/// The part of the map `detect_networks` reads, copied so networks can be
+/// detected without holding the map lock.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkGraph {
+    servers: Vec<GraphServer>,
+    /// Servers of each player, by player id.
+    player_servers: Vec<Vec<ServerId>>,
+}
+
+impl NetworkGraph {
+    pub fn new(map: &ServerMap) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut servers = Vec::with_capacity(map.servers.len());
+        for server in &map.servers {
+            let server = server.lock();
+            let addr = match server.addr {
+                SocketAddr::V4(addr) => addr,
+                SocketAddr::V6(_) => return Err("Not an IPv4 Address".into()),
+            };
+            let favicon = match server.attributes.get(&Attribute::FaviconHash) {
+                Some(AttrValue::Str(hash)) => Some(hash.clone()),
+                _ => None,
+            };
+            servers.push(GraphServer {
+                addr,
+                motd: server.motd.clone(),
+                favicon,
+                players: server.players.clone(),
+            });
+        }
+        let player_servers = map
+            .players
+            .iter()
+            .map(|player| player.lock().servers.clone())
+            .collect();
+        Ok(Self {
+            servers,
+            player_servers,
+        })
+    }
+}
+
+/// A detected network and its servers, in ascending address order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Network {
+    pub id: i64,
+    pub servers: Vec<SocketAddr>,
+}
+
+/// Id of the network whose lowest address is `addr`: the IPv4 address
+/// and port packed as `ip << 16 | port`. Unlike server ids, this survives
+/// deletes and restarts.
+pub fn network_id(addr: SocketAddrV4) -> i64 {
+    (u32::from(*addr.ip()) as i64) << 16 | addr.port() as i64
+}
+
+/// Clusters servers into networks. Two servers are linked when
+///
+/// - they are in the same /24 and share a player, an MOTD or a favicon,
+/// - they share enough players, see `NetworkOptions`, or
+/// - they share an MOTD or favicon few other servers have.
+///
+/// A network is a set of two or more linked servers, and its id is the
+/// `network_id` of its lowest address. Returns every network, largest
+/// first.
+pub fn detect_networks(graph: &NetworkGraph, options: &NetworkOptions) -> Vec<Network> {
+    let num_servers = graph.servers.len();
+    let bucket = |server_id: usize| u32::from(*graph.servers[server_id].addr.ip()) >> 8;
+    let mut fingerprints: HashMap<Fingerprint, Vec<ServerId>> = HashMap::new();
+    for (server_id, server) in graph.servers.iter().enumerate() {
+        let motd = motd::normalize(&server.motd);
+        if !motd.is_empty() {
+            fingerprints
+                .entry(Fingerprint::Motd(motd))
+                .or_default()
+                .push(server_id as ServerId);
+        }
+        if let Some(hash) = &server.favicon {
+            fingerprints
+                .entry(Fingerprint::Favicon(hash.clone()))
+                .or_default()
+                .push(server_id as ServerId);
+        }
+    }
+
+    let mut sets = DisjointSet::new(num_servers);
+    for server_ids in fingerprints.values() {
+        let rare = server_ids.len() <= options.max_fingerprint_servers;
+        let mut firsts: HashMap<u32, ServerId> = HashMap::new();
+        for server_id in server_ids {
+            let bucket = if rare { 0 } else { bucket(*server_id as usize) };
+            let first = *firsts.entry(bucket).or_insert(*server_id);
+            sets.union(first as usize, *server_id as usize);
+        }
+    }
+
+    for (server_id, server) in graph.servers.iter().enumerate() {
+        let mut shared: HashMap<ServerId, usize> = HashMap::new();
+        for player_id in &server.players {
+            let player_servers = &graph.player_servers[*player_id as usize];
+            if player_servers.len() > options.max_player_servers {
+                continue;
+            }
+            for other in player_servers {
+                if *other as usize > server_id {
+                    *shared.entry(*other).or_default() += 1;
+                }
+            }
+        }
+        for (other, shared) in shared {
+            let linked = bucket(server_id) == bucket(other as usize) || {
+                let smaller = server
+                    .players
+                    .len()
+                    .min(graph.servers[other as usize].players.len());
+                shared >= options.min_shared_players
+                    && shared as f64 >= options.min_overlap * smaller as f64
+            };
+            if linked {
+                sets.union(server_id, other as usize);
+            }
+        }
+    }
+
+    let mut networks: HashMap<usize, Vec<SocketAddrV4>> = HashMap::new();
+    for (server_id, server) in graph.servers.iter().enumerate() {
+        networks
+            .entry(sets.find(server_id))
+            .or_default()
+            .push(server.addr);
+    }
+    let mut res: Vec<Network> = networks
+        .into_values()
+        .filter(|addrs| addrs.len() >= 2)
+        .map(|mut addrs| {
+            addrs.sort_unstable();
+            Network {
+                id: network_id(addrs[0]),
+                servers: addrs.into_iter().map(SocketAddr::V4).collect(),
+            }
+        })
+        .collect();
+    res.sort_by(|a, b| b.servers.len().cmp(&a.servers.len()).then(a.id.cmp(&b.id)));
+    res
+}
+
+/// Stores each server's network in `Attribute::Network`. Servers outside
+/// any of `networks` lose the attribute, including ones added since they
+/// were detected.
+pub fn store_networks(
+    map: &mut ServerMap,
+    networks: &[Network],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let ids: HashMap<SocketAddr, i64> = networks
+        .iter()
+        .flat_map(|network| network.servers.iter().map(|addr| (*addr, network.id)))
+        .collect();
+    for server_id in 0..map.servers.len() {
+        let id = ids.get(&map.servers[server_id].addr());
+        map.set_attribute(
+            server_id as ServerId,
+            Attribute::Network,
+            id.map(|id| AttrValue::Int(*id)),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::attributes::AttrPredicate;
+    use crate::player_entry::Player;
+
+    #[test]
+    fn links_shared_players_fingerprints_and_neighbours() {
+        let mut map = ServerMap::new();
+        let players = |ids: std::ops::Range<u128>| {
+            ids.map(|i| Player::new(format!("p{i}"), Uuid::from_u128(i)))
+                .collect::<Vec<Player>>()
+        };
+        // 0 and 1: far apart, sharing most players
+        map.insert("10.0.0.1:25565".parse().unwrap(), players(0..5))
+            .unwrap();
+        map.insert("10.9.0.1:25565".parse().unwrap(), players(1..6))
+            .unwrap();
+        // 2 and 3: same /24 and one shared player
+        map.insert("10.1.1.1:25565".parse().unwrap(), players(10..11))
+            .unwrap();
+        map.insert("10.1.1.200:25566".parse().unwrap(), players(10..12))
+            .unwrap();
+        // 4 and 5: same favicon
+        map.insert_server("10.2.0.1:25565".parse().unwrap())
+            .unwrap();
+        map.insert_server("10.3.0.1:25565".parse().unwrap())
+            .unwrap();
+        for server_id in [4, 5] {
+            map.set_attribute(
+                server_id,
+                Attribute::FaviconHash,
+                Some(AttrValue::Str("abc".to_string())),
+            )
+            .unwrap();
+        }
+        // 6, 7 and 8: a default MOTD shared by too many servers
+        for addr in ["10.4.0.1:25565", "10.5.0.1:25565", "10.7.0.1:25565"] {
+            map.set_motd(addr.parse().unwrap(), "A Minecraft Server")
+                .unwrap();
+        }
+        // 9: one shared player with 0 is not enough
+        map.insert("10.6.0.1:25565".parse().unwrap(), players(0..1))
+            .unwrap();
+
+        let options = NetworkOptions {
+            max_fingerprint_servers: 2,
+            ..NetworkOptions::default()
+        };
+        let detect = |map: &mut ServerMap| {
+            let networks = detect_networks(&NetworkGraph::new(map).unwrap(), &options);
+            store_networks(map, &networks).unwrap();
+            networks
+        };
+        let id = |addr: &str| network_id(addr.parse().unwrap());
+        let networks: Vec<(i64, Vec<String>)> = detect(&mut map)
+            .into_iter()
+            .map(|network| {
+                let addrs = network.servers.iter().map(|addr| addr.to_string());
+                (network.id, addrs.collect())
+            })
+            .collect();
+        assert_eq!(
+            networks,
+            vec![
+                (
+                    id("10.0.0.1:25565"),
+                    vec!["10.0.0.1:25565".to_string(), "10.9.0.1:25565".to_string()]
+                ),
+                (
+                    id("10.1.1.1:25565"),
+                    vec!["10.1.1.1:25565".to_string(), "10.1.1.200:25566".to_string()]
+                ),
+                (
+                    id("10.2.0.1:25565"),
+                    vec!["10.2.0.1:25565".to_string(), "10.3.0.1:25565".to_string()]
+                ),
+            ]
+        );
+        let network = AttrPredicate::Eq(AttrValue::Int(id("10.1.1.1:25565")));
+        assert_eq!(
+            map.find_by_attribute(Attribute::Network, &network).unwrap(),
+            vec![2, 3]
+        );
+        assert!(!map.servers[9]
+            .lock()
+            .attributes
+            .contains_key(&Attribute::Network));
+
+        // deleting a server renumbers others, but network ids stay put
+        map.delete_server("10.0.0.1:25565".parse().unwrap())
+            .unwrap();
+        assert_eq!(detect(&mut map).len(), 2);
+        let mut servers = map.find_by_attribute(Attribute::Network, &network).unwrap();
+        servers.sort_unstable();
+        let addrs: Vec<String> = servers
+            .iter()
+            .map(|server_id| map.servers[*server_id as usize].addr().to_string())
+            .collect();
+        assert_eq!(addrs, ["10.1.1.1:25565", "10.1.1.200:25566"]);
+    }
+}
@@ -1,43 +1,55 @@
 use std::{
     cmp::Ordering,
-    collections::BTreeSet,
     error::Error,
     io::{Read, Write},
+    net::SocketAddr,
     sync::Arc,
 };
 
 use integer_encoding::{VarIntReader, VarIntWriter};
 use uuid::Uuid;
 
-use crate::server_entry::ServerArcWrapper;
+use crate::server_entry::{read_string, Server, ServerArcWrapper, ServerId};
 
-#[derive(Debug)]
+/// Index of a player in `ServerMap::players`.
+pub type PlayerId = u32;
+
+#[derive(Debug, Clone)]
 pub struct Player {
     pub name: String,
     pub uuid: Uuid,
-    pub servers: BTreeSet<ServerArcWrapper>,
+    /// Sorted ids of every server this player was seen on.
+    pub servers: Vec<ServerId>,
 }
 
 impl Player {
-    pub fn deserialize_pointer(mut buf: &[u8]) -> Result<Self, Box<dyn Error + Send + Sync>> {
-        let name_len = buf.read_varint()?;
-        let mut name = vec![0u8; name_len];
-        buf.read_exact(&mut name)?;
-        let name_string = std::str::from_utf8(&name);
-        match name_string {
-            Ok(name) => {
-                let mut uuid_buf = [0u8; 16];
-                buf.read_exact(&mut uuid_buf)?;
-                let uuid = Uuid::from_bytes(uuid_buf);
-                let servers: BTreeSet<ServerArcWrapper> = BTreeSet::new();
-                Ok(Player {
-                    name: name.to_string(),
-                    uuid,
-                    servers,
-                })
-            }
-            Err(err) => Err(Box::new(err)),
+    pub fn new(name: String, uuid: Uuid) -> Self {
+        Player {
+            name,
+            uuid,
+            servers: vec![],
+        }
+    }
+
+    pub fn deserialize_pointer(buf: &mut impl Read) -> Result<Uuid, Box<dyn Error + Send + Sync>> {
+        let mut uuid_buf = [0u8; 16];
+        buf.read_exact(&mut uuid_buf)?;
+        Ok(Uuid::from_bytes(uuid_buf))
+    }
+
+    /// Reads a player record, returning the player (with no servers) and
+    /// the addresses of its servers. The addresses are resolved by the loader.
+    pub fn deserialize(
+        buf: &mut impl Read,
+    ) -> Result<(Player, Vec<SocketAddr>), Box<dyn Error + Send + Sync>> {
+        let name = read_string(buf)?;
+        let uuid = Player::deserialize_pointer(buf)?;
+        let num_servers: usize = buf.read_varint()?;
+        let mut servers = Vec::with_capacity(num_servers.min(64));
+        for _ in 0..num_servers {
+            servers.push(Server::deserialize_pointer(buf)?);
         }
+        Ok((Player::new(name, uuid), servers))
     }
 
     /*--- Player ---------------------------------------|
@@ -47,21 +59,23 @@ impl Player {
     | player name   | string            | variable size |
     | player uuid   | uuid              | 16 bytes      |
     | num servers   | varint            | variable size |
-    | server list   | ServerPointer[]   | variable size |
+    | server list   | ServerPointer[]   | 6 bytes each  |
     |--------------------------------------------------*/
-    pub fn serialize(&self) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    pub fn serialize(
+        &self,
+        servers: &[ServerArcWrapper],
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
         let mut res = vec![];
         let name_bytes = self.name.as_bytes();
         res.write_varint(name_bytes.len())?;
         res.write_all(name_bytes)?;
-        let uuid_bytes: &[u8; 16] = self.uuid.as_bytes();
-        res.write_all(uuid_bytes)?;
+        res.write_all(&self.serialize_pointer()?)?;
         res.write_varint(self.servers.len())?;
-        let player_servers = self.servers.iter();
-        for server in player_servers {
-            let server_bytes = server.lock().serialize_pointer()?;
-            res.write_varint(server_bytes.len())?;
-            res.write_all(&server_bytes)?;
+        for server_id in &self.servers {
+            let server = servers
+                .get(*server_id as usize)
+                .ok_or_else(|| format!("player {} has unknown server id {server_id}", self.uuid))?;
+            res.write_all(&server.serialize_pointer()?)?;
         }
         Ok(res)
     }
@@ -69,34 +83,19 @@ impl Player {
     /*--- Player Pointer -----------------------|
     | field name    | type      | size          |
     |-------------------------------------------|
-    | name length   | varint    | variable size |
-    | player name   | string    | variable size |
     | player uuid   | uuid      | 16 bytes      |
     |------------------------------------------*/
     pub fn serialize_pointer(&self) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
-        let mut res = vec![];
-        let name_bytes = self.name.as_bytes();
-        res.write_varint(name_bytes.len())?;
-        res.write_all(name_bytes)?;
-        res.write_all(self.uuid.as_bytes())?;
-        Ok(res)
+        Ok(self.uuid.as_bytes().to_vec())
     }
 
-    pub fn update(&mut self, other: &Player) {
-        // println!(
-        //     "[Player] Merging self '{:?}' with other '{:?}'",
-        //     self, other
-        // );
-        for server in &other.servers {
-            let take = self.servers.take(server);
-            match take {
-                Some(mut_server) => {
-                    mut_server.lock().update(&server.lock());
-                    self.servers.insert(mut_server);
-                }
-                None => {
-                    self.servers.insert(server.clone());
-                }
+    /// Links a server to this player, returning `false` if it was already linked.
+    pub fn add_server(&mut self, server: ServerId) -> bool {
+        match self.servers.binary_search(&server) {
+            Ok(_) => false,
+            Err(index) => {
+                self.servers.insert(index, server);
+                true
             }
         }
     }
@@ -104,11 +103,7 @@ impl Player {
 
 impl PartialOrd for Player {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        let mut cmp = self.name.cmp(&other.name);
-        if cmp == Ordering::Equal {
-            cmp = self.uuid.cmp(&other.uuid);
-        }
-        Some(cmp)
+        Some(self.cmp(other))
     }
 }
 
@@ -130,44 +125,92 @@ impl PartialEq for Player {
 
 impl Eq for Player {}
 
-impl Clone for Player {
-    fn clone(&self) -> Self {
-        Player {
-            name: self.name.clone(),
-            uuid: self.uuid,
-            servers: BTreeSet::new(),
-        }
-    }
-}
-
+/// Shared handle to a player. Comparisons use the name and uuid captured
+/// at construction, in the same order as `Player`'s, so they never take
+/// the lock; neither may be changed on the inner player afterwards.
 #[derive(Debug, Clone)]
-pub struct PlayerArcWrapper(Arc<parking_lot::Mutex<Player>>);
+pub struct PlayerArcWrapper {
+    name: String,
+    uuid: Uuid,
+    inner: Arc<parking_lot::Mutex<Player>>,
+}
 
 impl PlayerArcWrapper {
     pub fn new(player: Player) -> Self {
-        Self(Arc::new(parking_lot::Mutex::new(player)))
+        Self {
+            name: player.name.clone(),
+            uuid: player.uuid,
+            inner: Arc::new(parking_lot::Mutex::new(player)),
+        }
+    }
+    pub fn uuid(&self) -> Uuid {
+        self.uuid
+    }
+    /// Same as `Player::serialize_pointer`, without locking the player.
+    pub fn serialize_pointer(&self) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        Ok(self.uuid.as_bytes().to_vec())
     }
     pub fn lock(&self) -> parking_lot::lock_api::MutexGuard<'_, parking_lot::RawMutex, Player> {
-        self.0.lock()
+        self.inner.lock()
     }
 }
 
 impl PartialOrd for PlayerArcWrapper {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.0.lock().partial_cmp(&other.0.lock())
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for PlayerArcWrapper {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.0.lock().cmp(&other.0.lock())
+        let mut cmp = self.name.cmp(&other.name);
+        if cmp == Ordering::Equal {
+            cmp = self.uuid.cmp(&other.uuid);
+        }
+        cmp
     }
 }
 
 impl PartialEq for PlayerArcWrapper {
     fn eq(&self, other: &Self) -> bool {
-        self.0.lock().eq(&other.0.lock())
+        self.name == other.name && self.uuid == other.uuid
     }
 }
 
 impl Eq for PlayerArcWrapper {}
+
+#[cfg(test)]
+mod tests {
+    use integer_encoding::VarIntWriter;
+
+    use super::*;
+    use crate::server_map::ServerMap;
+
+    #[test]
+    fn pointer_records_round_trip() {
+        let mut map = ServerMap::new();
+        let addr: SocketAddr = "10.0.0.1:25565".parse().unwrap();
+        let uuid = Uuid::from_u128(1);
+        map.insert(addr, [Player::new("alice".to_string(), uuid)])
+            .unwrap();
+
+        let player = map.players[0].lock().serialize(&map.servers).unwrap();
+        // name, uuid, then one 6 byte server pointer
+        assert_eq!(player.len(), 1 + 5 + 16 + 1 + 6);
+        let (read, servers) = Player::deserialize(&mut &player[..]).unwrap();
+        assert_eq!((read.name.as_str(), read.uuid), ("alice", uuid));
+        assert_eq!(servers, [addr]);
+
+        let server = map.servers[0].lock().serialize(&map.players).unwrap();
+        let (read, uuids) = Server::deserialize(&mut &server[..]).unwrap();
+        assert_eq!((read.addr, uuids), (addr, vec![uuid]));
+
+        // counts read from disk are not trusted for allocation
+        let mut corrupt = vec![];
+        corrupt.write_varint(u64::MAX >> 1).unwrap();
+        assert!(Player::deserialize(&mut &corrupt[..]).is_err());
+        let mut corrupt = player[..22].to_vec();
+        corrupt.write_varint(u64::MAX >> 1).unwrap();
+        assert!(Player::deserialize(&mut &corrupt[..]).is_err());
+    }
+}
@@ -0,0 +1,1710 @@
+use std::{
+    error::Error,
+    io::{Read, Write},
+    net::SocketAddr,
+};
+
+use integer_encoding::{VarIntAsyncReader, VarIntAsyncWriter, VarIntReader, VarIntWriter};
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use uuid::Uuid;
+
+use crate::attributes::{AttrKind, AttrPredicate, AttrValue, Attribute, MAX_ATTR_STR_LEN};
+use crate::cidr::Cidr;
+use crate::cursor::Cursor;
+use crate::graph::{Component, Related, TimeWindow, MAX_DEPTH};
+use crate::hostname::{Resolution, MAX_HOSTNAME_LEN};
+use crate::motd::{MotdMatch, MAX_MOTD_LEN};
+use crate::network::Network;
+use crate::player_entry::{Player, PlayerArcWrapper, PlayerId};
+use crate::query::MAX_QUERY_LEN;
+use crate::recent::RecentInsert;
+use crate::server_entry::{Server, ServerArcWrapper};
+use crate::server_map::NameMatch;
+use crate::stats::StatsReport;
+
+/*--- Request --------------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| opcode        | u8                | 1 byte        |
+| body          | depends on opcode | variable size |
+|--------------------------------------------------*/
+
+/// Body: a single `Sighting`. Responds with an empty body.
+pub const OP_INSERT: u8 = 0x00;
+/// Body: varint count followed by that many `Sighting`s. Responds with
+/// a `BatchSummary`.
+pub const OP_INSERT_BATCH: u8 = 0x01;
+/// Body: a single `Resolution`. Responds with an empty body.
+pub const OP_INSERT_RESOLUTION: u8 = 0x02;
+/// Body: a `Hostname`. Responds with a varint count followed by that
+/// many `ServerPointer`s, one per server the hostname leads to.
+pub const OP_LOOKUP_HOSTNAME: u8 = 0x03;
+/// Body: a `NameSearch`. Responds with a `NamePage`.
+pub const OP_SEARCH_NAMES: u8 = 0x04;
+/// Body: a `ServerPointer` followed by a `Motd`. Responds with an
+/// empty body.
+pub const OP_SET_MOTD: u8 = 0x05;
+/// Body: a `MotdSearch`. Responds with a varint count followed by that
+/// many `ServerPointer`s, in ascending server id order.
+pub const OP_SEARCH_MOTD: u8 = 0x06;
+/// Body: a `ServerPointer`, a varint count and that many
+/// `AttributeUpdate`s. Responds with an empty body.
+pub const OP_SET_ATTRIBUTES: u8 = 0x07;
+/// Body: an `AttributeSearch`. Responds with a varint count followed by
+/// that many `ServerPointer`s, in ascending server id order.
+pub const OP_FIND_BY_ATTRIBUTE: u8 = 0x08;
+/// Body: a `QueryRequest`. Responds with a `QueryPage`.
+pub const OP_QUERY: u8 = 0x09;
+/// Body: a `ServerScan`. Responds with a `ServerScanPage`.
+pub const OP_SCAN_SERVERS: u8 = 0x0a;
+/// Body: a `PlayerScan`. Responds with a `PlayerScanPage`.
+pub const OP_SCAN_PLAYERS: u8 = 0x0b;
+/// Body: a player uuid followed by a `RelatedSearch`. Responds with a
+/// `RelatedPage` keyed by player uuid.
+pub const OP_RELATED_PLAYERS: u8 = 0x0c;
+/// Body: a `ServerPointer` followed by a `RelatedSearch`. Responds with
+/// a `RelatedPage` keyed by `ServerPointer`.
+pub const OP_RELATED_SERVERS: u8 = 0x0d;
+/// Body: an `Expansion`. Responds with a varint count followed by that
+/// many `ExpandedNode`s, nearest first.
+pub const OP_EXPAND: u8 = 0x0e;
+/// Body: a `PathSearch`. Responds with a varint count followed by that
+/// many `NodeEntry`s from the first player to the second, or a count of
+/// 0 if there is no path.
+pub const OP_SHORTEST_PATH: u8 = 0x0f;
+/// Body: a varint limit, clamped to 1..=MAX_PAGE_LEN. Responds with a
+/// `ComponentSummary`.
+pub const OP_COMPONENTS: u8 = 0x10;
+/// Body: a varint limit, clamped to 1..=MAX_PAGE_LEN. Runs network
+/// detection and responds with a `NetworkSummary`.
+pub const OP_DETECT_NETWORKS: u8 = 0x11;
+/// Empty body. Responds with a `StatsReport`.
+pub const OP_STATS: u8 = 0x12;
+/// Body: a `ServerPointer`. Responds with a `ServerInfo`, or an error if
+/// the server is unknown.
+pub const OP_GET_SERVER: u8 = 0x13;
+/// Body: a player uuid. Responds with a varint count followed by that
+/// many `Player` records, one per name, oldest first.
+pub const OP_GET_PLAYER: u8 = 0x14;
+/// Empty body. Writes a snapshot and responds with a `SnapshotSummary`.
+pub const OP_SNAPSHOT: u8 = 0x15;
+/// Body: a `ServerPointer`. Responds with a u8, 1 if the server was
+/// deleted and 0 if it was unknown.
+pub const OP_DELETE_SERVER: u8 = 0x16;
+/// Body: a player uuid. Responds with a varint count of the deleted
+/// player records.
+pub const OP_DELETE_PLAYER: u8 = 0x17;
+/// Body: a `RecentRequest`. Responds with a `RecentPage`.
+pub const OP_RECENT_INSERTS: u8 = 0x18;
+
+/// Name of an opcode, for logs and metrics.
+pub fn op_name(opcode: u8) -> Option<&'static str> {
+    Some(match opcode {
+        OP_INSERT => "insert",
+        OP_INSERT_BATCH => "insert_batch",
+        OP_INSERT_RESOLUTION => "insert_resolution",
+        OP_LOOKUP_HOSTNAME => "lookup_hostname",
+        OP_SEARCH_NAMES => "search_names",
+        OP_SET_MOTD => "set_motd",
+        OP_SEARCH_MOTD => "search_motd",
+        OP_SET_ATTRIBUTES => "set_attributes",
+        OP_FIND_BY_ATTRIBUTE => "find_by_attribute",
+        OP_QUERY => "query",
+        OP_SCAN_SERVERS => "scan_servers",
+        OP_SCAN_PLAYERS => "scan_players",
+        OP_RELATED_PLAYERS => "related_players",
+        OP_RELATED_SERVERS => "related_servers",
+        OP_EXPAND => "expand",
+        OP_SHORTEST_PATH => "shortest_path",
+        OP_COMPONENTS => "components",
+        OP_DETECT_NETWORKS => "detect_networks",
+        OP_STATS => "stats",
+        OP_GET_SERVER => "get_server",
+        OP_GET_PLAYER => "get_player",
+        OP_SNAPSHOT => "snapshot",
+        OP_DELETE_SERVER => "delete_server",
+        OP_DELETE_PLAYER => "delete_player",
+        OP_RECENT_INSERTS => "recent_inserts",
+        _ => return None,
+    })
+}
+
+/*--- Response -------------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| status        | u8                | 1 byte        |
+| body length   | varint            | variable size |
+| body          | u8[]              | variable size |
+|--------------------------------------------------*/
+
+pub const STATUS_OK: u8 = 0x00;
+/// The body is a UTF-8 error message.
+pub const STATUS_ERROR: u8 = 0x01;
+
+/// Longest player name accepted in a request.
+const MAX_NAME_LEN: usize = 64;
+
+/// Most players returned in one `NamePage`.
+pub const MAX_PAGE_LEN: usize = 1000;
+
+/*--- Sighting -------------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| address       | ServerPointer     | 6 bytes       |
+| num players   | varint            | variable size |
+| player list   | SightedPlayer[]   | variable size |
+|--------------------------------------------------*/
+
+/*--- Sighted Player -------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| name length   | varint            | variable size |
+| player name   | string            | variable size |
+| player uuid   | uuid              | 16 bytes      |
+|--------------------------------------------------*/
+
+/*--- Hostname -------------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| length        | varint            | variable size |
+| hostname      | string            | variable size |
+|--------------------------------------------------*/
+
+/*--- Resolution -----------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| address       | ServerPointer     | 6 bytes       |
+| hostname      | Hostname          | variable size |
+| srv target    | Hostname          | variable size |
+|--------------------------------------------------*/
+// An empty srv target means the hostname was resolved without SRV.
+
+/*--- Name Search ----------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| mode          | u8                | 1 byte        |
+| name length   | varint            | variable size |
+| name          | string            | variable size |
+| after         | Cursor Field      | variable size |
+| limit         | varint            | variable size |
+|--------------------------------------------------*/
+// mode is 0 for an exact match and 1 for a prefix match, both ignoring
+// case. after is empty for the first page and otherwise the `next` of
+// the previous page. limit is clamped to 1..=MAX_PAGE_LEN.
+
+/*--- Name Page ------------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| next          | Cursor Field      | variable size |
+| num players   | varint            | variable size |
+| player list   | SightedPlayer[]   | variable size |
+|--------------------------------------------------*/
+// Players are ordered by case-folded name, then uuid, then name.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameSearch {
+    pub query: String,
+    pub mode: NameMatch,
+    pub after: Option<Cursor>,
+    pub limit: usize,
+}
+
+pub async fn read_name_search<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+) -> Result<NameSearch, Box<dyn Error + Send + Sync>> {
+    let mode = match reader.read_u8().await? {
+        0 => NameMatch::Exact,
+        1 => NameMatch::Prefix,
+        mode => return Err(format!("unknown name search mode {mode}").into()),
+    };
+    let query_len: usize = reader.read_varint_async().await?;
+    if query_len > MAX_NAME_LEN {
+        return Err(format!("player name is {query_len} bytes long").into());
+    }
+    let mut query = vec![0u8; query_len];
+    reader.read_exact(&mut query).await?;
+    let after = read_cursor(reader).await?;
+    let limit: usize = reader.read_varint_async().await?;
+    Ok(NameSearch {
+        query: String::from_utf8(query)?,
+        mode,
+        after,
+        limit: limit.clamp(1, MAX_PAGE_LEN),
+    })
+}
+
+pub fn serialize_name_search(search: &NameSearch) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut res = vec![match search.mode {
+        NameMatch::Exact => 0,
+        NameMatch::Prefix => 1,
+    }];
+    res.write_varint(search.query.len())?;
+    Write::write_all(&mut res, search.query.as_bytes())?;
+    serialize_cursor(&mut res, search.after.as_ref())?;
+    res.write_varint(search.limit)?;
+    Ok(res)
+}
+
+pub fn serialize_name_page(
+    next: Option<&Cursor>,
+    players: &[Player],
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut res = vec![];
+    serialize_cursor(&mut res, next)?;
+    res.write_varint(players.len())?;
+    for player in players {
+        let name_bytes = player.name.as_bytes();
+        res.write_varint(name_bytes.len())?;
+        Write::write_all(&mut res, name_bytes)?;
+        Write::write_all(&mut res, player.uuid.as_bytes())?;
+    }
+    Ok(res)
+}
+
+/*--- Motd -----------------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| length        | varint            | variable size |
+| motd          | string            | variable size |
+|--------------------------------------------------*/
+
+/*--- Motd Search ----------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| mode          | u8                | 1 byte        |
+| max distance  | u8                | 1 byte        |
+| query         | Motd              | variable size |
+| limit         | varint            | variable size |
+|--------------------------------------------------*/
+// mode is 0 for a substring match and 1 for a fuzzy match, in which
+// case max distance is the edit distance allowed per word. limit is
+// clamped to 1..=MAX_PAGE_LEN.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MotdSearch {
+    pub query: String,
+    pub mode: MotdMatch,
+    pub limit: usize,
+}
+
+pub async fn read_motd<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let len: usize = reader.read_varint_async().await?;
+    // formatting codes take up to 3 bytes each and are not counted
+    if len > MAX_MOTD_LEN * 4 {
+        return Err(format!("MOTD is {len} bytes long").into());
+    }
+    let mut motd = vec![0u8; len];
+    reader.read_exact(&mut motd).await?;
+    Ok(String::from_utf8(motd)?)
+}
+
+pub async fn read_motd_search<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+) -> Result<MotdSearch, Box<dyn Error + Send + Sync>> {
+    let mode = reader.read_u8().await?;
+    let max_distance = reader.read_u8().await?;
+    let mode = match mode {
+        0 => MotdMatch::Substring,
+        1 => MotdMatch::Fuzzy { max_distance },
+        mode => return Err(format!("unknown MOTD search mode {mode}").into()),
+    };
+    let query = read_motd(reader).await?;
+    let limit: usize = reader.read_varint_async().await?;
+    Ok(MotdSearch {
+        query,
+        mode,
+        limit: limit.clamp(1, MAX_PAGE_LEN),
+    })
+}
+
+pub fn serialize_motd_search(search: &MotdSearch) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut res = match search.mode {
+        MotdMatch::Substring => vec![0, 0],
+        MotdMatch::Fuzzy { max_distance } => vec![1, max_distance],
+    };
+    res.write_varint(search.query.len())?;
+    Write::write_all(&mut res, search.query.as_bytes())?;
+    res.write_varint(search.limit)?;
+    Ok(res)
+}
+
+/*--- Attribute Update -----------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| attribute id  | u8                | 1 byte        |
+| value         | OptionalValue     | variable size |
+|--------------------------------------------------*/
+// A missing value clears the attribute.
+
+/*--- Optional Value -------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| present       | u8                | 1 byte        |
+| value         | AttributeValue    | 0 if absent   |
+|--------------------------------------------------*/
+
+/*--- Attribute Search -----------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| attribute id  | u8                | 1 byte        |
+| predicate     | u8                | 1 byte        |
+| value / min   | OptionalValue     | variable size |
+| max           | OptionalValue     | range only    |
+| limit         | varint            | variable size |
+|--------------------------------------------------*/
+// predicate is 0 for equality and 2 for a string prefix, whose value
+// must be present, and 1 for an inclusive range. limit is clamped to
+// 1..=MAX_PAGE_LEN.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeSearch {
+    pub attribute: Attribute,
+    pub predicate: AttrPredicate,
+    pub limit: usize,
+}
+
+async fn read_attribute<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+) -> Result<Attribute, Box<dyn Error + Send + Sync>> {
+    let id = reader.read_u8().await?;
+    Attribute::from_id(id).ok_or_else(|| format!("unknown attribute id {id}").into())
+}
+
+/// Async counterpart of `AttrValue::deserialize`, behind a present byte.
+async fn read_optional_value<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+    kind: AttrKind,
+) -> Result<Option<AttrValue>, Box<dyn Error + Send + Sync>> {
+    if reader.read_u8().await? == 0 {
+        return Ok(None);
+    }
+    let value = match kind {
+        AttrKind::Bool => AttrValue::Bool(reader.read_u8().await? != 0),
+        AttrKind::Int => AttrValue::Int(reader.read_varint_async().await?),
+        AttrKind::Str => {
+            let len: usize = reader.read_varint_async().await?;
+            if len > MAX_ATTR_STR_LEN {
+                return Err(format!("attribute value is {len} bytes long").into());
+            }
+            let mut value = vec![0u8; len];
+            reader.read_exact(&mut value).await?;
+            AttrValue::Str(String::from_utf8(value)?)
+        }
+    };
+    Ok(Some(value))
+}
+
+fn serialize_optional_value(
+    buf: &mut Vec<u8>,
+    value: Option<&AttrValue>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match value {
+        Some(value) => {
+            buf.push(1);
+            value.serialize(buf)?;
+        }
+        None => buf.push(0),
+    }
+    Ok(())
+}
+
+pub async fn read_attribute_updates<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+) -> Result<Vec<(Attribute, Option<AttrValue>)>, Box<dyn Error + Send + Sync>> {
+    let num_updates: usize = reader.read_varint_async().await?;
+    if num_updates > Attribute::ALL.len() {
+        return Err(format!("{num_updates} attribute updates in one request").into());
+    }
+    let mut updates = Vec::with_capacity(num_updates);
+    for _ in 0..num_updates {
+        let attribute = read_attribute(reader).await?;
+        let value = read_optional_value(reader, attribute.kind()).await?;
+        updates.push((attribute, value));
+    }
+    Ok(updates)
+}
+
+pub fn serialize_attribute_updates(
+    updates: &[(Attribute, Option<AttrValue>)],
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut res = vec![];
+    res.write_varint(updates.len())?;
+    for (attribute, value) in updates {
+        res.push(attribute.id());
+        serialize_optional_value(&mut res, value.as_ref())?;
+    }
+    Ok(res)
+}
+
+pub async fn read_attribute_search<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+) -> Result<AttributeSearch, Box<dyn Error + Send + Sync>> {
+    let attribute = read_attribute(reader).await?;
+    let predicate = match reader.read_u8().await? {
+        0 => AttrPredicate::Eq(
+            read_optional_value(reader, attribute.kind())
+                .await?
+                .ok_or("equality search without a value")?,
+        ),
+        1 => AttrPredicate::Range {
+            min: read_optional_value(reader, attribute.kind()).await?,
+            max: read_optional_value(reader, attribute.kind()).await?,
+        },
+        2 => match read_optional_value(reader, AttrKind::Str).await? {
+            Some(AttrValue::Str(prefix)) => AttrPredicate::Prefix(prefix),
+            _ => return Err("prefix search without a value".into()),
+        },
+        predicate => return Err(format!("unknown attribute predicate {predicate}").into()),
+    };
+    let limit: usize = reader.read_varint_async().await?;
+    Ok(AttributeSearch {
+        attribute,
+        predicate,
+        limit: limit.clamp(1, MAX_PAGE_LEN),
+    })
+}
+
+pub fn serialize_attribute_search(
+    search: &AttributeSearch,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut res = vec![search.attribute.id()];
+    match &search.predicate {
+        AttrPredicate::Eq(value) => {
+            res.push(0);
+            serialize_optional_value(&mut res, Some(value))?;
+        }
+        AttrPredicate::Range { min, max } => {
+            res.push(1);
+            serialize_optional_value(&mut res, min.as_ref())?;
+            serialize_optional_value(&mut res, max.as_ref())?;
+        }
+        AttrPredicate::Prefix(prefix) => {
+            res.push(2);
+            serialize_optional_value(&mut res, Some(&AttrValue::Str(prefix.clone())))?;
+        }
+    }
+    res.write_varint(search.limit)?;
+    Ok(res)
+}
+
+/*--- Query Request --------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| query length  | varint            | variable size |
+| query         | string            | variable size |
+| after         | Cursor Field      | variable size |
+| limit         | varint            | variable size |
+|--------------------------------------------------*/
+// The query is in the language of `query::Query`. after is empty for
+// the first page and otherwise the `next` of the previous page. limit
+// is clamped to 1..=MAX_PAGE_LEN.
+
+/*--- Query Page -----------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| next          | Cursor Field      | variable size |
+| num servers   | varint            | variable size |
+| server list   | ServerPointer[]   | 6 bytes each  |
+|--------------------------------------------------*/
+// Servers are in address order, so this is laid out like a
+// `ServerScanPage`.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryRequest {
+    pub query: String,
+    pub after: Option<Cursor>,
+    pub limit: usize,
+}
+
+pub async fn read_query_request<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+) -> Result<QueryRequest, Box<dyn Error + Send + Sync>> {
+    let len: usize = reader.read_varint_async().await?;
+    if len > MAX_QUERY_LEN {
+        return Err(format!("query is {len} bytes long").into());
+    }
+    let mut query = vec![0u8; len];
+    reader.read_exact(&mut query).await?;
+    let after = read_cursor(reader).await?;
+    let limit: usize = reader.read_varint_async().await?;
+    Ok(QueryRequest {
+        query: String::from_utf8(query)?,
+        after,
+        limit: limit.clamp(1, MAX_PAGE_LEN),
+    })
+}
+
+pub fn serialize_query_request(
+    request: &QueryRequest,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut res = vec![];
+    res.write_varint(request.query.len())?;
+    Write::write_all(&mut res, request.query.as_bytes())?;
+    serialize_cursor(&mut res, request.after.as_ref())?;
+    res.write_varint(request.limit)?;
+    Ok(res)
+}
+
+/*--- Cursor Field ---------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| length        | varint            | variable size |
+| cursor        | u8[]              | variable size |
+|--------------------------------------------------*/
+// An encoded `Cursor`, opaque to clients. An empty cursor starts a scan
+// in a request and marks the last page in a response.
+
+/// Longest encoded cursor accepted.
+const MAX_CURSOR_LEN: usize = 128;
+
+async fn read_cursor<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+) -> Result<Option<Cursor>, Box<dyn Error + Send + Sync>> {
+    let len: usize = reader.read_varint_async().await?;
+    if len > MAX_CURSOR_LEN {
+        return Err(format!("cursor is {len} bytes long").into());
+    }
+    if len == 0 {
+        return Ok(None);
+    }
+    let mut cursor = vec![0u8; len];
+    reader.read_exact(&mut cursor).await?;
+    Ok(Some(Cursor::decode(&cursor)?))
+}
+
+fn serialize_cursor(
+    res: &mut Vec<u8>,
+    cursor: Option<&Cursor>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let cursor = cursor.map(Cursor::encode).transpose()?.unwrap_or_default();
+    res.write_varint(cursor.len())?;
+    Write::write_all(res, &cursor)?;
+    Ok(())
+}
+
+/*--- Server Scan ----------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| network       | u32               | 4 bytes       |
+| prefix length | u8                | 1 byte        |
+| after         | Cursor Field      | variable size |
+| limit         | varint            | variable size |
+|--------------------------------------------------*/
+// Pass network 0.0.0.0/0 for a full dump. limit is clamped to
+// 1..=MAX_PAGE_LEN.
+
+/*--- Server Scan Page -----------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| next          | Cursor Field      | variable size |
+| num servers   | varint            | variable size |
+| server list   | ServerPointer[]   | 6 bytes each  |
+|--------------------------------------------------*/
+// Servers are in address order.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerScan {
+    pub cidr: Cidr,
+    pub after: Option<Cursor>,
+    pub limit: usize,
+}
+
+pub async fn read_server_scan<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+) -> Result<ServerScan, Box<dyn Error + Send + Sync>> {
+    let network = reader.read_u32().await?;
+    let prefix_len = reader.read_u8().await?;
+    let cidr = Cidr::new(network.into(), prefix_len)?;
+    let after = read_cursor(reader).await?;
+    let limit: usize = reader.read_varint_async().await?;
+    Ok(ServerScan {
+        cidr,
+        after,
+        limit: limit.clamp(1, MAX_PAGE_LEN),
+    })
+}
+
+pub fn serialize_server_scan(scan: &ServerScan) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut res = u32::from(scan.cidr.network()).to_be_bytes().to_vec();
+    res.push(scan.cidr.prefix_len());
+    serialize_cursor(&mut res, scan.after.as_ref())?;
+    res.write_varint(scan.limit)?;
+    Ok(res)
+}
+
+/*--- Player Scan ----------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| after         | Cursor Field      | variable size |
+| limit         | varint            | variable size |
+|--------------------------------------------------*/
+// limit is clamped to 1..=MAX_PAGE_LEN.
+
+/*--- Player Scan Page -----------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| next          | Cursor Field      | variable size |
+| num players   | varint            | variable size |
+| player list   | SightedPlayer[]   | variable size |
+|--------------------------------------------------*/
+// Players are ordered by name, then uuid.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlayerScan {
+    pub after: Option<Cursor>,
+    pub limit: usize,
+}
+
+pub async fn read_player_scan<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+) -> Result<PlayerScan, Box<dyn Error + Send + Sync>> {
+    let after = read_cursor(reader).await?;
+    let limit: usize = reader.read_varint_async().await?;
+    Ok(PlayerScan {
+        after,
+        limit: limit.clamp(1, MAX_PAGE_LEN),
+    })
+}
+
+pub fn serialize_player_scan(scan: &PlayerScan) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut res = vec![];
+    serialize_cursor(&mut res, scan.after.as_ref())?;
+    res.write_varint(scan.limit)?;
+    Ok(res)
+}
+
+pub fn serialize_server_scan_page(
+    next: Option<&Cursor>,
+    servers: &[SocketAddr],
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut res = vec![];
+    serialize_cursor(&mut res, next)?;
+    res.write_varint(servers.len())?;
+    for addr in servers {
+        Write::write_all(&mut res, &Server::new(*addr).serialize_pointer()?)?;
+    }
+    Ok(res)
+}
+
+pub fn serialize_player_scan_page(
+    next: Option<&Cursor>,
+    players: &[Player],
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut res = vec![];
+    serialize_cursor(&mut res, next)?;
+    res.write_varint(players.len())?;
+    for player in players {
+        let name_bytes = player.name.as_bytes();
+        res.write_varint(name_bytes.len())?;
+        Write::write_all(&mut res, name_bytes)?;
+        Write::write_all(&mut res, player.uuid.as_bytes())?;
+    }
+    Ok(res)
+}
+
+/*--- Related Search -------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| window from   | varint            | variable size |
+| window to     | varint            | variable size |
+| limit         | varint            | variable size |
+|--------------------------------------------------*/
+// The window is inclusive unix times, and 0 to 0 means no window. limit
+// is clamped to 1..=MAX_PAGE_LEN.
+
+/*--- Related Page ---------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| num entries   | varint            | variable size |
+| entry list    | RelatedEntry[]    | variable size |
+|--------------------------------------------------*/
+// Entries are ordered most related first.
+
+/*--- Related Entry --------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| key           | uuid or pointer   | 16 or 6 bytes |
+| shared        | varint            | variable size |
+| score         | f64               | 8 bytes       |
+|--------------------------------------------------*/
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelatedSearch {
+    pub window: Option<TimeWindow>,
+    pub limit: usize,
+}
+
+pub async fn read_related_search<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+) -> Result<RelatedSearch, Box<dyn Error + Send + Sync>> {
+    let from: u64 = reader.read_varint_async().await?;
+    let to: u64 = reader.read_varint_async().await?;
+    let window = match (from, to) {
+        (0, 0) => None,
+        (from, to) if from <= to => Some(TimeWindow { from, to }),
+        _ => return Err(format!("empty time window {from}..={to}").into()),
+    };
+    let limit: usize = reader.read_varint_async().await?;
+    Ok(RelatedSearch {
+        window,
+        limit: limit.clamp(1, MAX_PAGE_LEN),
+    })
+}
+
+pub fn serialize_related_search(
+    search: &RelatedSearch,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut res = vec![];
+    let (from, to) = search
+        .window
+        .map_or((0, 0), |window| (window.from, window.to));
+    res.write_varint(from)?;
+    res.write_varint(to)?;
+    res.write_varint(search.limit)?;
+    Ok(res)
+}
+
+/// `key` writes the key of an entry, a uuid or a `ServerPointer`.
+pub fn serialize_related_page<T>(
+    related: &[Related<T>],
+    key: impl Fn(&T) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut res = vec![];
+    res.write_varint(related.len())?;
+    for entry in related {
+        Write::write_all(&mut res, &key(&entry.id)?)?;
+        res.write_varint(entry.shared)?;
+        Write::write_all(&mut res, &entry.score.to_be_bytes())?;
+    }
+    Ok(res)
+}
+
+/*--- Node Key -------------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| kind          | u8                | 1 byte        |
+| key           | uuid or pointer   | 16 or 6 bytes |
+|--------------------------------------------------*/
+// kind is 0 for a player uuid, which stands for every record of the
+// player, and 1 for a ServerPointer.
+
+/*--- Node Entry -----------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| kind          | u8                | 1 byte        |
+| node          | depends on kind   | variable size |
+|--------------------------------------------------*/
+// kind is 0 for a SightedPlayer and 1 for a ServerPointer.
+
+/*--- Expansion ------------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| start         | Node Key          | variable size |
+| max depth     | u8                | 1 byte        |
+| limit         | varint            | variable size |
+|--------------------------------------------------*/
+// max depth is at most graph::MAX_DEPTH edges. limit is clamped to
+// 1..=MAX_PAGE_LEN.
+
+/*--- Expanded Node --------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| depth         | u8                | 1 byte        |
+| node          | Node Entry        | variable size |
+|--------------------------------------------------*/
+
+/*--- Path Search ----------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| from          | uuid              | 16 bytes      |
+| to            | uuid              | 16 bytes      |
+| max depth     | u8                | 1 byte        |
+|--------------------------------------------------*/
+
+/*--- Component Summary ----------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| count         | varint            | variable size |
+| num listed    | varint            | variable size |
+| component list| ComponentEntry[]  | variable size |
+|--------------------------------------------------*/
+// count is the number of components in the graph, and the listed ones
+// are the largest, largest first.
+
+/*--- Component Entry ------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| num players   | varint            | variable size |
+| num servers   | varint            | variable size |
+| root          | Node Entry        | variable size |
+|--------------------------------------------------*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKey {
+    Player(Uuid),
+    Server(SocketAddr),
+}
+
+/// A node as sent to clients.
+#[derive(Debug, Clone)]
+pub enum NodeEntry {
+    Player(Player),
+    Server(SocketAddr),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expansion {
+    pub start: NodeKey,
+    pub max_depth: u8,
+    pub limit: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathSearch {
+    pub from: Uuid,
+    pub to: Uuid,
+    pub max_depth: u8,
+}
+
+async fn read_max_depth<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+) -> Result<u8, Box<dyn Error + Send + Sync>> {
+    let max_depth = reader.read_u8().await?;
+    if max_depth > MAX_DEPTH {
+        return Err(format!("depth {max_depth} is over {MAX_DEPTH}").into());
+    }
+    Ok(max_depth)
+}
+
+pub async fn read_expansion<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+) -> Result<Expansion, Box<dyn Error + Send + Sync>> {
+    let start = match reader.read_u8().await? {
+        0 => {
+            let mut uuid = [0u8; 16];
+            reader.read_exact(&mut uuid).await?;
+            NodeKey::Player(Uuid::from_bytes(uuid))
+        }
+        1 => {
+            let mut addr_buf = [0u8; 6];
+            reader.read_exact(&mut addr_buf).await?;
+            NodeKey::Server(Server::deserialize_pointer(&mut &addr_buf[..])?)
+        }
+        kind => return Err(format!("unknown node kind {kind}").into()),
+    };
+    let max_depth = read_max_depth(reader).await?;
+    let limit: usize = reader.read_varint_async().await?;
+    Ok(Expansion {
+        start,
+        max_depth,
+        limit: limit.clamp(1, MAX_PAGE_LEN),
+    })
+}
+
+pub fn serialize_expansion(expansion: &Expansion) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut res = match expansion.start {
+        NodeKey::Player(uuid) => {
+            let mut res = vec![0];
+            res.extend_from_slice(uuid.as_bytes());
+            res
+        }
+        NodeKey::Server(addr) => {
+            let mut res = vec![1];
+            res.extend_from_slice(&Server::new(addr).serialize_pointer()?);
+            res
+        }
+    };
+    res.push(expansion.max_depth);
+    res.write_varint(expansion.limit)?;
+    Ok(res)
+}
+
+pub async fn read_path_search<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+) -> Result<PathSearch, Box<dyn Error + Send + Sync>> {
+    let mut from = [0u8; 16];
+    reader.read_exact(&mut from).await?;
+    let mut to = [0u8; 16];
+    reader.read_exact(&mut to).await?;
+    Ok(PathSearch {
+        from: Uuid::from_bytes(from),
+        to: Uuid::from_bytes(to),
+        max_depth: read_max_depth(reader).await?,
+    })
+}
+
+pub fn serialize_path_search(search: &PathSearch) -> Vec<u8> {
+    let mut res = search.from.as_bytes().to_vec();
+    res.extend_from_slice(search.to.as_bytes());
+    res.push(search.max_depth);
+    res
+}
+
+fn serialize_node_entry(
+    res: &mut Vec<u8>,
+    node: &NodeEntry,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match node {
+        NodeEntry::Player(player) => {
+            res.push(0);
+            res.write_varint(player.name.len())?;
+            Write::write_all(res, player.name.as_bytes())?;
+            Write::write_all(res, player.uuid.as_bytes())?;
+        }
+        NodeEntry::Server(addr) => {
+            res.push(1);
+            Write::write_all(res, &Server::new(*addr).serialize_pointer()?)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn serialize_expanded_nodes(
+    nodes: &[(NodeEntry, u8)],
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut res = vec![];
+    res.write_varint(nodes.len())?;
+    for (node, depth) in nodes {
+        res.push(*depth);
+        serialize_node_entry(&mut res, node)?;
+    }
+    Ok(res)
+}
+
+pub fn serialize_path(path: &[NodeEntry]) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut res = vec![];
+    res.write_varint(path.len())?;
+    for node in path {
+        serialize_node_entry(&mut res, node)?;
+    }
+    Ok(res)
+}
+
+/// `largest` pairs each component with its resolved root.
+pub fn serialize_component_summary(
+    count: usize,
+    largest: &[(Component, NodeEntry)],
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut res = vec![];
+    res.write_varint(count)?;
+    res.write_varint(largest.len())?;
+    for (component, root) in largest {
+        res.write_varint(component.players)?;
+        res.write_varint(component.servers)?;
+        serialize_node_entry(&mut res, root)?;
+    }
+    Ok(res)
+}
+
+/*--- Network Summary ------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| count         | varint            | variable size |
+| num listed    | varint            | variable size |
+| network list  | NetworkEntry[]    | variable size |
+|--------------------------------------------------*/
+// count is the number of networks found, and the listed ones are the
+// largest, largest first. The servers of a network are found with
+// OP_FIND_BY_ATTRIBUTE on the network attribute.
+
+/*--- Network Entry --------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| network id    | varint            | variable size |
+| num servers   | varint            | variable size |
+|--------------------------------------------------*/
+
+pub fn serialize_network_summary(
+    networks: &[Network],
+    limit: usize,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut res = vec![];
+    res.write_varint(networks.len())?;
+    let listed = &networks[..networks.len().min(limit)];
+    res.write_varint(listed.len())?;
+    for network in listed {
+        res.write_varint(network.id as u64)?;
+        res.write_varint(network.servers.len())?;
+    }
+    Ok(res)
+}
+
+/*--- Stats Report ---------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| servers       | varint            | variable size |
+| players       | varint            | variable size |
+| uuids         | varint            | variable size |
+| links         | varint            | variable size |
+| seen last day | varint            | variable size |
+| seen last week| varint            | variable size |
+| seen last mon | varint            | variable size |
+| num /8s       | varint            | variable size |
+| per /8        | (u8, varint)[]    | variable size |
+| num ports     | varint            | variable size |
+| per port      | (u16, varint)[]   | variable size |
+| num versions  | varint            | variable size |
+| per version   | (str, varint)[]   | variable size |
+| num protocols | varint            | variable size |
+| per protocol  | (zigzag, varint)[]| variable size |
+|--------------------------------------------------*/
+// Strings are a varint length followed by the string.
+
+pub fn serialize_stats_report(
+    report: &StatsReport,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut res = vec![];
+    for count in [
+        report.servers,
+        report.players,
+        report.uuids,
+        report.links,
+        report.seen_last_day,
+        report.seen_last_week,
+        report.seen_last_month,
+    ] {
+        res.write_varint(count)?;
+    }
+    res.write_varint(report.per_slash8.len())?;
+    for (octet, count) in &report.per_slash8 {
+        res.push(*octet);
+        res.write_varint(*count)?;
+    }
+    res.write_varint(report.per_port.len())?;
+    for (port, count) in &report.per_port {
+        Write::write_all(&mut res, &port.to_be_bytes())?;
+        res.write_varint(*count)?;
+    }
+    res.write_varint(report.per_version.len())?;
+    for (version, count) in &report.per_version {
+        res.write_varint(version.len())?;
+        Write::write_all(&mut res, version.as_bytes())?;
+        res.write_varint(*count)?;
+    }
+    res.write_varint(report.per_protocol.len())?;
+    for (protocol, count) in &report.per_protocol {
+        res.write_varint(*protocol)?;
+        res.write_varint(*count)?;
+    }
+    Ok(res)
+}
+
+pub async fn read_stats_report<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+) -> Result<StatsReport, Box<dyn Error + Send + Sync>> {
+    let mut report = StatsReport {
+        servers: reader.read_varint_async().await?,
+        players: reader.read_varint_async().await?,
+        uuids: reader.read_varint_async().await?,
+        links: reader.read_varint_async().await?,
+        seen_last_day: reader.read_varint_async().await?,
+        seen_last_week: reader.read_varint_async().await?,
+        seen_last_month: reader.read_varint_async().await?,
+        ..StatsReport::default()
+    };
+    let len: usize = reader.read_varint_async().await?;
+    for _ in 0..len {
+        let octet = reader.read_u8().await?;
+        report
+            .per_slash8
+            .insert(octet, reader.read_varint_async().await?);
+    }
+    let len: usize = reader.read_varint_async().await?;
+    for _ in 0..len {
+        let port = reader.read_u16().await?;
+        report
+            .per_port
+            .insert(port, reader.read_varint_async().await?);
+    }
+    let len: usize = reader.read_varint_async().await?;
+    for _ in 0..len {
+        let version_len: usize = reader.read_varint_async().await?;
+        if version_len > MAX_ATTR_STR_LEN {
+            return Err(format!("version is {version_len} bytes long").into());
+        }
+        let mut version = vec![0u8; version_len];
+        reader.read_exact(&mut version).await?;
+        report.per_version.insert(
+            String::from_utf8(version)?,
+            reader.read_varint_async().await?,
+        );
+    }
+    let len: usize = reader.read_varint_async().await?;
+    for _ in 0..len {
+        let protocol: i64 = reader.read_varint_async().await?;
+        report
+            .per_protocol
+            .insert(protocol, reader.read_varint_async().await?);
+    }
+    Ok(report)
+}
+
+pub async fn read_hostname<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let len: usize = reader.read_varint_async().await?;
+    if len > MAX_HOSTNAME_LEN {
+        return Err(format!("hostname is {len} bytes long").into());
+    }
+    let mut hostname = vec![0u8; len];
+    reader.read_exact(&mut hostname).await?;
+    Ok(String::from_utf8(hostname)?)
+}
+
+pub async fn read_resolution<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+) -> Result<Resolution, Box<dyn Error + Send + Sync>> {
+    let mut addr_buf = [0u8; 6];
+    reader.read_exact(&mut addr_buf).await?;
+    let addr = Server::deserialize_pointer(&mut &addr_buf[..])?;
+    let hostname = read_hostname(reader).await?;
+    let srv_target = read_hostname(reader).await?;
+    Ok(Resolution {
+        hostname,
+        srv_target: (!srv_target.is_empty()).then_some(srv_target),
+        addr,
+    })
+}
+
+pub fn serialize_resolution(
+    resolution: &Resolution,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut res = Server::new(resolution.addr).serialize_pointer()?;
+    for hostname in [
+        resolution.hostname.as_str(),
+        resolution.srv_target.as_deref().unwrap_or_default(),
+    ] {
+        res.write_varint(hostname.len())?;
+        Write::write_all(&mut res, hostname.as_bytes())?;
+    }
+    Ok(res)
+}
+
+pub async fn read_sighting<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+) -> Result<(SocketAddr, Vec<Player>), Box<dyn Error + Send + Sync>> {
+    let mut addr_buf = [0u8; 6];
+    reader.read_exact(&mut addr_buf).await?;
+    let addr = Server::deserialize_pointer(&mut &addr_buf[..])?;
+
+    let num_players: usize = reader.read_varint_async().await?;
+    let mut players = vec![];
+    for _ in 0..num_players {
+        let name_len: usize = reader.read_varint_async().await?;
+        if name_len > MAX_NAME_LEN {
+            return Err(format!("player name is {name_len} bytes long").into());
+        }
+        let mut name = vec![0u8; name_len];
+        reader.read_exact(&mut name).await?;
+        let mut uuid = [0u8; 16];
+        reader.read_exact(&mut uuid).await?;
+        players.push(Player::new(
+            String::from_utf8(name)?,
+            Uuid::from_bytes(uuid),
+        ));
+    }
+
+    Ok((addr, players))
+}
+
+pub fn serialize_sighting(
+    addr: SocketAddr,
+    players: &[Player],
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut res = Server::new(addr).serialize_pointer()?;
+    res.write_varint(players.len())?;
+    for player in players {
+        let name_bytes = player.name.as_bytes();
+        res.write_varint(name_bytes.len())?;
+        Write::write_all(&mut res, name_bytes)?;
+        Write::write_all(&mut res, player.uuid.as_bytes())?;
+    }
+    Ok(res)
+}
+
+/*--- Server Info ----------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| server        | Server            | variable size |
+| player names  | Hostname[]        | variable size |
+|--------------------------------------------------*/
+// Server is the record of a snapshot. There is one player name, framed
+// like a `Hostname`, per PlayerPointer of the record, in the same order.
+
+pub fn serialize_server_info(
+    server: &Server,
+    players: &[PlayerArcWrapper],
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut res = server.serialize(players)?;
+    for player_id in &server.players {
+        let name = players[*player_id as usize].lock().name.clone();
+        res.write_varint(name.len())?;
+        Write::write_all(&mut res, name.as_bytes())?;
+    }
+    Ok(res)
+}
+
+/// Reads a `ServerInfo`, returning the server and its players.
+pub fn read_server_info(
+    buf: &mut impl Read,
+) -> Result<(Server, Vec<Player>), Box<dyn Error + Send + Sync>> {
+    let (server, uuids) = Server::deserialize(buf)?;
+    let mut players = Vec::with_capacity(uuids.len());
+    for uuid in uuids {
+        let len: usize = buf.read_varint()?;
+        if len > MAX_NAME_LEN {
+            return Err(format!("player name is {len} bytes long").into());
+        }
+        let mut name = vec![0u8; len];
+        buf.read_exact(&mut name)?;
+        players.push(Player::new(String::from_utf8(name)?, uuid));
+    }
+    Ok((server, players))
+}
+
+pub fn serialize_player_records(
+    player_ids: &[PlayerId],
+    players: &[PlayerArcWrapper],
+    servers: &[ServerArcWrapper],
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut res = vec![];
+    res.write_varint(player_ids.len())?;
+    for player_id in player_ids {
+        let record = players[*player_id as usize].lock().serialize(servers)?;
+        Write::write_all(&mut res, &record)?;
+    }
+    Ok(res)
+}
+
+/// Reads the response of `OP_GET_PLAYER`, returning each record and the
+/// addresses of its servers.
+#[allow(clippy::type_complexity)]
+pub fn read_player_records(
+    buf: &mut impl Read,
+) -> Result<Vec<(Player, Vec<SocketAddr>)>, Box<dyn Error + Send + Sync>> {
+    let len: usize = buf.read_varint()?;
+    let mut records = vec![];
+    for _ in 0..len {
+        records.push(Player::deserialize(buf)?);
+    }
+    Ok(records)
+}
+
+/*--- Snapshot Summary -----------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| bytes         | varint            | variable size |
+| elapsed ms    | varint            | variable size |
+|--------------------------------------------------*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SnapshotSummary {
+    pub bytes: u64,
+    pub elapsed_ms: u64,
+}
+
+pub fn serialize_snapshot_summary(
+    summary: &SnapshotSummary,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut res = vec![];
+    res.write_varint(summary.bytes)?;
+    res.write_varint(summary.elapsed_ms)?;
+    Ok(res)
+}
+
+pub async fn read_snapshot_summary<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+) -> Result<SnapshotSummary, Box<dyn Error + Send + Sync>> {
+    Ok(SnapshotSummary {
+        bytes: reader.read_varint_async().await?,
+        elapsed_ms: reader.read_varint_async().await?,
+    })
+}
+
+/*--- Recent Request -------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| after         | varint            | variable size |
+| limit         | varint            | variable size |
+|--------------------------------------------------*/
+// after is 0 for the latest sightings, and otherwise the number of the
+// last sighting already seen. limit is clamped to 1..=MAX_PAGE_LEN.
+
+/*--- Recent Page ----------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| latest        | varint            | variable size |
+| num entries   | varint            | variable size |
+| entry list    | RecentEntry[]     | variable size |
+|--------------------------------------------------*/
+// latest is the number of the latest sighting. Entries are oldest
+// first; a gap in their numbers means sightings fell out of the buffer.
+
+/*--- Recent Entry ---------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| number        | varint            | variable size |
+| time          | varint            | variable size |
+| address       | ServerPointer     | 6 bytes       |
+| num players   | varint            | variable size |
+|--------------------------------------------------*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecentRequest {
+    pub after: Option<u64>,
+    pub limit: usize,
+}
+
+pub async fn read_recent_request<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+) -> Result<RecentRequest, Box<dyn Error + Send + Sync>> {
+    let after: u64 = reader.read_varint_async().await?;
+    let limit: usize = reader.read_varint_async().await?;
+    Ok(RecentRequest {
+        after: (after != 0).then_some(after),
+        limit: limit.clamp(1, MAX_PAGE_LEN),
+    })
+}
+
+pub fn serialize_recent_request(
+    request: &RecentRequest,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut res = vec![];
+    res.write_varint(request.after.unwrap_or(0))?;
+    res.write_varint(request.limit)?;
+    Ok(res)
+}
+
+pub fn serialize_recent_page(
+    latest: u64,
+    entries: &[RecentInsert],
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut res = vec![];
+    res.write_varint(latest)?;
+    res.write_varint(entries.len())?;
+    for entry in entries {
+        res.write_varint(entry.seq)?;
+        res.write_varint(entry.time)?;
+        Write::write_all(&mut res, &Server::new(entry.addr).serialize_pointer()?)?;
+        res.write_varint(entry.players)?;
+    }
+    Ok(res)
+}
+
+/// Reads a `RecentPage`, returning the latest number and the entries.
+pub async fn read_recent_page<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+) -> Result<(u64, Vec<RecentInsert>), Box<dyn Error + Send + Sync>> {
+    let latest: u64 = reader.read_varint_async().await?;
+    let len: usize = reader.read_varint_async().await?;
+    let mut entries = Vec::with_capacity(len.min(MAX_PAGE_LEN));
+    for _ in 0..len {
+        let seq = reader.read_varint_async().await?;
+        let time = reader.read_varint_async().await?;
+        let addr = read_server_pointer(reader).await?;
+        let players = reader.read_varint_async().await?;
+        entries.push(RecentInsert {
+            seq,
+            time,
+            addr,
+            players,
+        });
+    }
+    Ok((latest, entries))
+}
+
+/// Reads a `ServerScanPage`, returning the cursor of the next page and
+/// the servers.
+pub async fn read_server_scan_page<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+) -> Result<(Option<Cursor>, Vec<SocketAddr>), Box<dyn Error + Send + Sync>> {
+    let next = read_cursor(reader).await?;
+    let len: usize = reader.read_varint_async().await?;
+    let mut servers = Vec::with_capacity(len.min(MAX_PAGE_LEN));
+    for _ in 0..len {
+        servers.push(read_server_pointer(reader).await?);
+    }
+    Ok((next, servers))
+}
+
+pub async fn read_server_pointer<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+) -> Result<SocketAddr, Box<dyn Error + Send + Sync>> {
+    let mut addr_buf = [0u8; 6];
+    reader.read_exact(&mut addr_buf).await?;
+    Server::deserialize_pointer(&mut &addr_buf[..])
+}
+
+pub async fn read_uuid<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+) -> Result<Uuid, Box<dyn Error + Send + Sync>> {
+    let mut uuid = [0u8; 16];
+    reader.read_exact(&mut uuid).await?;
+    Ok(Uuid::from_bytes(uuid))
+}
+
+pub async fn write_response<W: AsyncWrite + Unpin + Send>(
+    writer: &mut W,
+    status: u8,
+    body: &[u8],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    writer.write_u8(status).await?;
+    writer.write_varint_async(body.len()).await?;
+    writer.write_all(body).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Reads a response, returning its body or, for `STATUS_ERROR`, an error
+/// carrying its message.
+pub async fn read_response<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let status = reader.read_u8().await?;
+    let len: usize = reader.read_varint_async().await?;
+    let mut body = vec![0u8; len];
+    reader.read_exact(&mut body).await?;
+    match status {
+        STATUS_OK => Ok(body),
+        STATUS_ERROR => Err(String::from_utf8_lossy(&body).into_owned().into()),
+        _ => Err(format!("unknown status {status}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn sighting_round_trip() {
+        let addr: SocketAddr = "10.0.0.1:25565".parse().unwrap();
+        let players = vec![
+            Player::new("alice".to_string(), Uuid::from_u128(1)),
+            Player::new("bob".to_string(), Uuid::from_u128(2)),
+        ];
+        let buf = serialize_sighting(addr, &players).unwrap();
+        let (read_addr, read_players) = read_sighting(&mut &buf[..]).await.unwrap();
+        assert_eq!(read_addr, addr);
+        assert_eq!(read_players, players);
+    }
+
+    #[tokio::test]
+    async fn name_search_round_trip() {
+        let search = NameSearch {
+            query: "Ali".to_string(),
+            mode: NameMatch::Prefix,
+            after: Some(Cursor::Player("Alice".to_string(), Uuid::from_u128(1))),
+            limit: 50,
+        };
+        let buf = serialize_name_search(&search).unwrap();
+        assert_eq!(read_name_search(&mut &buf[..]).await.unwrap(), search);
+
+        let buf = serialize_name_search(&NameSearch {
+            after: None,
+            limit: 0,
+            ..search
+        })
+        .unwrap();
+        let read = read_name_search(&mut &buf[..]).await.unwrap();
+        assert_eq!((read.after, read.limit), (None, 1));
+    }
+
+    #[tokio::test]
+    async fn motd_search_round_trip() {
+        for mode in [MotdMatch::Substring, MotdMatch::Fuzzy { max_distance: 2 }] {
+            let search = MotdSearch {
+                query: "skyblock".to_string(),
+                mode,
+                limit: 10,
+            };
+            let buf = serialize_motd_search(&search).unwrap();
+            assert_eq!(read_motd_search(&mut &buf[..]).await.unwrap(), search);
+        }
+    }
+
+    #[tokio::test]
+    async fn attribute_messages_round_trip() {
+        let updates = vec![
+            (Attribute::ProtocolVersion, Some(AttrValue::Int(-1))),
+            (Attribute::OnlineMode, Some(AttrValue::Bool(true))),
+            (
+                Attribute::Modloader,
+                Some(AttrValue::Str("forge".to_string())),
+            ),
+            (Attribute::Country, None),
+        ];
+        let buf = serialize_attribute_updates(&updates).unwrap();
+        assert_eq!(
+            read_attribute_updates(&mut &buf[..]).await.unwrap(),
+            updates
+        );
+
+        for predicate in [
+            AttrPredicate::Eq(AttrValue::Str("1.20.1".to_string())),
+            AttrPredicate::Range {
+                min: None,
+                max: Some(AttrValue::Str("1.8".to_string())),
+            },
+            AttrPredicate::Prefix("1.20".to_string()),
+        ] {
+            let search = AttributeSearch {
+                attribute: Attribute::Version,
+                predicate,
+                limit: 5,
+            };
+            let buf = serialize_attribute_search(&search).unwrap();
+            assert_eq!(read_attribute_search(&mut &buf[..]).await.unwrap(), search);
+        }
+    }
+
+    #[tokio::test]
+    async fn query_request_round_trip() {
+        let request = QueryRequest {
+            query: "cidr 10.0.0.0/8 and online > 10".to_string(),
+            after: Some(Cursor::Server("10.0.0.41:25565".parse().unwrap())),
+            limit: 100,
+        };
+        let buf = serialize_query_request(&request).unwrap();
+        assert_eq!(read_query_request(&mut &buf[..]).await.unwrap(), request);
+    }
+
+    #[tokio::test]
+    async fn related_search_round_trip() {
+        let search = RelatedSearch {
+            window: Some(TimeWindow { from: 10, to: 20 }),
+            limit: 5,
+        };
+        let buf = serialize_related_search(&search).unwrap();
+        assert_eq!(read_related_search(&mut &buf[..]).await.unwrap(), search);
+        let buf = serialize_related_search(&RelatedSearch {
+            window: None,
+            limit: 5,
+        })
+        .unwrap();
+        assert_eq!(
+            read_related_search(&mut &buf[..]).await.unwrap().window,
+            None
+        );
+        assert!(read_related_search(&mut &[5u8, 4, 1][..]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn traversal_requests_round_trip() {
+        let expansion = Expansion {
+            start: NodeKey::Server("10.1.2.3:25565".parse().unwrap()),
+            max_depth: 4,
+            limit: 50,
+        };
+        let buf = serialize_expansion(&expansion).unwrap();
+        assert_eq!(read_expansion(&mut &buf[..]).await.unwrap(), expansion);
+
+        let search = PathSearch {
+            from: Uuid::from_u128(1),
+            to: Uuid::from_u128(2),
+            max_depth: MAX_DEPTH,
+        };
+        let mut buf = serialize_path_search(&search);
+        assert_eq!(read_path_search(&mut &buf[..]).await.unwrap(), search);
+        *buf.last_mut().unwrap() = MAX_DEPTH + 1;
+        assert!(read_path_search(&mut &buf[..]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn stats_report_round_trip() {
+        let mut report = StatsReport {
+            servers: 3,
+            players: 10,
+            uuids: 9,
+            links: 12,
+            seen_last_day: 1,
+            seen_last_week: 2,
+            seen_last_month: 3,
+            ..StatsReport::default()
+        };
+        report.per_slash8.insert(10, 3);
+        report.per_port.insert(25565, 2);
+        report.per_port.insert(25566, 1);
+        report.per_version.insert("1.20.4".to_string(), 2);
+        report.per_protocol.insert(-1, 1);
+        let buf = serialize_stats_report(&report).unwrap();
+        assert_eq!(read_stats_report(&mut &buf[..]).await.unwrap(), report);
+    }
+
+    #[tokio::test]
+    async fn scan_requests_round_trip() {
+        let scan = ServerScan {
+            cidr: "10.0.0.0/8".parse().unwrap(),
+            after: Some(Cursor::Server("10.1.2.3:25565".parse().unwrap())),
+            limit: 100,
+        };
+        let buf = serialize_server_scan(&scan).unwrap();
+        assert_eq!(read_server_scan(&mut &buf[..]).await.unwrap(), scan);
+
+        let scan = PlayerScan {
+            after: None,
+            limit: 5000,
+        };
+        let buf = serialize_player_scan(&scan).unwrap();
+        assert_eq!(
+            read_player_scan(&mut &buf[..]).await.unwrap().limit,
+            MAX_PAGE_LEN
+        );
+    }
+
+    #[tokio::test]
+    async fn resolution_round_trip() {
+        for srv_target in [None, Some("mc.example.net".to_string())] {
+            let resolution = Resolution {
+                hostname: "example.com".to_string(),
+                srv_target,
+                addr: "10.0.0.1:25570".parse().unwrap(),
+            };
+            let buf = serialize_resolution(&resolution).unwrap();
+            assert_eq!(read_resolution(&mut &buf[..]).await.unwrap(), resolution);
+        }
+    }
+
+    #[tokio::test]
+    async fn admin_round_trips() {
+        let mut map = crate::server_map::ServerMap::new();
+        let addr: SocketAddr = "10.0.0.1:25565".parse().unwrap();
+        let players = vec![
+            Player::new("alice".to_string(), Uuid::from_u128(1)),
+            Player::new("alice2".to_string(), Uuid::from_u128(1)),
+        ];
+        let server_id = map.insert(addr, players.clone()).unwrap();
+
+        let server = map.servers[server_id as usize].lock().clone();
+        let buf = serialize_server_info(&server, &map.players).unwrap();
+        let (read_server, read_players) = read_server_info(&mut &buf[..]).unwrap();
+        assert_eq!(read_server.addr, addr);
+        assert_eq!(read_players, players);
+
+        let player_ids = map.find_player(Uuid::from_u128(1)).to_vec();
+        let buf = serialize_player_records(&player_ids, &map.players, &map.servers).unwrap();
+        let records = read_player_records(&mut &buf[..]).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].0.name, "alice2");
+        assert_eq!(records[1].1, vec![addr]);
+
+        let request = RecentRequest {
+            after: None,
+            limit: 10,
+        };
+        let buf = serialize_recent_request(&request).unwrap();
+        assert_eq!(read_recent_request(&mut &buf[..]).await.unwrap(), request);
+        let entries = map.recent.since(request.after, request.limit);
+        let buf = serialize_recent_page(map.recent.latest(), &entries).unwrap();
+        assert_eq!(read_recent_page(&mut &buf[..]).await.unwrap(), (1, entries));
+
+        let mut buf = vec![];
+        write_response(&mut buf, STATUS_ERROR, b"unknown server")
+            .await
+            .unwrap();
+        let err = read_response(&mut &buf[..]).await.unwrap_err();
+        assert_eq!(err.to_string(), "unknown server");
+    }
+}
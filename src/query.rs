@@ -0,0 +1,558 @@
+use std::error::Error;
+use std::fmt::Display;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use crate::attributes::{AttrKind, AttrPredicate, AttrValue, Attribute};
+use crate::cidr::Cidr;
+use crate::player_entry::PlayerId;
+use crate::server_entry::{Server, ServerId};
+use crate::server_map::{NameMatch, ServerMap};
+
+/// Longest query text accepted.
+pub const MAX_QUERY_LEN: usize = 4096;
+
+/// A conjunction of predicates over servers, e.g.
+///
+/// ```text
+/// cidr 5.0.0.0/8 and version ~ 1.20 and online > 10
+///     and player Notch and seen after 1685577600
+/// ```
+///
+/// Terms are joined with `and`:
+///
+/// - `cidr <network>`: the server address is in the network
+/// - `port <op> <n>`: compares the port
+/// - `players <op> <n>`: compares the number of players ever seen
+/// - `player <name>`: a player with this name, ignoring case, was seen
+/// - `seen after <unix time>`: the server was last seen at or after
+/// - `seen before <unix time>`: the server was first seen at or before
+/// - `<attribute> <op> <value>`: compares an attribute, see `Attribute`
+/// - `<attribute> ~ <prefix>`: a string attribute starts with the prefix
+///
+/// `<op>` is one of `=`, `<`, `<=`, `>`, `>=`, and strings with spaces
+/// are written in double quotes. Sightings are not timestamped per
+/// player, so `seen` applies to the server as a whole.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Query {
+    pub predicates: Vec<Predicate>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Predicate {
+    Cidr(Cidr),
+    Port(Cmp, u16),
+    Players(Cmp, u64),
+    Player(String),
+    SeenAfter(u64),
+    SeenBefore(u64),
+    Attribute(Attribute, AttrPredicate),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cmp {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Cmp {
+    pub fn matches<T: Ord>(self, value: T, other: T) -> bool {
+        match self {
+            Cmp::Eq => value == other,
+            Cmp::Lt => value < other,
+            Cmp::Le => value <= other,
+            Cmp::Gt => value > other,
+            Cmp::Ge => value >= other,
+        }
+    }
+}
+
+/// How `Query::execute` finds its candidate servers before every
+/// predicate is checked on them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Plan {
+    /// The servers of the players matching a `player` term.
+    PlayerIndex(String),
+    /// A secondary index lookup for an attribute term.
+    AttributeIndex(Attribute),
+    /// The servers in the narrowest `cidr` term.
+    Cidr(Cidr),
+    /// Every server.
+    FullScan,
+}
+
+impl Display for Plan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Plan::PlayerIndex(name) => write!(f, "player index ({name})"),
+            Plan::AttributeIndex(attribute) => write!(f, "{attribute} index"),
+            Plan::Cidr(cidr) => write!(f, "cidr scan ({cidr})"),
+            Plan::FullScan => f.write_str("full scan"),
+        }
+    }
+}
+
+/// One page of `Query::execute`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct QueryPage {
+    /// Matching servers in address order.
+    pub servers: Vec<ServerId>,
+    /// Pass as `after` to get the next page, or `None` on the last page.
+    pub next: Option<SocketAddr>,
+}
+
+impl Query {
+    pub fn parse(s: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        if s.len() > MAX_QUERY_LEN {
+            return Err(format!("query is {} bytes long", s.len()).into());
+        }
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let mut query = Query::default();
+        if parser.peek().is_none() {
+            return Ok(query);
+        }
+        loop {
+            query.predicates.push(parser.predicate()?);
+            match parser.next() {
+                None => return Ok(query),
+                Some(Token::Word(word)) if word.eq_ignore_ascii_case("and") => {}
+                Some(token) => return Err(format!("expected `and`, found {token}").into()),
+            }
+        }
+    }
+
+    /// Picks the cheapest way to find candidates: a player join, then the
+    /// smallest secondary index result, then the narrowest network.
+    /// Candidates are returned in address order.
+    pub fn plan(
+        &self,
+        map: &ServerMap,
+    ) -> Result<(Plan, Vec<ServerId>), Box<dyn Error + Send + Sync>> {
+        let mut best: Option<(Plan, Vec<ServerId>)> = None;
+        for predicate in &self.predicates {
+            if let Predicate::Player(name) = predicate {
+                let candidates = player_servers(map, name)?;
+                if best
+                    .as_ref()
+                    .is_none_or(|(_, best)| candidates.len() < best.len())
+                {
+                    best = Some((Plan::PlayerIndex(name.clone()), candidates));
+                }
+            }
+        }
+        if best.is_none() {
+            for predicate in &self.predicates {
+                if let Predicate::Attribute(attribute, predicate) = predicate {
+                    if !map.attribute_indexes.contains_key(attribute) {
+                        continue;
+                    }
+                    let candidates = map.find_by_attribute(*attribute, predicate)?;
+                    if best
+                        .as_ref()
+                        .is_none_or(|(_, best)| candidates.len() < best.len())
+                    {
+                        best = Some((Plan::AttributeIndex(*attribute), candidates));
+                    }
+                }
+            }
+        }
+        if let Some((plan, mut candidates)) = best {
+            candidates.sort_unstable_by_key(|server_id| map.servers[*server_id as usize].addr());
+            return Ok((plan, candidates));
+        }
+
+        let narrowest = self
+            .predicates
+            .iter()
+            .filter_map(|predicate| match predicate {
+                Predicate::Cidr(cidr) => Some(*cidr),
+                _ => None,
+            })
+            .max_by_key(|cidr| cidr.prefix_len());
+        Ok(match narrowest {
+            Some(cidr) => (Plan::Cidr(cidr), map.servers_in(cidr)),
+            None => (Plan::FullScan, map.servers_in(Cidr::ALL)),
+        })
+    }
+
+    /// Runs the query, returning at most `limit` servers after the
+    /// address `after`, the `next` of a previous page. `after` does not
+    /// have to be a known server, so paging survives inserts, deletes
+    /// and restarts.
+    pub fn execute(
+        &self,
+        map: &ServerMap,
+        after: Option<SocketAddr>,
+        limit: usize,
+    ) -> Result<QueryPage, Box<dyn Error + Send + Sync>> {
+        let (_, candidates) = self.plan(map)?;
+        let mut player_sets = vec![];
+        for predicate in &self.predicates {
+            if let Predicate::Player(name) = predicate {
+                player_sets.push(player_ids(map, name)?);
+            }
+        }
+
+        let start = match after {
+            Some(after) => candidates
+                .partition_point(|server_id| map.servers[*server_id as usize].addr() <= after),
+            None => 0,
+        };
+        let mut page = QueryPage::default();
+        let mut last = None;
+        for server_id in &candidates[start..] {
+            let server = map.servers[*server_id as usize].lock();
+            if !self.matches(&server, &player_sets) {
+                continue;
+            }
+            if page.servers.len() == limit {
+                page.next = last;
+                break;
+            }
+            page.servers.push(*server_id);
+            last = Some(server.addr);
+        }
+        Ok(page)
+    }
+
+    /// `player_sets` holds the sorted ids of the players of each `player`
+    /// term, in order.
+    fn matches(&self, server: &Server, player_sets: &[Vec<PlayerId>]) -> bool {
+        let mut player_sets = player_sets.iter();
+        self.predicates.iter().all(|predicate| match predicate {
+            Predicate::Cidr(cidr) => match server.addr.ip() {
+                IpAddr::V4(ip) => cidr.contains(ip),
+                IpAddr::V6(_) => false,
+            },
+            Predicate::Port(cmp, port) => cmp.matches(server.addr.port(), *port),
+            Predicate::Players(cmp, count) => cmp.matches(server.players.len() as u64, *count),
+            Predicate::Player(_) => {
+                let player_ids = player_sets.next().expect("one player set per player term");
+                player_ids
+                    .iter()
+                    .any(|player_id| server.players.binary_search(player_id).is_ok())
+            }
+            Predicate::SeenAfter(time) => server.last_seen >= *time,
+            Predicate::SeenBefore(time) => server.first_seen != 0 && server.first_seen <= *time,
+            Predicate::Attribute(attribute, predicate) => server
+                .attributes
+                .get(attribute)
+                .is_some_and(|value| predicate.matches(value)),
+        })
+    }
+}
+
+impl FromStr for Query {
+    type Err = Box<dyn Error + Send + Sync>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Query::parse(s)
+    }
+}
+
+fn player_ids(map: &ServerMap, name: &str) -> Result<Vec<PlayerId>, Box<dyn Error + Send + Sync>> {
+    let mut page = map.search_names(name, NameMatch::Exact, None, usize::MAX);
+    page.players.sort_unstable();
+    Ok(page.players)
+}
+
+fn player_servers(
+    map: &ServerMap,
+    name: &str,
+) -> Result<Vec<ServerId>, Box<dyn Error + Send + Sync>> {
+    let mut servers = vec![];
+    for player_id in player_ids(map, name)? {
+        servers.extend(
+            map.players[player_id as usize]
+                .lock()
+                .servers
+                .iter()
+                .copied(),
+        );
+    }
+    servers.sort_unstable();
+    servers.dedup();
+    Ok(servers)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Op(String),
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "`{word}`"),
+            Token::Quoted(string) => write!(f, "{string:?}"),
+            Token::Op(op) => write!(f, "`{op}`"),
+        }
+    }
+}
+
+const OP_CHARS: &[char] = &['=', '<', '>', '~'];
+
+fn tokenize(s: &str) -> Result<Vec<Token>, Box<dyn Error + Send + Sync>> {
+    let mut tokens = vec![];
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut string = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => string.push(chars.next().ok_or("unterminated string")?),
+                    Some(c) => string.push(c),
+                    None => return Err("unterminated string".into()),
+                }
+            }
+            tokens.push(Token::Quoted(string));
+        } else if OP_CHARS.contains(&c) {
+            let mut op = String::new();
+            while let Some(&c) = chars.peek() {
+                if !OP_CHARS.contains(&c) {
+                    break;
+                }
+                op.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Op(op));
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == '"' || OP_CHARS.contains(&c) {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Word(word));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    /// A bare word or a quoted string.
+    fn value(&mut self, what: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+        match self.next() {
+            Some(Token::Word(value) | Token::Quoted(value)) => Ok(value),
+            Some(token) => Err(format!("expected {what}, found {token}").into()),
+            None => Err(format!("expected {what}").into()),
+        }
+    }
+
+    fn op(&mut self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        match self.next() {
+            Some(Token::Op(op)) => Ok(op),
+            Some(token) => Err(format!("expected an operator, found {token}").into()),
+            None => Err("expected an operator".into()),
+        }
+    }
+
+    fn cmp(&mut self) -> Result<Cmp, Box<dyn Error + Send + Sync>> {
+        let op = self.op()?;
+        Ok(match op.as_str() {
+            "=" | "==" => Cmp::Eq,
+            "<" => Cmp::Lt,
+            "<=" => Cmp::Le,
+            ">" => Cmp::Gt,
+            ">=" => Cmp::Ge,
+            _ => return Err(format!("unknown operator `{op}`").into()),
+        })
+    }
+
+    fn predicate(&mut self) -> Result<Predicate, Box<dyn Error + Send + Sync>> {
+        let keyword = match self.next() {
+            Some(Token::Word(word)) => word.to_ascii_lowercase(),
+            Some(token) => return Err(format!("expected a term, found {token}").into()),
+            None => return Err("expected a term".into()),
+        };
+        Ok(match keyword.as_str() {
+            "cidr" => Predicate::Cidr(self.value("a network")?.parse()?),
+            "port" => {
+                let cmp = self.cmp()?;
+                Predicate::Port(cmp, self.value("a port")?.parse()?)
+            }
+            "players" => {
+                let cmp = self.cmp()?;
+                Predicate::Players(cmp, self.value("a player count")?.parse()?)
+            }
+            "player" => Predicate::Player(self.value("a player name")?),
+            "seen" => match self.value("`after` or `before`")?.as_str() {
+                "after" => Predicate::SeenAfter(self.value("a unix time")?.parse()?),
+                "before" => Predicate::SeenBefore(self.value("a unix time")?.parse()?),
+                word => return Err(format!("expected `after` or `before`, found `{word}`").into()),
+            },
+            _ => {
+                let attribute: Attribute = keyword.parse()?;
+                let op = self.op()?;
+                let value = self.value("a value")?;
+                Predicate::Attribute(attribute, attribute_predicate(attribute, &op, &value)?)
+            }
+        })
+    }
+}
+
+/// Attribute predicates are inclusive, so strict comparisons are only
+/// supported on integers, where they can be made inclusive.
+fn attribute_predicate(
+    attribute: Attribute,
+    op: &str,
+    value: &str,
+) -> Result<AttrPredicate, Box<dyn Error + Send + Sync>> {
+    if op == "~" {
+        if attribute.kind() != AttrKind::Str {
+            return Err(format!("`~` needs a string attribute, {attribute} is not one").into());
+        }
+        return Ok(AttrPredicate::Prefix(value.to_string()));
+    }
+    let value = attribute.parse_value(value)?;
+    let min = |value| AttrPredicate::Range {
+        min: Some(value),
+        max: None,
+    };
+    let max = |value| AttrPredicate::Range {
+        min: None,
+        max: Some(value),
+    };
+    Ok(match (op, value) {
+        ("=" | "==", value) => AttrPredicate::Eq(value),
+        ("<=", value) if attribute.kind() != AttrKind::Bool => max(value),
+        (">=", value) if attribute.kind() != AttrKind::Bool => min(value),
+        ("<", AttrValue::Int(value)) => max(AttrValue::Int(
+            value.checked_sub(1).ok_or("value out of range")?,
+        )),
+        (">", AttrValue::Int(value)) => min(AttrValue::Int(
+            value.checked_add(1).ok_or("value out of range")?,
+        )),
+        _ => return Err(format!("`{op}` is not supported on {attribute}").into()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::player_entry::Player;
+
+    fn map() -> ServerMap {
+        let mut map = ServerMap::new();
+        let notch = Player::new("Notch".to_string(), Uuid::from_u128(1));
+        let servers = [
+            ("5.1.0.1:25565", "1.20.1", 20, true),
+            ("5.2.0.1:25565", "1.20.4", 5, true),
+            ("5.3.0.1:25566", "1.19.4", 50, true),
+            ("6.0.0.1:25565", "1.20.1", 30, true),
+            ("5.4.0.1:25565", "1.20.2", 40, false),
+        ];
+        for (addr, version, online, with_notch) in servers {
+            let players = if with_notch {
+                vec![notch.clone()]
+            } else {
+                vec![]
+            };
+            let server_id = map.insert(addr.parse().unwrap(), players).unwrap();
+            map.set_attribute(
+                server_id,
+                Attribute::Version,
+                Some(AttrValue::Str(version.to_string())),
+            )
+            .unwrap();
+            map.set_attribute(server_id, Attribute::Online, Some(AttrValue::Int(online)))
+                .unwrap();
+        }
+        map
+    }
+
+    #[test]
+    fn parse_and_execute() {
+        let map = map();
+        let query: Query =
+            "cidr 5.0.0.0/8 AND version ~ \"1.20\" and online > 10 and player notch and seen after 0"
+                .parse()
+                .unwrap();
+        assert_eq!(query.predicates.len(), 5);
+        assert_eq!(
+            query.plan(&map).unwrap().0,
+            Plan::PlayerIndex("notch".to_string())
+        );
+        assert_eq!(query.execute(&map, None, 10).unwrap().servers, vec![0]);
+
+        let query: Query = "version ~ 1.20 and port = 25565".parse().unwrap();
+        assert_eq!(
+            query.plan(&map).unwrap().0,
+            Plan::AttributeIndex(Attribute::Version)
+        );
+        let page = query.execute(&map, None, 2).unwrap();
+        assert_eq!(
+            page,
+            QueryPage {
+                servers: vec![0, 1],
+                next: Some("5.2.0.1:25565".parse().unwrap())
+            }
+        );
+        let page = query.execute(&map, page.next, 2).unwrap();
+        assert_eq!(
+            page,
+            QueryPage {
+                servers: vec![4, 3],
+                next: None
+            }
+        );
+        // an address that isn't a server still resumes in place
+        let after = Some("5.3.0.0:1".parse().unwrap());
+        assert_eq!(query.execute(&map, after, 10).unwrap().servers, vec![4, 3]);
+
+        let query: Query = "cidr 5.0.0.0/8 and cidr 5.3.0.0/16".parse().unwrap();
+        assert_eq!(
+            query.plan(&map).unwrap().0,
+            Plan::Cidr("5.3.0.0/16".parse().unwrap())
+        );
+        assert_eq!(query.execute(&map, None, 10).unwrap().servers, vec![2]);
+
+        let query: Query = "".parse().unwrap();
+        assert_eq!(query.plan(&map).unwrap().0, Plan::FullScan);
+        assert_eq!(query.execute(&map, None, 10).unwrap().servers.len(), 5);
+
+        for bad in [
+            "cidr",
+            "port 25565",
+            "colour = red",
+            "online ~ 1",
+            "version > 1.20",
+            "cidr 5.0.0.0/8 or port = 1",
+            "player \"notch",
+            "port != 1",
+        ] {
+            assert!(bad.parse::<Query>().is_err(), "{bad}");
+        }
+        let err = "port != 1".parse::<Query>().unwrap_err().to_string();
+        assert_eq!(err, "expected an operator, found `!`");
+    }
+}
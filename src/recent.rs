@@ -0,0 +1,85 @@
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+
+use serde::Serialize;
+
+/// Number of sightings kept by `Recent`.
+pub const RECENT_LEN: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RecentInsert {
+    /// Position of the sighting among all sightings, starting at 1.
+    pub seq: u64,
+    /// Unix time of the sighting.
+    pub time: u64,
+    pub addr: SocketAddr,
+    /// Number of players in the sighting.
+    pub players: usize,
+}
+
+/// The latest `RECENT_LEN` sightings, numbered so a reader can poll for
+/// the ones after the last it saw.
+#[derive(Debug, Default)]
+pub struct Recent {
+    entries: VecDeque<RecentInsert>,
+    latest: u64,
+}
+
+impl Recent {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, time: u64, addr: SocketAddr, players: usize) {
+        if self.entries.len() == RECENT_LEN {
+            self.entries.pop_front();
+        }
+        self.latest += 1;
+        self.entries.push_back(RecentInsert {
+            seq: self.latest,
+            time,
+            addr,
+            players,
+        });
+    }
+
+    /// Number of the latest sighting, or 0 if there was none.
+    pub fn latest(&self) -> u64 {
+        self.latest
+    }
+
+    /// Up to `limit` sightings, oldest first: the first ones numbered
+    /// after `after`, or the latest ones if `after` is `None`.
+    pub fn since(&self, after: Option<u64>, limit: usize) -> Vec<RecentInsert> {
+        let start = match after {
+            Some(after) => self.entries.partition_point(|entry| entry.seq <= after),
+            None => self.entries.len().saturating_sub(limit),
+        };
+        self.entries.range(start..).take(limit).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_latest_sightings() {
+        let mut recent = Recent::new();
+        let addr: SocketAddr = "10.0.0.1:25565".parse().unwrap();
+        for time in 0..RECENT_LEN as u64 + 10 {
+            recent.push(time, addr, 1);
+        }
+        assert_eq!(recent.latest(), RECENT_LEN as u64 + 10);
+
+        let seqs = |entries: Vec<RecentInsert>| -> Vec<u64> {
+            entries.into_iter().map(|entry| entry.seq).collect()
+        };
+        let latest = recent.latest();
+        assert_eq!(seqs(recent.since(None, 2)), vec![latest - 1, latest]);
+        assert_eq!(seqs(recent.since(Some(latest - 1), 5)), vec![latest]);
+        assert!(recent.since(Some(latest), 5).is_empty());
+        // sightings that fell out are skipped
+        assert_eq!(seqs(recent.since(Some(1), 2)), vec![11, 12]);
+    }
+}
@@ -1,90 +1,218 @@
 use std::{
     cmp::Ordering,
-    collections::BTreeSet,
+    collections::BTreeMap,
     error::Error,
     fmt::Debug,
     io::{Read, Write},
-    net::SocketAddr,
-    str::FromStr,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
     sync::Arc,
 };
 
 use integer_encoding::{VarIntReader, VarIntWriter};
+use uuid::Uuid;
 
-use crate::player_entry::PlayerArcWrapper;
+use crate::attributes::{AttrValue, Attribute};
+use crate::player_entry::{Player, PlayerArcWrapper, PlayerId};
+
+/// Index of a server in `ServerMap::servers`.
+pub type ServerId = u32;
 
 #[derive(Debug, Clone)]
 pub struct Server {
     pub addr: SocketAddr,
-    pub players: BTreeSet<PlayerArcWrapper>,
+    /// Sorted ids of every player seen on this server.
+    pub players: Vec<PlayerId>,
+    /// Unix time of the first sighting, or 0 if it was never sighted.
+    pub first_seen: u64,
+    /// Unix time of the latest sighting, or 0 if it was never sighted.
+    pub last_seen: u64,
+    /// Sorted tags naming where the server was learned from, e.g. the
+    /// `servers.dat` it was imported from.
+    pub sources: Vec<String>,
+    /// Sorted hostnames known to lead to this server, directly or
+    /// through a `_minecraft._tcp` SRV record.
+    pub hostnames: Vec<String>,
+    /// Sorted SRV targets that point at this server.
+    pub srv_targets: Vec<String>,
+    /// Latest MOTD, formatting codes included, or empty if unknown.
+    pub motd: String,
+    /// Latest known value of each attribute.
+    pub attributes: BTreeMap<Attribute, AttrValue>,
 }
 
 impl Server {
-    pub fn deserialize_pointer(mut buf: &[u8]) -> Result<Self, Box<dyn Error + Send + Sync>> {
-        let bytes_size = buf.read_varint()?;
-        let mut bytes = vec![0u8; bytes_size];
-        buf.read_exact(&mut bytes)?;
-        let addr_string = std::str::from_utf8(&bytes)?;
-        let addr = SocketAddr::from_str(addr_string)?;
-        let players: BTreeSet<PlayerArcWrapper> = BTreeSet::new();
-        Ok(Server { addr, players })
+    pub fn new(addr: SocketAddr) -> Self {
+        Server {
+            addr,
+            players: vec![],
+            first_seen: 0,
+            last_seen: 0,
+            sources: vec![],
+            hostnames: vec![],
+            srv_targets: vec![],
+            motd: String::new(),
+            attributes: BTreeMap::new(),
+        }
+    }
+
+    pub fn deserialize_pointer(
+        buf: &mut impl Read,
+    ) -> Result<SocketAddr, Box<dyn Error + Send + Sync>> {
+        let mut addr_buf = [0u8; 6];
+        buf.read_exact(&mut addr_buf)?;
+        Ok(SocketAddr::V4(SocketAddrV4::new(
+            Ipv4Addr::new(addr_buf[0], addr_buf[1], addr_buf[2], addr_buf[3]),
+            u16::from_be_bytes([addr_buf[4], addr_buf[5]]),
+        )))
+    }
+
+    /// Reads a server record, returning the server (with no players) and
+    /// the uuids of its players. The loader links servers from the player
+    /// records and checks these uuids against them.
+    pub fn deserialize(
+        buf: &mut impl Read,
+    ) -> Result<(Server, Vec<Uuid>), Box<dyn Error + Send + Sync>> {
+        let mut server = Server::new(Server::deserialize_pointer(buf)?);
+        server.first_seen = buf.read_varint()?;
+        server.last_seen = buf.read_varint()?;
+        server.sources = read_strings(buf)?;
+        server.hostnames = read_strings(buf)?;
+        server.srv_targets = read_strings(buf)?;
+        server.motd = read_string(buf)?;
+        let num_attributes: usize = buf.read_varint()?;
+        for _ in 0..num_attributes {
+            let mut id = [0u8; 1];
+            buf.read_exact(&mut id)?;
+            let attribute = Attribute::from_id(id[0])
+                .ok_or_else(|| format!("unknown attribute id {}", id[0]))?;
+            let value = AttrValue::deserialize(buf, attribute.kind())?;
+            server.attributes.insert(attribute, value);
+        }
+        let num_players: usize = buf.read_varint()?;
+        let mut players = Vec::with_capacity(num_players.min(1024));
+        for _ in 0..num_players {
+            players.push(Player::deserialize_pointer(buf)?);
+        }
+        Ok((server, players))
     }
 
     /*--- Server -------------------------------------------|
     | field name        | type              | size          |
     |-------------------------------------------------------|
-    | address length    | varint            | variable size |
-    | server address    | string            | variable size |
+    | server address    | ServerPointer     | 6 bytes       |
+    | first seen        | varint            | variable size |
+    | last seen         | varint            | variable size |
+    | sources           | StringList        | variable size |
+    | hostnames         | StringList        | variable size |
+    | srv targets       | StringList        | variable size |
+    | motd length       | varint            | variable size |
+    | motd              | string            | variable size |
+    | num attributes    | varint            | variable size |
+    | attribute list    | ServerAttribute[] | variable size |
     | players length    | varint            | variable size |
-    | player list       | PlayerPointer[]   | variable size |
+    | player list       | PlayerPointer[]   | 16 bytes each |
     |------------------------------------------------------*/
-    pub fn serialize(&self) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
-        let mut res = vec![];
-        let addr_string = self.addr.to_string();
-        let addr_bytes = addr_string.as_bytes();
-        res.write_varint(addr_bytes.len())?;
-        res.write_all(addr_bytes)?;
+    pub fn serialize(
+        &self,
+        players: &[PlayerArcWrapper],
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let mut res = self.serialize_pointer()?;
+        res.write_varint(self.first_seen)?;
+        res.write_varint(self.last_seen)?;
+        write_strings(&mut res, &self.sources)?;
+        write_strings(&mut res, &self.hostnames)?;
+        write_strings(&mut res, &self.srv_targets)?;
+        res.write_varint(self.motd.len())?;
+        res.write_all(self.motd.as_bytes())?;
+        res.write_varint(self.attributes.len())?;
+        for (attribute, value) in &self.attributes {
+            res.write_all(&[attribute.id()])?;
+            value.serialize(&mut res)?;
+        }
         res.write_varint(self.players.len())?;
-        let server_players = self.players.iter();
-        for player in server_players {
-            let player_bytes = player.lock().serialize_pointer()?;
-            res.write_varint(player_bytes.len())?;
-            res.write_all(&player_bytes)?;
+        for player_id in &self.players {
+            let player = players
+                .get(*player_id as usize)
+                .ok_or_else(|| format!("server {} has unknown player id {player_id}", self.addr))?;
+            res.write_all(&player.serialize_pointer()?)?;
         }
         Ok(res)
     }
 
+    /*--- Server Attribute -----------------------------|
+    | field name    | type              | size          |
+    |---------------------------------------------------|
+    | attribute id  | u8                | 1 byte        |
+    | value         | AttributeValue    | variable size |
+    |--------------------------------------------------*/
+
     /*--- Server Pointer ---------------------------|
     | field name        | type      | size          |
     |-----------------------------------------------|
-    | address length    | varint    | variable size |
-    | server address    | string    | variable size |
+    | ipv4 address      | u8[4]     | 4 bytes       |
+    | port              | u16 (BE)  | 2 bytes       |
     |----------------------------------------------*/
     pub fn serialize_pointer(&self) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
-        let mut res = vec![];
-        let addr_string = self.addr.to_string();
-        let addr_bytes = addr_string.as_bytes();
-        res.write_varint(addr_bytes.len())?;
-        res.write_all(addr_bytes)?;
-        Ok(res)
+        serialize_addr_pointer(self.addr)
+    }
+
+    /// Records a sighting at unix time `timestamp`.
+    pub fn seen(&mut self, timestamp: u64) {
+        if self.first_seen == 0 || timestamp < self.first_seen {
+            self.first_seen = timestamp;
+        }
+        self.last_seen = self.last_seen.max(timestamp);
     }
 
+    /// Merges everything but the player list of `other` into this server.
     pub fn update(&mut self, other: &Server) {
-        // println!(
-        //     "[Server] Merging self '{:?}' with other '{:?}'",
-        //     self, other
-        // );
-        let self_list = &mut self.players;
-        for player in &other.players {
-            let take = self_list.take(player);
-            match take {
-                Some(mut_player) => {
-                    mut_player.lock().update(&player.lock());
-                    self_list.insert(mut_player);
-                }
-                None => {
-                    self_list.insert(player.clone());
-                }
+        if other.first_seen != 0 {
+            self.seen(other.first_seen);
+        }
+        if other.last_seen != 0 {
+            self.seen(other.last_seen);
+        }
+        for source in &other.sources {
+            self.add_source(source);
+        }
+        for hostname in &other.hostnames {
+            self.add_hostname(hostname);
+        }
+        for target in &other.srv_targets {
+            self.add_srv_target(target);
+        }
+        if !other.motd.is_empty() {
+            self.motd = other.motd.clone();
+        }
+        for (attribute, value) in &other.attributes {
+            self.attributes.insert(*attribute, value.clone());
+        }
+    }
+
+    /// Tags this server with `source`, returning `false` if it already was.
+    pub fn add_source(&mut self, source: &str) -> bool {
+        insert_sorted(&mut self.sources, source)
+    }
+
+    /// Records a hostname of this server, returning `false` if it was
+    /// already known.
+    pub fn add_hostname(&mut self, hostname: &str) -> bool {
+        insert_sorted(&mut self.hostnames, hostname)
+    }
+
+    /// Records an SRV target of this server, returning `false` if it was
+    /// already known.
+    pub fn add_srv_target(&mut self, target: &str) -> bool {
+        insert_sorted(&mut self.srv_targets, target)
+    }
+
+    /// Links a player to this server, returning `false` if it was already linked.
+    pub fn add_player(&mut self, player: PlayerId) -> bool {
+        match self.players.binary_search(&player) {
+            Ok(_) => false,
+            Err(index) => {
+                self.players.insert(index, player);
+                true
             }
         }
     }
@@ -110,34 +238,109 @@ impl Ord for Server {
     }
 }
 
+/// Shared handle to a server. Comparisons use the address captured at
+/// construction, so they never take the lock; the address of the inner
+/// server must not be changed afterwards.
 #[derive(Debug, Clone)]
-pub struct ServerArcWrapper(Arc<parking_lot::Mutex<Server>>);
+pub struct ServerArcWrapper {
+    addr: SocketAddr,
+    inner: Arc<parking_lot::Mutex<Server>>,
+}
 
 impl ServerArcWrapper {
     pub fn new(server: Server) -> Self {
-        Self(Arc::new(parking_lot::Mutex::new(server)))
+        Self {
+            addr: server.addr,
+            inner: Arc::new(parking_lot::Mutex::new(server)),
+        }
+    }
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+    /// Same as `Server::serialize_pointer`, without locking the server.
+    pub fn serialize_pointer(&self) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        serialize_addr_pointer(self.addr)
     }
     pub fn lock(&self) -> parking_lot::lock_api::MutexGuard<'_, parking_lot::RawMutex, Server> {
-        self.0.lock()
+        self.inner.lock()
     }
 }
 
 impl PartialOrd for ServerArcWrapper {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.0.lock().partial_cmp(&other.0.lock())
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for ServerArcWrapper {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.0.lock().cmp(&other.0.lock())
+        self.addr.cmp(&other.addr)
     }
 }
 
 impl PartialEq for ServerArcWrapper {
     fn eq(&self, other: &Self) -> bool {
-        self.0.lock().eq(&other.0.lock())
+        self.addr == other.addr
     }
 }
 
 impl Eq for ServerArcWrapper {}
+
+fn serialize_addr_pointer(addr: SocketAddr) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let octets = match addr.ip() {
+        IpAddr::V4(addr) => addr.octets(),
+        _ => return Err("Not an IPv4 Address".into()),
+    };
+    let mut res = Vec::with_capacity(6);
+    res.write_all(&octets)?;
+    res.write_all(&addr.port().to_be_bytes())?;
+    Ok(res)
+}
+
+fn insert_sorted(list: &mut Vec<String>, value: &str) -> bool {
+    match list.binary_search_by(|item| item.as_str().cmp(value)) {
+        Ok(_) => false,
+        Err(index) => {
+            list.insert(index, value.to_string());
+            true
+        }
+    }
+}
+
+/// Reads a length-prefixed string. The length comes from disk, so the
+/// buffer only grows as bytes actually arrive.
+pub(crate) fn read_string(buf: &mut impl Read) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let len: u64 = buf.read_varint()?;
+    let mut string = vec![];
+    buf.take(len).read_to_end(&mut string)?;
+    if string.len() as u64 != len {
+        return Err("string runs past the end of the record".into());
+    }
+    Ok(String::from_utf8(string)?)
+}
+
+fn read_strings(buf: &mut impl Read) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    let len: usize = buf.read_varint()?;
+    let mut res = Vec::with_capacity(len.min(64));
+    for _ in 0..len {
+        res.push(read_string(buf)?);
+    }
+    Ok(res)
+}
+
+/*--- String List ----------------------------------|
+| field name    | type              | size          |
+|---------------------------------------------------|
+| list length   | varint            | variable size |
+| string length | varint            | variable size |
+| string        | string            | variable size |
+| ...           | repeats per entry |               |
+|--------------------------------------------------*/
+fn write_strings(buf: &mut Vec<u8>, list: &[String]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    buf.write_varint(list.len())?;
+    for string in list {
+        buf.write_varint(string.len())?;
+        buf.write_all(string.as_bytes())?;
+    }
+    Ok(())
+}
@@ -1,20 +1,56 @@
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
-use std::net::{IpAddr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::ops::Bound;
 use std::sync::Arc;
 
 use parking_lot::Mutex;
+use uuid::Uuid;
 
-use crate::player_entry::Player;
-use crate::server_entry::{Server, ServerArcWrapper};
-
-const PRE_RESERVE: bool = false;
+use crate::attributes::{check_value, AttrPredicate, AttrValue, Attribute, AttributeIndex};
+use crate::cidr::Cidr;
+use crate::hostname::{normalize_hostname, Resolution, MAX_HOSTNAME_LEN};
+use crate::metrics::METRICS;
+use crate::motd::{strip_formatting, MotdIndex, MotdMatch, MAX_MOTD_LEN};
+use crate::player_entry::{Player, PlayerArcWrapper, PlayerId};
+use crate::recent::Recent;
+use crate::server_entry::{Server, ServerArcWrapper, ServerId};
+use crate::stats::{Stats, StatsReport, DAY, MONTH, WEEK};
 
 #[derive(Debug)]
 pub struct ServerMap {
     #[allow(clippy::type_complexity)]
-    pub server_array: HashMap<u16, Arc<Mutex<HashMap<u16, HashMap<u16, ServerArcWrapper>>>>>,
-    pub player_array: BTreeSet<Player>,
+    pub server_array: HashMap<u16, Arc<Mutex<HashMap<u16, HashMap<u16, ServerId>>>>>,
+    pub player_array: BTreeMap<(String, Uuid), PlayerId>,
+    /// Every player keyed by case-folded name, for case-insensitive and
+    /// prefix searches. The uuid and then the exact name break ties, so
+    /// every key is unique and a search can resume after any of them.
+    pub name_index: BTreeMap<(String, Uuid, String), PlayerId>,
+    /// Ids of every record of each player. A player has one record per
+    /// name it was seen with.
+    pub uuid_index: HashMap<Uuid, Vec<PlayerId>>,
+    /// Every server, indexed by `ServerId`.
+    pub servers: Vec<ServerArcWrapper>,
+    /// Every player, indexed by `PlayerId`.
+    pub players: Vec<PlayerArcWrapper>,
+    /// Sorted ids of the servers each normalized hostname or SRV target
+    /// leads to.
+    pub hostname_index: HashMap<String, Vec<ServerId>>,
+    pub motd_index: MotdIndex,
+    /// Secondary indexes of the declared attributes. Attributes without
+    /// an index are still stored on the servers, but are found by a scan.
+    pub attribute_indexes: BTreeMap<Attribute, AttributeIndex>,
+    pub stats: Stats,
+    /// Latest sightings of `insert` and `insert_batch`.
+    pub recent: Recent,
+    /// Reserve room for every /24 of a /16 when its shard is created,
+    /// trading memory for fewer rehashes on dense ranges.
+    pub pre_reserve: bool,
+    /// Most servers held, or 0 for no limit. Creating a server past the
+    /// limit fails.
+    pub max_servers: usize,
+    /// Most player records held, or 0 for no limit.
+    pub max_players: usize,
 }
 
 impl ServerMap {
@@ -23,86 +59,525 @@ impl ServerMap {
         alloc_hashmap.reserve(65536);
         ServerMap {
             server_array: alloc_hashmap,
-            player_array: BTreeSet::new(),
+            player_array: BTreeMap::new(),
+            name_index: BTreeMap::new(),
+            uuid_index: HashMap::new(),
+            servers: vec![],
+            players: vec![],
+            hostname_index: HashMap::new(),
+            motd_index: MotdIndex::new(),
+            attribute_indexes: Attribute::ALL
+                .into_iter()
+                .map(|attribute| (attribute, AttributeIndex::new()))
+                .collect(),
+            stats: Stats::new(),
+            recent: Recent::new(),
+            pre_reserve: false,
+            max_servers: 0,
+            max_players: 0,
         }
     }
 
+    /// Records a sighting of `players` on the server at `addr`, creating
+    /// the server and any unknown players.
     pub fn insert(
         &mut self,
-        server_arc: ServerArcWrapper,
-    ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let mut server = server_arc.lock();
-        let octets: [u8; 4] = match server.addr.ip() {
-            IpAddr::V4(addr) => addr.octets(),
-            _ => return Err("Not an IPv4 Address".into()),
-        };
+        addr: SocketAddr,
+        players: impl IntoIterator<Item = Player>,
+    ) -> Result<ServerId, Box<dyn Error + Send + Sync>> {
+        let server_id = self.insert_server(addr)?;
+        let now = unix_now();
+        self.seen(server_id, now);
+        let mut num_players = 0;
+        for player in players {
+            let player_id = self.insert_player(player)?;
+            self.link(server_id, player_id);
+            num_players += 1;
+        }
+        self.recent.push(now, addr, num_players);
+        Ok(server_id)
+    }
 
-        let a = u8s_to_u16(octets[0], octets[1]);
-        let b = u8s_to_u16(octets[2], octets[3]);
+    /// Records many sightings at once. Servers are grouped by /16 so each
+    /// shard is locked once, and each server record is locked once for
+    /// all of its players.
+    pub fn insert_batch(
+        &mut self,
+        sightings: impl IntoIterator<Item = (SocketAddr, Vec<Player>)>,
+    ) -> Result<BatchSummary, Box<dyn Error + Send + Sync>> {
+        let mut summary = BatchSummary::default();
+        let players_before = self.players.len();
+        let now = unix_now();
 
-        let open1 = &mut self.server_array;
-        open1.entry(a).or_insert_with(|| {
-            let mut alloc_hashmap = HashMap::new();
-            if PRE_RESERVE {
-                alloc_hashmap.reserve(65536);
+        let mut shards: HashMap<u16, Vec<(u16, SocketAddr, Vec<Player>)>> = HashMap::new();
+        for (addr, players) in sightings {
+            let (a, b) = split_addr(addr)?;
+            shards.entry(a).or_default().push((b, addr, players));
+        }
+
+        for (a, shard_sightings) in shards {
+            let pre_reserve = self.pre_reserve;
+            let open2 = self
+                .server_array
+                .entry(a)
+                .or_insert_with(|| new_shard(pre_reserve))
+                .clone();
+
+            let mut resolved = Vec::with_capacity(shard_sightings.len());
+            {
+                let mut open3 = METRICS.lock(&open2, &METRICS.shard_lock_wait);
+                for (b, addr, players) in shard_sightings {
+                    let open4 = open3.entry(b).or_default();
+                    let server_id = match open4.get(&addr.port()) {
+                        Some(server_id) => *server_id,
+                        None => {
+                            check_limit(self.servers.len(), self.max_servers, "servers")?;
+                            let server_id = next_id(self.servers.len())?;
+                            self.servers.push(ServerArcWrapper::new(Server::new(addr)));
+                            open4.insert(addr.port(), server_id);
+                            self.stats.add_server(addr);
+                            summary.new_servers += 1;
+                            server_id
+                        }
+                    };
+                    self.recent.push(now, addr, players.len());
+                    resolved.push((server_id, players));
+                }
             }
-            Arc::new(Mutex::new(alloc_hashmap))
-        });
 
-        let open2 = open1.get_mut(&a).unwrap().clone();
-        let mut open3 = open2.lock();
-        open3.entry(b).or_insert_with(HashMap::new);
-
-        let open4 = open3.get_mut(&b).unwrap();
-        let find = open4.get(&server.addr.port());
-        let inserted_arc: ServerArcWrapper;
-        match find {
-            Some(find) => {
-                inserted_arc = find.clone();
-                let temp = inserted_arc;
-                let mut temp_lock = temp.lock();
-                temp_lock.update(&server);
-                drop(temp_lock);
+            for (server_id, players) in resolved {
+                self.seen(server_id, now);
+                let mut player_ids = Vec::with_capacity(players.len());
+                for player in players {
+                    player_ids.push(self.insert_player(player)?);
+                }
+                self.link_many(server_id, &player_ids);
+                summary.servers += 1;
             }
+        }
+
+        summary.new_players = self.players.len() - players_before;
+        Ok(summary)
+    }
+
+    /// Returns the id of the server at `addr`, creating it if it is unknown.
+    pub fn insert_server(
+        &mut self,
+        addr: SocketAddr,
+    ) -> Result<ServerId, Box<dyn Error + Send + Sync>> {
+        let (a, b) = split_addr(addr)?;
+
+        let pre_reserve = self.pre_reserve;
+        let open1 = &mut self.server_array;
+        open1.entry(a).or_insert_with(|| new_shard(pre_reserve));
+
+        let open2 = open1.get_mut(&a).unwrap().clone();
+        let mut open3 = METRICS.lock(&open2, &METRICS.shard_lock_wait);
+        let open4 = open3.entry(b).or_default();
+
+        match open4.get(&addr.port()) {
+            Some(server_id) => Ok(*server_id),
             None => {
-                inserted_arc = server_arc.clone();
-                open4.insert(server.addr.port(), inserted_arc);
+                check_limit(self.servers.len(), self.max_servers, "servers")?;
+                let server_id = next_id(self.servers.len())?;
+                self.servers.push(ServerArcWrapper::new(Server::new(addr)));
+                open4.insert(addr.port(), server_id);
+                self.stats.add_server(addr);
+                Ok(server_id)
+            }
+        }
+    }
+
+    /// Returns the id of the server at `server.addr`, creating it if it is
+    /// unknown, and merges everything but the player list of `server`
+    /// into it.
+    pub fn merge_server(
+        &mut self,
+        server: &Server,
+    ) -> Result<ServerId, Box<dyn Error + Send + Sync>> {
+        if strip_formatting(&server.motd).len() > MAX_MOTD_LEN {
+            return Err(format!("MOTD of {} is too long", server.addr).into());
+        }
+        let server_id = self.insert_server(server.addr)?;
+        for (attribute, value) in &server.attributes {
+            self.set_attribute(server_id, *attribute, Some(value.clone()))?;
+        }
+        let (old, new) = {
+            let mut record = self.servers[server_id as usize].lock();
+            let old = record.last_seen;
+            record.update(server);
+            (old, record.last_seen)
+        };
+        self.stats.last_seen_changed(old, new);
+        for name in server.hostnames.iter().chain(&server.srv_targets) {
+            self.index_hostname(name, server_id);
+        }
+        if !server.motd.is_empty() {
+            self.motd_index.set(server_id, &server.motd);
+        }
+        Ok(server_id)
+    }
+
+    /// Replaces the MOTD of the server at `addr`, creating the server if
+    /// it is unknown, and updates the MOTD index.
+    pub fn set_motd(
+        &mut self,
+        addr: SocketAddr,
+        motd: &str,
+    ) -> Result<ServerId, Box<dyn Error + Send + Sync>> {
+        if strip_formatting(motd).len() > MAX_MOTD_LEN {
+            return Err(format!("MOTD of {addr} is too long").into());
+        }
+        let server_id = self.insert_server(addr)?;
+        self.servers[server_id as usize].lock().motd = motd.to_string();
+        self.motd_index.set(server_id, motd);
+        Ok(server_id)
+    }
+
+    /// Sets or, with `None`, clears an attribute of a server and keeps
+    /// its index in step.
+    pub fn set_attribute(
+        &mut self,
+        server_id: ServerId,
+        attribute: Attribute,
+        value: Option<AttrValue>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if let Some(value) = &value {
+            check_value(attribute, value)?;
+        }
+        let server = self
+            .servers
+            .get(server_id as usize)
+            .ok_or_else(|| format!("unknown server id {server_id}"))?;
+        let old = {
+            let mut server = server.lock();
+            match &value {
+                Some(value) => server.attributes.insert(attribute, value.clone()),
+                None => server.attributes.remove(&attribute),
             }
         };
+        if old == value {
+            return Ok(());
+        }
+        if let Some(index) = self.attribute_indexes.get_mut(&attribute) {
+            if let Some(old) = &old {
+                index.remove(old, server_id);
+            }
+            if let Some(value) = value {
+                index.insert(value, server_id);
+            }
+        }
+        Ok(())
+    }
 
-        let server_players = server.players.clone();
-        for player in server_players.iter() {
-            let player = player.lock().clone();
-            // let found = self.player_array.get(&*player);
-            // ^ does not work because return value of `get`
-            // is immutable, and we need a mutable reference
-            // to call .update() on it
-
-            // this is inefficient
-            // TODO: use a RefCell?
-            let found = self.player_array.take(&player);
-            let to_insert: Player = match found {
-                Some(mut found) => {
-                    found.update(&player);
-                    let has_server = player_has_server(&found, &server)?;
-                    if !has_server {
-                        drop(server);
-                        // error here
-                        found.servers.insert(server_arc.clone());
-                        server = server_arc.lock();
-                    }
-                    found
+    /// Ids of the servers whose `attribute` matches `predicate`, in
+    /// ascending order.
+    pub fn find_by_attribute(
+        &self,
+        attribute: Attribute,
+        predicate: &AttrPredicate,
+    ) -> Result<Vec<ServerId>, Box<dyn Error + Send + Sync>> {
+        match predicate {
+            AttrPredicate::Eq(value) => check_value(attribute, value)?,
+            AttrPredicate::Range { min, max } => {
+                for value in min.iter().chain(max) {
+                    check_value(attribute, value)?;
                 }
-                None => {
-                    let mut res = player.clone();
-                    res.servers.insert(server_arc.clone());
-                    res
+            }
+            AttrPredicate::Prefix(prefix) => {
+                check_value(attribute, &AttrValue::Str(prefix.clone()))?
+            }
+        }
+        if let Some(index) = self.attribute_indexes.get(&attribute) {
+            return Ok(index.find(predicate));
+        }
+        Ok((0..self.servers.len() as ServerId)
+            .filter(|server_id| {
+                self.servers[*server_id as usize]
+                    .lock()
+                    .attributes
+                    .get(&attribute)
+                    .is_some_and(|value| predicate.matches(value))
+            })
+            .collect())
+    }
+
+    /// Ids of the servers whose MOTD matches `query`, in ascending order.
+    pub fn search_motd(&self, query: &str, mode: MotdMatch) -> Vec<ServerId> {
+        self.motd_index.search(query, mode)
+    }
+
+    /// Records that `resolution.hostname` leads to `resolution.addr`,
+    /// creating the server if it is unknown.
+    pub fn add_resolution(
+        &mut self,
+        resolution: &Resolution,
+    ) -> Result<ServerId, Box<dyn Error + Send + Sync>> {
+        let hostname = normalize_hostname(&resolution.hostname);
+        if hostname.is_empty() || hostname.len() > MAX_HOSTNAME_LEN {
+            return Err(format!("invalid hostname {:?}", resolution.hostname).into());
+        }
+        let server_id = self.insert_server(resolution.addr)?;
+        self.servers[server_id as usize]
+            .lock()
+            .add_hostname(&hostname);
+        self.index_hostname(&hostname, server_id);
+        if let Some(target) = &resolution.srv_target {
+            let target = normalize_hostname(target);
+            if target.is_empty() || target.len() > MAX_HOSTNAME_LEN {
+                return Err(format!("invalid SRV target {target:?}").into());
+            }
+            self.servers[server_id as usize]
+                .lock()
+                .add_srv_target(&target);
+            self.index_hostname(&target, server_id);
+        }
+        Ok(server_id)
+    }
+
+    /// Ids of the servers `hostname` is known to lead to, either as a
+    /// hostname or as an SRV target.
+    pub fn lookup_hostname(&self, hostname: &str) -> Vec<ServerId> {
+        self.hostname_index
+            .get(&normalize_hostname(hostname))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn index_hostname(&mut self, hostname: &str, server_id: ServerId) {
+        let server_ids = self.hostname_index.entry(hostname.to_string()).or_default();
+        if let Err(index) = server_ids.binary_search(&server_id) {
+            server_ids.insert(index, server_id);
+        }
+    }
+
+    /// Returns the id of `player`, creating it if it is unknown. Any
+    /// servers already listed on `player` are ignored.
+    pub fn insert_player(
+        &mut self,
+        player: Player,
+    ) -> Result<PlayerId, Box<dyn Error + Send + Sync>> {
+        let key = (player.name, player.uuid);
+        if let Some(player_id) = self.player_array.get(&key) {
+            return Ok(*player_id);
+        }
+        check_limit(self.players.len(), self.max_players, "players")?;
+        let player_id = next_id(self.players.len())?;
+        self.players
+            .push(PlayerArcWrapper::new(Player::new(key.0.clone(), key.1)));
+        self.index_player(key, player_id);
+        Ok(player_id)
+    }
+
+    /// Ids of every record of the player with `uuid`, oldest first.
+    pub fn find_player(&self, uuid: Uuid) -> &[PlayerId] {
+        self.uuid_index.get(&uuid).map_or(&[], Vec::as_slice)
+    }
+
+    /// Links a server and a player in both directions. Only one record
+    /// is locked at a time, and no code path holds a record lock while
+    /// taking another, so records can never deadlock against each other.
+    pub fn link(&mut self, server_id: ServerId, player_id: PlayerId) {
+        if self.servers[server_id as usize]
+            .lock()
+            .add_player(player_id)
+        {
+            self.stats.links += 1;
+        }
+        self.players[player_id as usize]
+            .lock()
+            .add_server(server_id);
+    }
+
+    /// Same as `link`, for several players of one server.
+    pub fn link_many(&mut self, server_id: ServerId, player_ids: &[PlayerId]) {
+        {
+            let mut server = self.servers[server_id as usize].lock();
+            for player_id in player_ids {
+                if server.add_player(*player_id) {
+                    self.stats.links += 1;
                 }
+            }
+        }
+        for player_id in player_ids {
+            self.players[*player_id as usize]
+                .lock()
+                .add_server(server_id);
+        }
+    }
+
+    /// Removes the server at `addr`, its links and its index entries, and
+    /// returns whether it existed. The server with the highest id takes
+    /// over the freed id, so ids stay dense.
+    pub fn delete_server(
+        &mut self,
+        addr: SocketAddr,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let Some(server_id) = self.find_id(addr)? else {
+            return Ok(false);
+        };
+        self.unindex_server(server_id);
+        let (player_ids, last_seen) = {
+            let server = self.servers[server_id as usize].lock();
+            (server.players.clone(), server.last_seen)
+        };
+        for player_id in player_ids {
+            remove_sorted(
+                &mut self.players[player_id as usize].lock().servers,
+                server_id,
+            );
+            self.stats.links -= 1;
+        }
+        self.stats.remove_server(addr);
+        self.stats.last_seen_changed(last_seen, 0);
+        self.set_shard_entry(addr, None)?;
+
+        let last = (self.servers.len() - 1) as ServerId;
+        if last != server_id {
+            self.unindex_server(last);
+            let (moved_addr, player_ids) = {
+                let server = self.servers[last as usize].lock();
+                (server.addr, server.players.clone())
             };
+            for player_id in player_ids {
+                let mut player = self.players[player_id as usize].lock();
+                remove_sorted(&mut player.servers, last);
+                player.add_server(server_id);
+            }
+            self.set_shard_entry(moved_addr, Some(server_id))?;
+        }
+        self.servers.swap_remove(server_id as usize);
+        if last != server_id {
+            self.index_server(server_id);
+        }
+        Ok(true)
+    }
 
-            self.player_array.insert(to_insert);
+    /// Removes every record of the player with `uuid` and their links,
+    /// and returns how many there were. As with `delete_server`, the
+    /// player with the highest id takes over each freed id.
+    pub fn delete_player(&mut self, uuid: Uuid) -> usize {
+        let mut deleted = 0;
+        while let Some(player_id) = self.find_player(uuid).first().copied() {
+            self.delete_player_record(player_id);
+            deleted += 1;
         }
+        deleted
+    }
+
+    fn delete_player_record(&mut self, player_id: PlayerId) {
+        let (key, server_ids) = {
+            let player = self.players[player_id as usize].lock();
+            ((player.name.clone(), player.uuid), player.servers.clone())
+        };
+        for server_id in server_ids {
+            remove_sorted(
+                &mut self.servers[server_id as usize].lock().players,
+                player_id,
+            );
+            self.stats.links -= 1;
+        }
+        self.unindex_player(&key, player_id);
+
+        let last = (self.players.len() - 1) as PlayerId;
+        if last != player_id {
+            let (moved_key, server_ids) = {
+                let player = self.players[last as usize].lock();
+                ((player.name.clone(), player.uuid), player.servers.clone())
+            };
+            for server_id in server_ids {
+                let mut server = self.servers[server_id as usize].lock();
+                remove_sorted(&mut server.players, last);
+                server.add_player(player_id);
+            }
+            self.unindex_player(&moved_key, last);
+            self.index_player(moved_key, player_id);
+        }
+        self.players.swap_remove(player_id as usize);
+    }
 
+    fn index_player(&mut self, key: (String, Uuid), player_id: PlayerId) {
+        self.name_index
+            .insert((fold_name(&key.0), key.1, key.0.clone()), player_id);
+        let ids = self.uuid_index.entry(key.1).or_default();
+        if let Err(index) = ids.binary_search(&player_id) {
+            ids.insert(index, player_id);
+        }
+        self.player_array.insert(key, player_id);
+    }
+
+    fn unindex_player(&mut self, key: &(String, Uuid), player_id: PlayerId) {
+        self.name_index
+            .remove(&(fold_name(&key.0), key.1, key.0.clone()));
+        if let Some(ids) = self.uuid_index.get_mut(&key.1) {
+            remove_sorted(ids, player_id);
+            if ids.is_empty() {
+                self.uuid_index.remove(&key.1);
+            }
+        }
+        if self.player_array.get(key) == Some(&player_id) {
+            self.player_array.remove(key);
+        }
+    }
+
+    /// Adds the hostnames, MOTD and attributes of a server to their
+    /// indexes.
+    fn index_server(&mut self, server_id: ServerId) {
+        let server = self.servers[server_id as usize].lock().clone();
+        for name in server.hostnames.iter().chain(&server.srv_targets) {
+            self.index_hostname(name, server_id);
+        }
+        self.motd_index.set(server_id, &server.motd);
+        for (attribute, value) in server.attributes {
+            if let Some(index) = self.attribute_indexes.get_mut(&attribute) {
+                index.insert(value, server_id);
+            }
+        }
+    }
+
+    fn unindex_server(&mut self, server_id: ServerId) {
+        let server = self.servers[server_id as usize].lock().clone();
+        for name in server.hostnames.iter().chain(&server.srv_targets) {
+            if let Some(server_ids) = self.hostname_index.get_mut(name) {
+                remove_sorted(server_ids, server_id);
+                if server_ids.is_empty() {
+                    self.hostname_index.remove(name);
+                }
+            }
+        }
+        self.motd_index.set(server_id, "");
+        for (attribute, value) in &server.attributes {
+            if let Some(index) = self.attribute_indexes.get_mut(attribute) {
+                index.remove(value, server_id);
+            }
+        }
+    }
+
+    /// Points the shard entry of `addr` at `server_id`, or removes it.
+    fn set_shard_entry(
+        &mut self,
+        addr: SocketAddr,
+        server_id: Option<ServerId>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (a, b) = split_addr(addr)?;
+        let shard = self
+            .server_array
+            .get(&a)
+            .ok_or_else(|| format!("no shard for {addr}"))?;
+        let mut shard = METRICS.lock(shard, &METRICS.shard_lock_wait);
+        match server_id {
+            Some(server_id) => {
+                shard.entry(b).or_default().insert(addr.port(), server_id);
+            }
+            None => {
+                if let Some(ports) = shard.get_mut(&b) {
+                    ports.remove(&addr.port());
+                    if ports.is_empty() {
+                        shard.remove(&b);
+                    }
+                }
+            }
+        }
         Ok(())
     }
 
@@ -110,35 +585,231 @@ impl ServerMap {
         &mut self,
         addr: SocketAddr,
     ) -> Result<Option<ServerArcWrapper>, Box<dyn Error + Send + Sync>> {
-        let octets: [u8; 4] = match addr.ip() {
-            IpAddr::V4(addr) => addr.octets(),
-            _ => return Err("Not an IPv4 Address".into()),
-        };
+        Ok(self
+            .find_id(addr)?
+            .map(|server_id| self.servers[server_id as usize].clone()))
+    }
 
-        let a = u8s_to_u16(octets[0], octets[1]);
-        let b = u8s_to_u16(octets[2], octets[3]);
+    pub fn find_id(
+        &self,
+        addr: SocketAddr,
+    ) -> Result<Option<ServerId>, Box<dyn Error + Send + Sync>> {
+        let (a, b) = split_addr(addr)?;
 
-        let open = &mut self.server_array;
+        let open = &self.server_array;
         if !open.contains_key(&a) {
             return Ok(None);
         }
-        let open = open.get_mut(&a).unwrap();
-        let mut open = open.lock();
+        let open = open.get(&a).unwrap();
+        let open = open.lock();
         if !open.contains_key(&b) {
             return Ok(None);
         }
-        let open = open.get_mut(&b).unwrap();
+        let open = open.get(&b).unwrap();
         let find = open.get(&addr.port());
 
-        match find {
-            Some(find) => Ok(Some(find.clone())),
-            None => Ok(None),
+        Ok(find.copied())
+    }
+
+    /// Ids of every server in `cidr`, ordered by address.
+    pub fn servers_in(&self, cidr: Cidr) -> Vec<ServerId> {
+        self.scan_servers(cidr, None, usize::MAX).servers
+    }
+
+    /// Servers in `cidr` ordered by /16, then address, then port, starting
+    /// after the address `after`, the `next` of a previous page, and
+    /// holding at most `limit` servers. `after` does not have to be a
+    /// known server, so a scan can resume after servers were inserted.
+    pub fn scan_servers(&self, cidr: Cidr, after: Option<SocketAddr>, limit: usize) -> ServerPage {
+        let after = after.and_then(|addr| {
+            let (a, b) = split_addr(addr).ok()?;
+            Some((a, b, addr.port()))
+        });
+        let mut page = ServerPage::default();
+        let mut last = None;
+        for a in cidr.shards() {
+            if after.is_some_and(|(after_a, _, _)| a < after_a) {
+                continue;
+            }
+            let Some(shard) = self.server_array.get(&a) else {
+                continue;
+            };
+            let shard = shard.lock();
+            let mut ips: Vec<(&u16, &HashMap<u16, ServerId>)> = shard
+                .iter()
+                .filter(|(b, _)| cidr.contains(Ipv4Addr::from(u16s_to_u32(a, **b))))
+                .filter(|(b, _)| after.is_none_or(|after| (a, **b) >= (after.0, after.1)))
+                .collect();
+            ips.sort_by_key(|(b, _)| **b);
+            for (b, ports) in ips {
+                let mut ports: Vec<(&u16, &ServerId)> = ports
+                    .iter()
+                    .filter(|(port, _)| after.is_none_or(|after| (a, *b, **port) > after))
+                    .collect();
+                ports.sort_by_key(|(port, _)| **port);
+                for (port, server_id) in ports {
+                    if page.servers.len() == limit {
+                        page.next = last;
+                        return page;
+                    }
+                    page.servers.push(*server_id);
+                    last = Some(SocketAddr::from((
+                        Ipv4Addr::from(u16s_to_u32(a, *b)),
+                        *port,
+                    )));
+                }
+            }
+        }
+        page
+    }
+
+    /// Players in `player_array` order, starting after the key `after`,
+    /// the `next` of a previous page, and holding at most `limit` players.
+    pub fn scan_players(&self, after: Option<(String, Uuid)>, limit: usize) -> PlayerPage {
+        let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+        let mut page = PlayerPage::default();
+        let mut last: Option<&(String, Uuid)> = None;
+        for (key, player_id) in self.player_array.range((start, Bound::Unbounded)) {
+            if page.players.len() == limit {
+                page.next = last.cloned();
+                break;
+            }
+            page.players.push(*player_id);
+            last = Some(key);
         }
+        page
     }
 
+    /// Players whose name matches `query` case-insensitively, ordered by
+    /// folded name, uuid and then name. Results start after the key
+    /// `after`, the `next` of a previous page, and hold at most `limit`
+    /// players. `after` does not have to be a known player, so a search
+    /// can resume after players were inserted or deleted.
+    pub fn search_names(
+        &self,
+        query: &str,
+        mode: NameMatch,
+        after: Option<(String, Uuid)>,
+        limit: usize,
+    ) -> NamePage {
+        let query = fold_name(query);
+        let first = (query.clone(), Uuid::nil(), String::new());
+        let start = match after {
+            Some((name, uuid)) if (fold_name(&name), uuid, name.clone()) >= first => {
+                Bound::Excluded((fold_name(&name), uuid, name))
+            }
+            _ => Bound::Included(first),
+        };
+
+        let mut page = NamePage::default();
+        let mut last: Option<&(String, Uuid, String)> = None;
+        for (key, player_id) in self.name_index.range((start, Bound::Unbounded)) {
+            let matches = match mode {
+                NameMatch::Exact => key.0 == query,
+                NameMatch::Prefix => key.0.starts_with(&query),
+            };
+            if !matches {
+                break;
+            }
+            if page.players.len() == limit {
+                page.next = last.map(|(_, uuid, name)| (name.clone(), *uuid));
+                break;
+            }
+            page.players.push(*player_id);
+            last = Some(key);
+        }
+        page
+    }
+
+    /// Number of servers.
     pub fn size(&self) -> usize {
-        self.server_array.len()
+        self.servers.len()
     }
+
+    /// Reports the counters of `self.stats` and the attribute indexes.
+    /// Servers count as seen in the last day, week or month to the hour.
+    pub fn stats(&self, now: u64) -> StatsReport {
+        let counts = |attribute: Attribute| {
+            self.attribute_indexes
+                .get(&attribute)
+                .into_iter()
+                .flat_map(AttributeIndex::counts)
+        };
+        StatsReport {
+            servers: self.servers.len(),
+            players: self.players.len(),
+            uuids: self.uuid_index.len(),
+            links: self.stats.links,
+            per_slash8: self.stats.per_slash8.clone(),
+            per_port: self.stats.per_port.clone(),
+            per_version: counts(Attribute::Version)
+                .map(|(value, count)| (value.to_string(), count))
+                .collect(),
+            per_protocol: counts(Attribute::ProtocolVersion)
+                .filter_map(|(value, count)| match value {
+                    AttrValue::Int(protocol) => Some((*protocol, count)),
+                    _ => None,
+                })
+                .collect(),
+            seen_last_day: self.stats.seen_since(now.saturating_sub(DAY)),
+            seen_last_week: self.stats.seen_since(now.saturating_sub(WEEK)),
+            seen_last_month: self.stats.seen_since(now.saturating_sub(MONTH)),
+        }
+    }
+
+    /// Records a sighting of a server at `timestamp`.
+    fn seen(&mut self, server_id: ServerId, timestamp: u64) {
+        let (old, new) = {
+            let mut server = self.servers[server_id as usize].lock();
+            let old = server.last_seen;
+            server.seen(timestamp);
+            (old, server.last_seen)
+        };
+        self.stats.last_seen_changed(old, new);
+    }
+}
+
+/// Outcome of `ServerMap::insert_batch`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BatchSummary {
+    /// Number of sightings processed.
+    pub servers: usize,
+    /// Number of servers that were not known before the batch.
+    pub new_servers: usize,
+    /// Number of players that were not known before the batch.
+    pub new_players: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameMatch {
+    /// The whole name matches, ignoring case.
+    Exact,
+    /// The name starts with the query, ignoring case.
+    Prefix,
+}
+
+/// One page of `ServerMap::scan_servers`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ServerPage {
+    pub servers: Vec<ServerId>,
+    /// Pass as `after` to get the next page, or `None` on the last page.
+    pub next: Option<SocketAddr>,
+}
+
+/// One page of `ServerMap::scan_players`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PlayerPage {
+    pub players: Vec<PlayerId>,
+    /// Pass as `after` to get the next page, or `None` on the last page.
+    pub next: Option<(String, Uuid)>,
+}
+
+/// One page of `ServerMap::search_names`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct NamePage {
+    pub players: Vec<PlayerId>,
+    /// Pass as `after` to get the next page, or `None` on the last page.
+    pub next: Option<(String, Uuid)>,
 }
 
 impl Default for ServerMap {
@@ -147,18 +818,576 @@ impl Default for ServerMap {
     }
 }
 
+/// Current unix time in seconds, used to stamp sightings.
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// The key of a name in `ServerMap::name_index`.
+pub fn fold_name(name: &str) -> String {
+    name.to_lowercase()
+}
+
 fn u8s_to_u16(a: u8, b: u8) -> u16 {
     ((a as u16) << 8) | b as u16
 }
 
-fn player_has_server(
-    player: &Player,
-    server: &Server,
-) -> Result<bool, Box<dyn Error + Send + Sync>> {
-    for player_server in &player.servers {
-        if *player_server.lock() == *server {
-            return Ok(true);
+fn u16s_to_u32(a: u16, b: u16) -> u32 {
+    ((a as u32) << 16) | b as u32
+}
+
+fn split_addr(addr: SocketAddr) -> Result<(u16, u16), Box<dyn Error + Send + Sync>> {
+    let octets: [u8; 4] = match addr.ip() {
+        IpAddr::V4(addr) => addr.octets(),
+        _ => return Err("Not an IPv4 Address".into()),
+    };
+
+    let a = u8s_to_u16(octets[0], octets[1]);
+    let b = u8s_to_u16(octets[2], octets[3]);
+    Ok((a, b))
+}
+
+#[allow(clippy::type_complexity)]
+fn new_shard(pre_reserve: bool) -> Arc<Mutex<HashMap<u16, HashMap<u16, ServerId>>>> {
+    let mut alloc_hashmap = HashMap::new();
+    if pre_reserve {
+        alloc_hashmap.reserve(65536);
+    }
+    Arc::new(Mutex::new(alloc_hashmap))
+}
+
+/// Removes `id` from a sorted list of ids, if it is there.
+fn remove_sorted(ids: &mut Vec<u32>, id: u32) {
+    if let Ok(index) = ids.binary_search(&id) {
+        ids.remove(index);
+    }
+}
+
+/// Fails when `len` records already reach a `max` other than 0.
+fn check_limit(len: usize, max: usize, what: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if max != 0 && len >= max {
+        return Err(format!("limit of {max} {what} reached").into());
+    }
+    Ok(())
+}
+
+fn next_id(len: usize) -> Result<u32, Box<dyn Error + Send + Sync>> {
+    u32::try_from(len).map_err(|_| "Too many entries to assign an id".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddrV4};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use parking_lot::deadlock;
+
+    use super::*;
+
+    fn addr(i: u32) -> SocketAddr {
+        SocketAddr::V4(SocketAddrV4::new(
+            Ipv4Addr::new(10, (i % 4) as u8, (i % 7) as u8, 1),
+            25565 + (i % 3) as u16,
+        ))
+    }
+
+    fn player(i: u32) -> Player {
+        Player::new(format!("player{i}"), Uuid::from_u128(i as u128))
+    }
+
+    /// Joins `handles`, failing the test if parking_lot reports a deadlock
+    /// or the threads do not finish in time.
+    fn join_without_deadlock(handles: Vec<thread::JoinHandle<()>>) {
+        let start = Instant::now();
+        while !handles.iter().all(|handle| handle.is_finished()) {
+            let deadlocks = deadlock::check_deadlock();
+            assert!(
+                deadlocks.is_empty(),
+                "{} deadlocks detected",
+                deadlocks.len()
+            );
+            assert!(
+                start.elapsed() < Duration::from_secs(30),
+                "threads did not finish"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn insert_links_both_directions() {
+        let mut map = ServerMap::new();
+        let server_id = map.insert(addr(0), [player(0), player(1)]).unwrap();
+        map.insert(addr(0), [player(1), player(2)]).unwrap();
+        let other_id = map.insert(addr(1), [player(1)]).unwrap();
+
+        assert_eq!(map.servers.len(), 2);
+        assert_eq!(map.players.len(), 3);
+        assert_eq!(
+            map.servers[server_id as usize].lock().players,
+            vec![0, 1, 2]
+        );
+        assert_eq!(map.players[1].lock().servers, vec![server_id, other_id]);
+        assert_eq!(map.find_id(addr(1)).unwrap(), Some(other_id));
+    }
+
+    #[test]
+    fn insert_batch_matches_insert() {
+        let sightings: Vec<(SocketAddr, Vec<Player>)> = (0..50)
+            .map(|i| (addr(i), (0..3).map(|j| player((i + j) % 10)).collect()))
+            .collect();
+
+        let mut single = ServerMap::new();
+        for (addr, players) in sightings.clone() {
+            single.insert(addr, players).unwrap();
+        }
+        let mut batch = ServerMap::new();
+        let summary = batch.insert_batch(sightings).unwrap();
+
+        assert_eq!(summary.servers, 50);
+        assert_eq!(summary.new_servers, single.servers.len());
+        assert_eq!(summary.new_players, 10);
+        for server in &single.servers {
+            let batch_id = batch.find_id(server.addr()).unwrap().unwrap();
+            let mut batch_players: Vec<Uuid> = batch.servers[batch_id as usize]
+                .lock()
+                .players
+                .iter()
+                .map(|id| batch.players[*id as usize].uuid())
+                .collect();
+            let mut single_players: Vec<Uuid> = server
+                .lock()
+                .players
+                .iter()
+                .map(|id| single.players[*id as usize].uuid())
+                .collect();
+            batch_players.sort();
+            single_players.sort();
+            assert_eq!(batch_players, single_players);
+        }
+    }
+
+    #[test]
+    fn attribute_indexes_follow_updates() {
+        let mut map = ServerMap::new();
+        for i in 0..4 {
+            map.insert_server(addr(i)).unwrap();
+        }
+        map.set_attribute(0, Attribute::ProtocolVersion, Some(AttrValue::Int(47)))
+            .unwrap();
+        map.set_attribute(1, Attribute::ProtocolVersion, Some(AttrValue::Int(763)))
+            .unwrap();
+        map.set_attribute(2, Attribute::ProtocolVersion, Some(AttrValue::Int(765)))
+            .unwrap();
+        map.set_attribute(
+            3,
+            Attribute::Country,
+            Some(AttrValue::Str("DE".to_string())),
+        )
+        .unwrap();
+        assert!(map
+            .set_attribute(3, Attribute::ProtocolVersion, Some(AttrValue::Bool(true)))
+            .is_err());
+
+        let modern = AttrPredicate::Range {
+            min: Some(AttrValue::Int(700)),
+            max: None,
+        };
+        assert_eq!(
+            map.find_by_attribute(Attribute::ProtocolVersion, &modern)
+                .unwrap(),
+            vec![1, 2]
+        );
+        map.set_attribute(2, Attribute::ProtocolVersion, Some(AttrValue::Int(5)))
+            .unwrap();
+        map.set_attribute(1, Attribute::ProtocolVersion, None)
+            .unwrap();
+        assert!(map
+            .find_by_attribute(Attribute::ProtocolVersion, &modern)
+            .unwrap()
+            .is_empty());
+
+        // scans give the same answer as the index
+        let germany = AttrPredicate::Eq(AttrValue::Str("DE".to_string()));
+        assert_eq!(
+            map.find_by_attribute(Attribute::Country, &germany).unwrap(),
+            vec![3]
+        );
+        map.attribute_indexes.remove(&Attribute::Country);
+        assert_eq!(
+            map.find_by_attribute(Attribute::Country, &germany).unwrap(),
+            vec![3]
+        );
+
+        let mut reloaded = ServerMap::new();
+        for server in &map.servers {
+            let buf = server.lock().serialize(&map.players).unwrap();
+            let (server, _) = Server::deserialize(&mut &buf[..]).unwrap();
+            reloaded.merge_server(&server).unwrap();
+        }
+        assert_eq!(
+            reloaded
+                .find_by_attribute(Attribute::Country, &germany)
+                .unwrap(),
+            vec![3]
+        );
+        assert_eq!(
+            reloaded
+                .find_by_attribute(
+                    Attribute::ProtocolVersion,
+                    &AttrPredicate::Eq(AttrValue::Int(5))
+                )
+                .unwrap(),
+            vec![2]
+        );
+    }
+
+    #[test]
+    fn motd_index_survives_reload() {
+        let mut map = ServerMap::new();
+        map.set_motd(addr(1), "§aWelcome to §lSkyblock").unwrap();
+        map.set_motd(addr(2), "Survival").unwrap();
+        map.set_motd(addr(2), "Creative").unwrap();
+        assert_eq!(map.search_motd("skyblock", MotdMatch::Substring), vec![0]);
+        assert!(map.search_motd("survival", MotdMatch::Substring).is_empty());
+
+        let mut reloaded = ServerMap::new();
+        for server in &map.servers {
+            let buf = server.lock().serialize(&map.players).unwrap();
+            let (server, _) = Server::deserialize(&mut &buf[..]).unwrap();
+            reloaded.merge_server(&server).unwrap();
+        }
+        assert_eq!(
+            reloaded.search_motd("creatve", MotdMatch::Fuzzy { max_distance: 1 }),
+            vec![1]
+        );
+        assert!(map
+            .set_motd(addr(3), &"x".repeat(MAX_MOTD_LEN + 1))
+            .is_err());
+    }
+
+    #[test]
+    fn search_names_ignores_case_and_pages() {
+        let mut map = ServerMap::new();
+        for (i, name) in ["Alice", "alice", "ALICE2", "alfred", "bob"]
+            .iter()
+            .enumerate()
+        {
+            map.insert_player(Player::new(name.to_string(), Uuid::from_u128(i as u128)))
+                .unwrap();
+        }
+        let names = |map: &ServerMap, ids: &[PlayerId]| -> Vec<String> {
+            ids.iter()
+                .map(|id| map.players[*id as usize].lock().name.clone())
+                .collect()
+        };
+
+        let page = map.search_names("ALICE", NameMatch::Exact, None, 10);
+        assert_eq!(names(&map, &page.players), vec!["Alice", "alice"]);
+        assert_eq!(page.next, None);
+
+        let page = map.search_names("al", NameMatch::Prefix, None, 2);
+        assert_eq!(names(&map, &page.players), vec!["alfred", "Alice"]);
+        let next = page.next.clone();
+        assert_eq!(next, Some(("Alice".to_string(), Uuid::from_u128(0))));
+
+        // the cursor is a key, so it survives its player being deleted
+        // and the ids moving underneath it
+        map.delete_player(Uuid::from_u128(0));
+        let page = map.search_names("al", NameMatch::Prefix, next, 2);
+        assert_eq!(names(&map, &page.players), vec!["alice", "ALICE2"]);
+        assert_eq!(page.next, None);
+
+        assert!(map
+            .search_names("zed", NameMatch::Prefix, None, 10)
+            .players
+            .is_empty());
+        let before = Some(("aa".to_string(), Uuid::nil()));
+        let page = map.search_names("al", NameMatch::Prefix, before, 10);
+        assert_eq!(page.players.len(), 3);
+    }
+
+    #[test]
+    fn limits_reject_new_records() {
+        let mut map = ServerMap::new();
+        map.max_servers = 1;
+        map.max_players = 2;
+        let addr: SocketAddr = "10.0.0.1:25565".parse().unwrap();
+        let players = (0..2).map(|i| Player::new(format!("p{i}"), Uuid::from_u128(i)));
+        map.insert(addr, players).unwrap();
+        // known records are still found
+        map.insert(addr, [Player::new("p0".to_string(), Uuid::from_u128(0))])
+            .unwrap();
+        assert!(map
+            .insert(addr, [Player::new("p2".to_string(), Uuid::from_u128(2))])
+            .is_err());
+        assert!(map
+            .insert_batch([("10.0.0.2:25565".parse().unwrap(), vec![])])
+            .is_err());
+        assert_eq!((map.servers.len(), map.players.len()), (1, 2));
+    }
+
+    /// Checks that links are symmetric and counted, and that every
+    /// server and player is found under its own id.
+    fn assert_consistent(map: &ServerMap) {
+        let mut links = 0;
+        for (server_id, server) in map.servers.iter().enumerate() {
+            let server = server.lock();
+            assert_eq!(
+                map.find_id(server.addr).unwrap(),
+                Some(server_id as ServerId)
+            );
+            for player_id in &server.players {
+                assert!(map.players[*player_id as usize]
+                    .lock()
+                    .servers
+                    .contains(&(server_id as ServerId)));
+                links += 1;
+            }
+        }
+        assert_eq!(map.stats.links, links);
+        for (player_id, player) in map.players.iter().enumerate() {
+            let player = player.lock();
+            let key = (player.name.clone(), player.uuid);
+            assert_eq!(map.player_array[&key], player_id as PlayerId);
+            assert!(map
+                .find_player(player.uuid)
+                .contains(&(player_id as PlayerId)));
+        }
+        assert_eq!(map.name_index.len(), map.players.len());
+    }
+
+    #[test]
+    fn deletes_keep_ids_dense() {
+        let mut map = ServerMap::new();
+        let player = |name: &str, uuid| Player::new(name.to_string(), Uuid::from_u128(uuid));
+        let addrs: Vec<SocketAddr> = (1..=4)
+            .map(|i| format!("10.0.0.{i}:25565").parse().unwrap())
+            .collect();
+        map.insert(addrs[0], [player("a", 1)]).unwrap();
+        map.insert(addrs[1], [player("a", 1), player("c", 3)])
+            .unwrap();
+        map.insert(addrs[2], []).unwrap();
+        map.insert(addrs[3], [player("a", 1), player("b", 2), player("b2", 2)])
+            .unwrap();
+        map.set_motd(addrs[3], "Creative").unwrap();
+        map.set_attribute(
+            3,
+            Attribute::Country,
+            Some(AttrValue::Str("DE".to_string())),
+        )
+        .unwrap();
+        map.add_resolution(&Resolution {
+            hostname: "play.example.com".to_string(),
+            srv_target: None,
+            addr: addrs[3],
+        })
+        .unwrap();
+
+        assert!(map.delete_server(addrs[1]).unwrap());
+        assert!(!map.delete_server(addrs[1]).unwrap());
+        assert_consistent(&map);
+        assert_eq!(map.find_id(addrs[3]).unwrap(), Some(1));
+        assert_eq!(map.lookup_hostname("play.example.com"), vec![1]);
+        assert_eq!(map.search_motd("creative", MotdMatch::Substring), vec![1]);
+        let germany = AttrPredicate::Eq(AttrValue::Str("DE".to_string()));
+        assert_eq!(
+            map.find_by_attribute(Attribute::Country, &germany).unwrap(),
+            vec![1]
+        );
+        assert_eq!(map.players[0].lock().servers, vec![0, 1]);
+        assert!(map.players[1].lock().servers.is_empty());
+        assert_eq!(map.stats.per_port[&25565], 3);
+
+        assert_eq!(map.delete_player(Uuid::from_u128(2)), 2);
+        assert_eq!(map.delete_player(Uuid::from_u128(2)), 0);
+        assert_consistent(&map);
+        assert_eq!(map.players.len(), 2);
+        assert_eq!(map.servers[1].lock().players, vec![0]);
+
+        assert!(map.delete_server(addrs[3]).unwrap());
+        assert_consistent(&map);
+        assert!(map.lookup_hostname("play.example.com").is_empty());
+        assert!(map.motd_index.is_empty());
+    }
+
+    #[test]
+    fn stats_follow_inserts() {
+        let mut map = ServerMap::new();
+        let player = |i: u128| Player::new(format!("p{i}"), Uuid::from_u128(i));
+        map.insert(addr(0), [player(0), player(1)]).unwrap();
+        map.insert(addr(0), [player(1), player(2)]).unwrap();
+        map.insert_batch([(addr(1), vec![player(0)]), (addr(2), vec![])])
+            .unwrap();
+        map.insert_server(addr(3)).unwrap();
+        map.insert_player(Player::new("renamed".to_string(), Uuid::from_u128(0)))
+            .unwrap();
+        map.set_attribute(0, Attribute::ProtocolVersion, Some(AttrValue::Int(765)))
+            .unwrap();
+        map.set_attribute(1, Attribute::ProtocolVersion, Some(AttrValue::Int(765)))
+            .unwrap();
+
+        let now = unix_now();
+        let old = Server {
+            last_seen: now - 3 * DAY,
+            first_seen: now - 3 * DAY,
+            ..Server::new(addr(4))
+        };
+        map.merge_server(&old).unwrap();
+
+        let report = map.stats(now);
+        assert_eq!(map.size(), 5);
+        assert_eq!(report.servers, 5);
+        assert_eq!(report.players, 4);
+        assert_eq!(report.uuids, 3);
+        assert_eq!(report.links, 4);
+        assert_eq!(report.per_slash8[&10], 5);
+        assert_eq!(report.per_port.values().sum::<usize>(), 5);
+        assert_eq!(report.per_protocol[&765], 2);
+        assert_eq!(report.seen_last_day, 3);
+        assert_eq!(report.seen_last_week, 4);
+    }
+
+    #[test]
+    fn servers_in_cidr() {
+        let mut map = ServerMap::new();
+        for i in 0..50 {
+            map.insert(addr(i), []).unwrap();
+        }
+        let cidr: Cidr = "10.1.0.0/16".parse().unwrap();
+        let addrs: Vec<SocketAddr> = map
+            .servers_in(cidr)
+            .into_iter()
+            .map(|id| map.servers[id as usize].addr())
+            .collect();
+        let mut expected: Vec<SocketAddr> = map
+            .servers
+            .iter()
+            .map(|server| server.addr())
+            .filter(|addr| matches!(addr.ip(), IpAddr::V4(ip) if cidr.contains(ip)))
+            .collect();
+        expected.sort();
+        assert!(!expected.is_empty());
+        assert_eq!(addrs, expected);
+        assert_eq!(map.servers_in(Cidr::ALL).len(), map.servers.len());
+    }
+
+    #[test]
+    fn scans_resume_across_inserts() {
+        let mut map = ServerMap::new();
+        for i in 0..20 {
+            map.insert(
+                addr(i),
+                [Player::new(format!("p{i:02}"), Uuid::from_u128(i as u128))],
+            )
+            .unwrap();
+        }
+        let expected = map.servers_in(Cidr::ALL);
+
+        let mut seen = vec![];
+        let mut after = None;
+        loop {
+            let page = map.scan_servers(Cidr::ALL, after, 3);
+            assert!(page.servers.len() <= 3);
+            seen.extend(page.servers);
+            if seen.len() == 6 {
+                // one server sorts before the cursor and one after
+                map.insert("10.0.0.0:1".parse().unwrap(), []).unwrap();
+                map.insert("10.3.6.1:30000".parse().unwrap(), []).unwrap();
+            }
+            match page.next {
+                Some(next) => after = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(seen.len(), expected.len() + 1);
+        assert_eq!(&seen[..expected.len()], &expected[..]);
+        assert_eq!(
+            map.servers[*seen.last().unwrap() as usize].addr(),
+            "10.3.6.1:30000".parse().unwrap()
+        );
+
+        let page = map.scan_players(None, 15);
+        assert_eq!(page.players.len(), 15);
+        let rest = map.scan_players(page.next, 15);
+        assert_eq!(rest.players.len(), 5);
+        assert_eq!(rest.next, None);
+        assert_eq!(map.players[rest.players[0] as usize].lock().name, "p15");
+    }
+
+    #[test]
+    fn wrapper_comparisons_do_not_lock() {
+        let server = ServerArcWrapper::new(Server::new(addr(0)));
+        let player = PlayerArcWrapper::new(player(0));
+        let _server_guard = server.lock();
+        let _player_guard = player.lock();
+        assert!(server.clone() == server);
+        assert!(player.clone() == player);
+        // two names of one player are different records
+        let renamed = PlayerArcWrapper::new(Player::new("renamed".to_string(), player.uuid()));
+        assert!(renamed != player);
+    }
+
+    #[test]
+    fn concurrent_overlapping_inserts() {
+        let map = Arc::new(Mutex::new(ServerMap::new()));
+        let mut handles = vec![];
+
+        for worker in 0..8u32 {
+            let map = map.clone();
+            handles.push(thread::spawn(move || {
+                for i in 0..200u32 {
+                    let players = (0..5).map(|j| player((worker + i + j) % 40));
+                    map.lock().insert(addr(worker * 7 + i), players).unwrap();
+                }
+            }));
+        }
+
+        // Copies records under the map lock and serializes the servers
+        // outside it while inserts are running, the same way
+        // `serialize_all` does.
+        for _ in 0..2 {
+            let map = map.clone();
+            handles.push(thread::spawn(move || {
+                for _ in 0..50 {
+                    let (servers, players) = {
+                        let lock = map.lock();
+                        for player in &lock.players {
+                            player.lock().serialize(&lock.servers).unwrap();
+                        }
+                        let servers: Vec<Server> = lock
+                            .servers
+                            .iter()
+                            .map(|server| server.lock().clone())
+                            .collect();
+                        (servers, lock.players.clone())
+                    };
+                    for server in &servers {
+                        server.serialize(&players).unwrap();
+                    }
+                }
+            }));
+        }
+
+        join_without_deadlock(handles);
+
+        let map = map.lock();
+        assert_eq!(map.players.len(), 40);
+        for (server_id, server) in map.servers.iter().enumerate() {
+            let player_ids = server.lock().players.clone();
+            for player_id in player_ids {
+                assert!(map.players[player_id as usize]
+                    .lock()
+                    .servers
+                    .contains(&(server_id as ServerId)));
+            }
         }
     }
-    Ok(false)
 }
@@ -0,0 +1,125 @@
+use std::error::Error;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+
+use crate::nbt::Tag;
+
+/// Port the client connects to when an address has none.
+pub const DEFAULT_PORT: u16 = 25565;
+
+/// One entry of the client's multiplayer server list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerListEntry {
+    pub name: String,
+    /// `host[:port]` as typed into the client.
+    pub ip: String,
+}
+
+/// Reads an uncompressed `servers.dat`:
+/// `{ servers: [ { name: string, ip: string, icon?: string, ... } ] }`.
+/// Entries without an `ip` are skipped.
+pub fn read_servers_dat(
+    reader: &mut impl Read,
+) -> Result<Vec<ServerListEntry>, Box<dyn Error + Send + Sync>> {
+    let (_, root) = Tag::read_root(reader)?;
+    let servers = match root.get("servers") {
+        Some(Tag::List(_, servers)) => servers,
+        Some(_) => return Err("servers.dat `servers` is not a list".into()),
+        // the client omits the list when it is empty
+        None => return Ok(vec![]),
+    };
+    Ok(servers
+        .iter()
+        .filter_map(|server| {
+            let ip = server.get("ip")?.as_str()?.trim();
+            if ip.is_empty() {
+                return None;
+            }
+            Some(ServerListEntry {
+                name: server
+                    .get("name")
+                    .and_then(Tag::as_str)
+                    .unwrap_or_default()
+                    .to_string(),
+                ip: ip.to_string(),
+            })
+        })
+        .collect())
+}
+
+/// Writes `entries` as an uncompressed `servers.dat` the client can load.
+pub fn write_servers_dat(
+    writer: &mut impl Write,
+    entries: &[ServerListEntry],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let servers = entries
+        .iter()
+        .map(|entry| {
+            Tag::Compound(vec![
+                ("name".to_string(), Tag::String(entry.name.clone())),
+                ("ip".to_string(), Tag::String(entry.ip.clone())),
+            ])
+        })
+        .collect();
+    let root = Tag::Compound(vec![("servers".to_string(), Tag::List(10, servers))]);
+    root.write_root(writer, "")
+}
+
+/// The `host[:port]` form the client accepts for `addr`.
+pub fn format_host_port(addr: SocketAddr) -> String {
+    if addr.port() == DEFAULT_PORT {
+        addr.ip().to_string()
+    } else {
+        addr.to_string()
+    }
+}
+
+/// Splits `host[:port]`. The port is `None` when it is left out, in
+/// which case the client tries an SRV record before port 25565. IPv6
+/// literals are not supported because the map only stores IPv4 servers.
+pub fn parse_host_port(s: &str) -> Result<(&str, Option<u16>), Box<dyn Error + Send + Sync>> {
+    match s.rsplit_once(':') {
+        Some((host, port)) => Ok((
+            host,
+            Some(port.parse().map_err(|_| format!("invalid port in {s}"))?),
+        )),
+        None => Ok((s, None)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn servers_dat_round_trip() {
+        let entries = vec![
+            ServerListEntry {
+                name: "Local".to_string(),
+                ip: "127.0.0.1".to_string(),
+            },
+            ServerListEntry {
+                name: "Other port".to_string(),
+                ip: "10.0.0.1:25570".to_string(),
+            },
+        ];
+        let mut buf = vec![];
+        write_servers_dat(&mut buf, &entries).unwrap();
+        assert_eq!(read_servers_dat(&mut &buf[..]).unwrap(), entries);
+
+        assert_eq!(parse_host_port("127.0.0.1").unwrap(), ("127.0.0.1", None));
+        assert_eq!(
+            parse_host_port("10.0.0.1:25570").unwrap(),
+            ("10.0.0.1", Some(25570))
+        );
+        assert!(parse_host_port("example.com:port").is_err());
+        assert_eq!(
+            format_host_port("10.0.0.1:25565".parse().unwrap()),
+            "10.0.0.1"
+        );
+        assert_eq!(
+            format_host_port("10.0.0.1:1".parse().unwrap()),
+            "10.0.0.1:1"
+        );
+    }
+}
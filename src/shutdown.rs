@@ -0,0 +1,69 @@
+use std::error::Error;
+
+use tokio::sync::watch;
+
+/// Tells tasks to stop. Every clone sees the same signal.
+#[derive(Debug, Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+/// Stops every `Shutdown` made from it, when sent `true` or dropped.
+pub type Trigger = watch::Sender<bool>;
+
+pub fn channel() -> (Trigger, Shutdown) {
+    let (tx, rx) = watch::channel(false);
+    (tx, Shutdown(rx))
+}
+
+impl Shutdown {
+    /// Resolves once the trigger fires. Cancel-safe, so it can sit in a
+    /// `select!` next to the work it interrupts.
+    pub async fn wait(&mut self) {
+        while !*self.0.borrow_and_update() {
+            if self.0.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Resolves on the first SIGINT or SIGTERM, with the signal's name.
+pub async fn signal() -> Result<&'static str, Box<dyn Error + Send + Sync>> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            _ = interrupt.recv() => Ok("SIGINT"),
+            _ = terminate.recv() => Ok("SIGTERM"),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await?;
+        Ok("ctrl-c")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn wait_follows_the_trigger() {
+        let (trigger, mut shutdown) = channel();
+        let mut other = shutdown.clone();
+        let pending = tokio::time::timeout(Duration::from_millis(10), shutdown.wait()).await;
+        assert!(pending.is_err());
+
+        trigger.send(true).unwrap();
+        shutdown.wait().await;
+        other.wait().await;
+
+        let (trigger, mut shutdown) = channel();
+        drop(trigger);
+        shutdown.wait().await;
+    }
+}
@@ -0,0 +1,356 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+use integer_encoding::{VarIntReader, VarIntWriter};
+use parking_lot::Mutex;
+use threadpool::ThreadPool;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::metrics::METRICS;
+use crate::player_entry::{Player, PlayerArcWrapper, PlayerId};
+use crate::server_entry::{Server, ServerId};
+use crate::server_map::ServerMap;
+
+/// Held while a snapshot is written, so two writers never interleave
+/// their files.
+static WRITING: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// The files of a snapshot, relative to `Config::data_dir`.
+const SNAPSHOT_FILES: [&str; 2] = ["players.bin", "servers"];
+
+/// Writes the map to `Config::data_dir` and returns the number of bytes
+/// written. The snapshot is written to `snapshot.tmp` and renamed to
+/// `snapshot.new` once whole; from then on it is committed, and
+/// `finish_snapshot` moves it into place, here or on the next start.
+pub async fn serialize_all(
+    map: Arc<Mutex<ServerMap>>,
+    config: &Config,
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let _writing = WRITING.lock().await;
+    let start = Instant::now();
+    // Everything the workers read is copied under the map lock: every
+    // shard together with the servers it holds, and the player handles
+    // their pointers resolve through. Inserts and deletes after this can't
+    // hand a worker an id that means something else in its copy.
+    let (player_buf, shards, players) = {
+        let lock = METRICS.lock_map(&map);
+        let mut player_buf: Vec<u8> = vec![];
+        player_buf.write_varint(lock.players.len())?;
+        for player in &lock.players {
+            player_buf.write_all(&player.lock().serialize(&lock.servers)?)?;
+        }
+        let shards: Vec<(u16, Vec<Server>)> = lock
+            .server_array
+            .iter()
+            .map(|(ip_a, shard)| {
+                // in address order, so a reload hands out the same ids
+                let mut servers: Vec<Server> = shard
+                    .lock()
+                    .values()
+                    .flat_map(HashMap::values)
+                    .map(|server_id| lock.servers[*server_id as usize].lock().clone())
+                    .collect();
+                servers.sort_unstable_by_key(|server| server.addr);
+                (*ip_a, servers)
+            })
+            .collect();
+        (player_buf, shards, Arc::new(lock.players.to_owned()))
+    };
+
+    finish_snapshot(&config.data_dir)?;
+    let staging_dir = config.data_dir.join("snapshot.tmp");
+    if tokio::fs::try_exists(&staging_dir).await? {
+        tokio::fs::remove_dir_all(&staging_dir).await?;
+    }
+    let servers_dir = Arc::new(staging_dir.join("servers"));
+    tokio::fs::create_dir_all(&*servers_dir).await?;
+
+    let mut snapshot_bytes = player_buf.len() as u64;
+    tokio::fs::write(staging_dir.join("players.bin"), player_buf).await?;
+
+    let n_workers = config.snapshot_workers;
+    let n_jobs = shards.len();
+    let pool = ThreadPool::new(n_workers);
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+    for (ip_a, servers) in shards {
+        let tx = tx.clone();
+        let players = players.clone();
+        let servers_dir = servers_dir.clone();
+        pool.execute(move || {
+            let res = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                serialize_server_range(&servers_dir, ip_a, &servers, &players)
+            }))
+            .unwrap_or_else(|_| Err("snapshot worker panicked".into()));
+            // the receiver only goes away if the snapshot was dropped
+            let _ = tx.send(res);
+        });
+    }
+    // the channel closes once every job has reported or died
+    drop(tx);
+
+    let mut success = true;
+    let mut reported = 0;
+    while let Some(res) = rx.recv().await {
+        reported += 1;
+        match res {
+            Ok(len) => snapshot_bytes += len,
+            Err(err) => {
+                error!(error = %err, "could not write snapshot shard");
+                success = false;
+            }
+        }
+    }
+    if reported < n_jobs {
+        error!(
+            missing = n_jobs - reported,
+            "snapshot workers stopped without reporting"
+        );
+        success = false;
+    }
+
+    if !success {
+        // the live snapshot was never touched; every job has finished, so
+        // nothing writes to the staging directory anymore
+        tokio::fs::remove_dir_all(&staging_dir).await?;
+        return Err("snapshot incomplete".into());
+    }
+    tokio::fs::rename(&staging_dir, config.data_dir.join("snapshot.new")).await?;
+    finish_snapshot(&config.data_dir)?;
+    METRICS.snapshot(start.elapsed(), snapshot_bytes);
+    info!(
+        bytes = snapshot_bytes,
+        elapsed_ms = start.elapsed().as_millis() as u64,
+        "snapshot written"
+    );
+
+    Ok(snapshot_bytes)
+}
+
+fn serialize_server_range(
+    servers_dir: &Path,
+    ip_a: u16,
+    servers: &[Server],
+    players: &[PlayerArcWrapper],
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let segment_a: u8;
+    let segment_b: u8;
+    {
+        let segments = ip_a.to_be_bytes();
+        segment_a = segments[0];
+        segment_b = segments[1];
+    }
+    let segment_dir = servers_dir.join(segment_a.to_string());
+    std::fs::create_dir_all(&segment_dir)?;
+    let mut stack = std::collections::LinkedList::new();
+    for server in servers {
+        stack.push_back(server.serialize(players)?);
+    }
+    let total_len = stack.len();
+    let mut total_len_buf = vec![];
+    total_len_buf.write_varint(total_len)?;
+    let mut file = std::fs::File::create(segment_dir.join(format!("{segment_b}.bin")))?;
+    file.write_all(&total_len_buf)?;
+    let mut len = total_len_buf.len() as u64;
+    for server in stack {
+        file.write_all(&server)?;
+        len += server.len() as u64;
+    }
+
+    Ok(len)
+}
+
+/// Moves a committed snapshot from `snapshot.new` into place, returning
+/// whether there was one. Each step can be repeated, so this is safe to
+/// run again after a crash part way through.
+pub fn finish_snapshot(data_dir: &Path) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let new_dir = data_dir.join("snapshot.new");
+    if !new_dir.exists() {
+        return Ok(false);
+    }
+    for name in SNAPSHOT_FILES {
+        let new = new_dir.join(name);
+        if !new.exists() {
+            // already moved
+            continue;
+        }
+        let old = data_dir.join(name);
+        if old.is_dir() {
+            std::fs::remove_dir_all(&old)?;
+        } else if old.exists() {
+            std::fs::remove_file(&old)?;
+        }
+        std::fs::rename(new, old)?;
+    }
+    std::fs::remove_dir_all(new_dir)?;
+    Ok(true)
+}
+
+/// Loads the map from `Config::data_dir`, or an empty map if there is no
+/// snapshot yet. Links come from each player record's own server list,
+/// since a uuid pointer can't tell two names of one player apart; the
+/// pointers in the server records must agree with them. Finishes a
+/// snapshot that was interrupted first.
+pub async fn deserialize_all(config: &Config) -> Result<ServerMap, Box<dyn Error + Send + Sync>> {
+    if finish_snapshot(&config.data_dir)? {
+        info!("finished an interrupted snapshot");
+    }
+    let mut map = config.server_map();
+    let players_path = config.data_dir.join("players.bin");
+    if !tokio::fs::try_exists(&players_path).await? {
+        return Ok(map);
+    }
+
+    let player_buf = tokio::fs::read(&players_path).await?;
+    let mut reader = &player_buf[..];
+    let num_players: usize = reader.read_varint()?;
+    let mut player_servers: Vec<(PlayerId, Vec<SocketAddr>)> =
+        Vec::with_capacity(num_players.min(reader.len()));
+    for _ in 0..num_players {
+        let (player, servers) = Player::deserialize(&mut reader)?;
+        let player_id = map.insert_player(player)?;
+        player_servers.push((player_id, servers));
+    }
+
+    // shards are read in a fixed order, so ids are the same on every load
+    let mut server_uuids: BTreeMap<ServerId, Vec<Uuid>> = BTreeMap::new();
+    for segment_a in sorted_dir(&config.data_dir.join("servers")).await? {
+        for segment_b in sorted_dir(&segment_a).await? {
+            let server_buf = tokio::fs::read(segment_b).await?;
+            let mut reader = &server_buf[..];
+            let num_servers: usize = reader.read_varint()?;
+            for _ in 0..num_servers {
+                let (server, uuids) = Server::deserialize(&mut reader)?;
+                let server_id = map.merge_server(&server)?;
+                server_uuids.entry(server_id).or_default().extend(uuids);
+            }
+        }
+    }
+
+    for (player_id, servers) in player_servers {
+        for addr in servers {
+            match map.find_id(addr)? {
+                Some(server_id) => map.link(server_id, player_id),
+                None => {
+                    return Err(format!(
+                        "player {} points to unknown server {addr}",
+                        map.players[player_id as usize].uuid()
+                    )
+                    .into())
+                }
+            }
+        }
+    }
+
+    for (server_id, mut uuids) in server_uuids {
+        let server = &map.servers[server_id as usize];
+        let mut linked: Vec<Uuid> = server
+            .lock()
+            .players
+            .iter()
+            .map(|player_id| map.players[*player_id as usize].uuid())
+            .collect();
+        uuids.sort_unstable();
+        linked.sort_unstable();
+        if uuids != linked {
+            return Err(format!(
+                "server {} and the players that list it disagree",
+                server.addr()
+            )
+            .into());
+        }
+    }
+
+    Ok(map)
+}
+
+/// The paths in `dir`, sorted by name.
+async fn sorted_dir(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error + Send + Sync>> {
+    let mut paths = vec![];
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        paths.push(entry.path());
+    }
+    paths.sort_unstable();
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn renamed_players_keep_their_servers() {
+        let data_dir = std::env::temp_dir().join(format!("mcdb-snapshot-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&data_dir);
+        let config = Config {
+            data_dir: data_dir.clone(),
+            ..Config::default()
+        };
+        let uuid = Uuid::from_u128(1);
+        let mut map = ServerMap::new();
+        map.insert(
+            "10.0.0.1:25565".parse().unwrap(),
+            [Player::new("alice".to_string(), uuid)],
+        )
+        .unwrap();
+        map.insert(
+            "10.0.0.2:25565".parse().unwrap(),
+            [Player::new("alice2".to_string(), uuid)],
+        )
+        .unwrap();
+        for host in (3..20).rev() {
+            map.insert(format!("10.0.0.{host}:25565").parse().unwrap(), [])
+                .unwrap();
+        }
+        serialize_all(Arc::new(Mutex::new(map)), &config)
+            .await
+            .unwrap();
+
+        let map = deserialize_all(&config).await.unwrap();
+        let servers_of = |name: &str| -> Vec<SocketAddr> {
+            let player_id = map.player_array[&(name.to_string(), uuid)];
+            let player = map.players[player_id as usize].lock();
+            player
+                .servers
+                .iter()
+                .map(|server_id| map.servers[*server_id as usize].addr())
+                .collect()
+        };
+        assert_eq!(servers_of("alice"), ["10.0.0.1:25565".parse().unwrap()]);
+        assert_eq!(servers_of("alice2"), ["10.0.0.2:25565".parse().unwrap()]);
+        // ids follow address order, whatever order the map was filled in
+        let addrs: Vec<SocketAddr> = map.servers.iter().map(|server| server.addr()).collect();
+        assert!(addrs.is_sorted());
+        assert!(!data_dir.join("snapshot.tmp").exists());
+        assert!(!data_dir.join("snapshot.new").exists());
+
+        // a crash after players.bin was moved in and the old servers/ removed
+        std::fs::create_dir(data_dir.join("snapshot.new")).unwrap();
+        std::fs::rename(
+            data_dir.join("servers"),
+            data_dir.join("snapshot.new/servers"),
+        )
+        .unwrap();
+        let map = deserialize_all(&config).await.unwrap();
+        assert_eq!(map.servers.len(), 19);
+        assert!(!data_dir.join("snapshot.new").exists());
+
+        // a shard that fails to write leaves the last snapshot in place
+        let map = Arc::new(Mutex::new(map));
+        map.lock().servers[0].lock().players.push(99);
+        assert!(serialize_all(map, &config).await.is_err());
+        assert!(!data_dir.join("snapshot.tmp").exists());
+        assert_eq!(deserialize_all(&config).await.unwrap().servers.len(), 19);
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+}
@@ -0,0 +1,126 @@
+use std::error::Error;
+use std::path::Path;
+
+use rusqlite::{params, Connection};
+
+use crate::server_map::ServerMap;
+
+/// Row ids are the in-memory `ServerId` / `PlayerId`, so `sightings`
+/// joins without any lookups. Player uuids are not unique on their own
+/// because a player is keyed by name and uuid.
+const SCHEMA: &str = "
+CREATE TABLE servers (
+    id INTEGER PRIMARY KEY,
+    ip TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    first_seen INTEGER NOT NULL,
+    last_seen INTEGER NOT NULL,
+    UNIQUE (ip, port)
+);
+CREATE TABLE players (
+    id INTEGER PRIMARY KEY,
+    uuid TEXT NOT NULL,
+    name TEXT NOT NULL,
+    UNIQUE (uuid, name)
+);
+CREATE TABLE sightings (
+    server_id INTEGER NOT NULL REFERENCES servers (id),
+    player_id INTEGER NOT NULL REFERENCES players (id),
+    PRIMARY KEY (server_id, player_id)
+) WITHOUT ROWID;
+CREATE INDEX servers_last_seen ON servers (last_seen);
+CREATE INDEX players_name ON players (name COLLATE NOCASE);
+CREATE INDEX sightings_player_id ON sightings (player_id, server_id);
+";
+
+/// Materializes `map` into a new SQLite database at `path`, replacing
+/// any existing file. The database is built next to `path` and renamed
+/// into place, so readers never see a half written file.
+///
+/// mcdb has no write-ahead log to follow yet, so the mirror is refreshed
+/// by exporting again rather than updated incrementally.
+pub fn write_sqlite(map: &ServerMap, path: &Path) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let tmp_path = path.with_extension("tmp");
+    if tmp_path.exists() {
+        std::fs::remove_file(&tmp_path)?;
+    }
+
+    let mut conn = Connection::open(&tmp_path)?;
+    conn.execute_batch("PRAGMA journal_mode = OFF; PRAGMA synchronous = OFF;")?;
+    let tx = conn.transaction()?;
+    tx.execute_batch(SCHEMA)?;
+    {
+        let mut insert_player =
+            tx.prepare("INSERT INTO players (id, uuid, name) VALUES (?1, ?2, ?3)")?;
+        for (player_id, player) in map.players.iter().enumerate() {
+            let name = player.lock().name.clone();
+            insert_player.execute(params![player_id as i64, player.uuid().to_string(), name])?;
+        }
+
+        let mut insert_server = tx.prepare(
+            "INSERT INTO servers (id, ip, port, first_seen, last_seen) VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        let mut insert_sighting =
+            tx.prepare("INSERT INTO sightings (server_id, player_id) VALUES (?1, ?2)")?;
+        for (server_id, server) in map.servers.iter().enumerate() {
+            let server = server.lock().clone();
+            insert_server.execute(params![
+                server_id as i64,
+                server.addr.ip().to_string(),
+                server.addr.port(),
+                server.first_seen as i64,
+                server.last_seen as i64,
+            ])?;
+            for player_id in server.players {
+                insert_sighting.execute(params![server_id as i64, player_id as i64])?;
+            }
+        }
+    }
+    tx.commit()?;
+    conn.close().map_err(|(_conn, err)| err)?;
+
+    std::fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::player_entry::Player;
+
+    #[test]
+    fn sightings_join() {
+        let mut map = ServerMap::new();
+        let alice = Player::new("alice".to_string(), Uuid::from_u128(1));
+        let bob = Player::new("bob".to_string(), Uuid::from_u128(2));
+        map.insert("10.0.0.1:25565".parse().unwrap(), [alice.clone(), bob])
+            .unwrap();
+        map.insert("10.0.0.2:25565".parse().unwrap(), [alice])
+            .unwrap();
+
+        let path = std::env::temp_dir().join(format!("mcdb-sqlite-{}.db", std::process::id()));
+        write_sqlite(&map, &path).unwrap();
+
+        let conn = Connection::open(&path).unwrap();
+        let mut stmt = conn
+            .prepare(
+                "SELECT servers.ip FROM sightings
+                JOIN servers ON servers.id = sightings.server_id
+                JOIN players ON players.id = sightings.player_id
+                WHERE players.name = 'alice' ORDER BY servers.ip",
+            )
+            .unwrap();
+        let ips: Vec<String> = stmt
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(|ip| ip.unwrap())
+            .collect();
+        assert_eq!(ips, vec!["10.0.0.1", "10.0.0.2"]);
+
+        drop(stmt);
+        drop(conn);
+        std::fs::remove_file(path).unwrap();
+    }
+}
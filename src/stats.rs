@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+
+use serde::Serialize;
+
+/// Seconds per bucket of `Stats::last_seen_hours`.
+const HOUR: u64 = 60 * 60;
+pub const DAY: u64 = 24 * HOUR;
+pub const WEEK: u64 = 7 * DAY;
+pub const MONTH: u64 = 30 * DAY;
+
+/// Counters kept up to date by every `ServerMap` mutation, so reports
+/// never scan the map. Counts by version and protocol come from the
+/// attribute indexes instead.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// Number of distinct server–player links.
+    pub links: usize,
+    /// Number of servers per first octet.
+    pub per_slash8: BTreeMap<u8, usize>,
+    pub per_port: BTreeMap<u16, usize>,
+    /// Number of servers by the hour of their latest sighting, keyed by
+    /// unix time divided by `HOUR`. Servers never sighted are left out.
+    last_seen_hours: BTreeMap<u64, usize>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_server(&mut self, addr: SocketAddr) {
+        if let IpAddr::V4(ip) = addr.ip() {
+            *self.per_slash8.entry(ip.octets()[0]).or_default() += 1;
+        }
+        *self.per_port.entry(addr.port()).or_default() += 1;
+    }
+
+    pub fn remove_server(&mut self, addr: SocketAddr) {
+        if let IpAddr::V4(ip) = addr.ip() {
+            decrement(&mut self.per_slash8, ip.octets()[0]);
+        }
+        decrement(&mut self.per_port, addr.port());
+    }
+
+    /// Moves a server whose latest sighting went from `old` to `new`, 0
+    /// meaning never sighted.
+    pub fn last_seen_changed(&mut self, old: u64, new: u64) {
+        if old / HOUR == new / HOUR && (old == 0) == (new == 0) {
+            return;
+        }
+        if old != 0 {
+            let hour = old / HOUR;
+            if let Some(count) = self.last_seen_hours.get_mut(&hour) {
+                *count -= 1;
+                if *count == 0 {
+                    self.last_seen_hours.remove(&hour);
+                }
+            }
+        }
+        if new != 0 {
+            *self.last_seen_hours.entry(new / HOUR).or_default() += 1;
+        }
+    }
+
+    /// Number of servers last seen at or after `since`, to the hour.
+    pub fn seen_since(&self, since: u64) -> usize {
+        self.last_seen_hours
+            .range(since / HOUR..)
+            .map(|(_, count)| count)
+            .sum()
+    }
+}
+
+fn decrement<K: Ord>(counts: &mut BTreeMap<K, usize>, key: K) {
+    if let Some(count) = counts.get_mut(&key) {
+        *count -= 1;
+        if *count == 0 {
+            counts.remove(&key);
+        }
+    }
+}
+
+/// A point-in-time report of `ServerMap::stats`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct StatsReport {
+    pub servers: usize,
+    /// Number of player records; a player has one per name.
+    pub players: usize,
+    /// Number of distinct player uuids.
+    pub uuids: usize,
+    pub links: usize,
+    pub per_slash8: BTreeMap<u8, usize>,
+    pub per_port: BTreeMap<u16, usize>,
+    pub per_version: BTreeMap<String, usize>,
+    pub per_protocol: BTreeMap<i64, usize>,
+    pub seen_last_day: usize,
+    pub seen_last_week: usize,
+    pub seen_last_month: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_follow_sightings() {
+        let mut stats = Stats::new();
+        stats.add_server("10.0.0.1:25565".parse().unwrap());
+        stats.add_server("10.0.0.2:25566".parse().unwrap());
+        assert_eq!(stats.per_slash8[&10], 2);
+        assert_eq!(stats.per_port[&25566], 1);
+
+        let now = 100 * DAY;
+        stats.last_seen_changed(0, now - 2 * DAY);
+        stats.last_seen_changed(0, now - 10);
+        assert_eq!(stats.seen_since(now - DAY), 1);
+        assert_eq!(stats.seen_since(now - WEEK), 2);
+        stats.last_seen_changed(now - 2 * DAY, now);
+        assert_eq!(stats.seen_since(now - DAY), 2);
+        stats.last_seen_changed(now, 0);
+        assert_eq!(stats.seen_since(0), 1);
+    }
+}
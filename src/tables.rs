@@ -0,0 +1,379 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::path::Path;
+
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::cidr::Cidr;
+use crate::player_entry::PlayerId;
+use crate::server_entry::ServerId;
+use crate::server_map::ServerMap;
+
+/// Restricts which servers are exported. Players and sightings follow
+/// the servers that pass.
+#[derive(Debug, Clone, Default)]
+pub struct TableFilter {
+    /// Servers must be in one of these networks. Empty means every network.
+    pub cidrs: Vec<Cidr>,
+    /// Servers must have been seen at or after this unix time.
+    pub seen_after: Option<u64>,
+    /// Servers must have been seen at or before this unix time.
+    pub seen_before: Option<u64>,
+}
+
+impl TableFilter {
+    pub fn is_empty(&self) -> bool {
+        self.cidrs.is_empty() && self.seen_after.is_none() && self.seen_before.is_none()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ServerRow {
+    pub ip: String,
+    pub port: u16,
+    pub first_seen: u64,
+    pub last_seen: u64,
+    pub players: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlayerRow {
+    pub uuid: Uuid,
+    pub name: String,
+    /// Number of exported servers the player was seen on.
+    pub servers: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SightingRow {
+    pub ip: String,
+    pub port: u16,
+    pub uuid: Uuid,
+}
+
+/// The map as normalized `servers`, `players` and `server_players` tables.
+#[derive(Debug, Default)]
+pub struct Tables {
+    pub servers: Vec<ServerRow>,
+    pub players: Vec<PlayerRow>,
+    pub sightings: Vec<SightingRow>,
+}
+
+impl Tables {
+    pub fn collect(map: &ServerMap, filter: &TableFilter) -> Self {
+        let mut tables = Tables::default();
+        let cidrs = if filter.cidrs.is_empty() {
+            vec![Cidr::ALL]
+        } else {
+            filter.cidrs.clone()
+        };
+
+        let mut server_ids = BTreeSet::new();
+        for cidr in cidrs {
+            server_ids.extend(map.servers_in(cidr));
+        }
+
+        let mut player_ids: BTreeSet<PlayerId> = BTreeSet::new();
+        let mut exported: BTreeSet<ServerId> = BTreeSet::new();
+        for server_id in server_ids {
+            let server = map.servers[server_id as usize].lock().clone();
+            if filter
+                .seen_after
+                .is_some_and(|after| server.last_seen < after)
+                || filter
+                    .seen_before
+                    .is_some_and(|before| server.first_seen > before)
+            {
+                continue;
+            }
+            exported.insert(server_id);
+            let ip = server.addr.ip().to_string();
+            tables.servers.push(ServerRow {
+                ip: ip.clone(),
+                port: server.addr.port(),
+                first_seen: server.first_seen,
+                last_seen: server.last_seen,
+                players: server.players.len() as u64,
+            });
+            for player_id in &server.players {
+                tables.sightings.push(SightingRow {
+                    ip: ip.clone(),
+                    port: server.addr.port(),
+                    uuid: map.players[*player_id as usize].uuid(),
+                });
+            }
+            player_ids.extend(server.players);
+        }
+
+        if filter.is_empty() {
+            // keep players that were never seen on a server
+            player_ids.extend(0..map.players.len() as PlayerId);
+        }
+        for player_id in player_ids {
+            let player = map.players[player_id as usize].lock();
+            tables.players.push(PlayerRow {
+                uuid: player.uuid,
+                name: player.name.clone(),
+                servers: player
+                    .servers
+                    .iter()
+                    .filter(|server_id| exported.contains(server_id))
+                    .count() as u64,
+            });
+        }
+
+        tables
+    }
+
+    /// Writes `servers.csv`, `players.csv` and `server_players.csv` to `dir`.
+    pub fn write_csv(&self, dir: &Path) -> Result<(), Box<dyn Error + Send + Sync>> {
+        std::fs::create_dir_all(dir)?;
+        write_csv_table(
+            &dir.join("servers.csv"),
+            &["ip", "port", "first_seen", "last_seen", "players"],
+            &self.servers,
+        )?;
+        write_csv_table(
+            &dir.join("players.csv"),
+            &["uuid", "name", "servers"],
+            &self.players,
+        )?;
+        write_csv_table(
+            &dir.join("server_players.csv"),
+            &["ip", "port", "uuid"],
+            &self.sightings,
+        )?;
+        Ok(())
+    }
+
+    /// Writes `servers.parquet`, `players.parquet` and
+    /// `server_players.parquet` to `dir`.
+    #[cfg(feature = "parquet")]
+    pub fn write_parquet(&self, dir: &Path) -> Result<(), Box<dyn Error + Send + Sync>> {
+        use parquet::data_type::{ByteArray, ByteArrayType, Int32Type, Int64Type};
+
+        std::fs::create_dir_all(dir)?;
+
+        let mut writer = create_parquet(
+            &dir.join("servers.parquet"),
+            "message servers {
+                required binary ip (UTF8);
+                required int32 port;
+                required int64 first_seen;
+                required int64 last_seen;
+                required int64 players;
+            }",
+        )?;
+        let mut row_group = writer.next_row_group()?;
+        let rows = &self.servers;
+        write_parquet_column::<ByteArrayType>(
+            &mut row_group,
+            rows.iter()
+                .map(|row| ByteArray::from(row.ip.as_str()))
+                .collect(),
+        )?;
+        write_parquet_column::<Int32Type>(
+            &mut row_group,
+            rows.iter().map(|row| row.port as i32).collect(),
+        )?;
+        write_parquet_column::<Int64Type>(
+            &mut row_group,
+            rows.iter().map(|row| row.first_seen as i64).collect(),
+        )?;
+        write_parquet_column::<Int64Type>(
+            &mut row_group,
+            rows.iter().map(|row| row.last_seen as i64).collect(),
+        )?;
+        write_parquet_column::<Int64Type>(
+            &mut row_group,
+            rows.iter().map(|row| row.players as i64).collect(),
+        )?;
+        row_group.close()?;
+        writer.close()?;
+
+        let mut writer = create_parquet(
+            &dir.join("players.parquet"),
+            "message players {
+                required binary uuid (UTF8);
+                required binary name (UTF8);
+                required int64 servers;
+            }",
+        )?;
+        let mut row_group = writer.next_row_group()?;
+        let rows = &self.players;
+        write_parquet_column::<ByteArrayType>(
+            &mut row_group,
+            rows.iter()
+                .map(|row| ByteArray::from(row.uuid.to_string().as_str()))
+                .collect(),
+        )?;
+        write_parquet_column::<ByteArrayType>(
+            &mut row_group,
+            rows.iter()
+                .map(|row| ByteArray::from(row.name.as_str()))
+                .collect(),
+        )?;
+        write_parquet_column::<Int64Type>(
+            &mut row_group,
+            rows.iter().map(|row| row.servers as i64).collect(),
+        )?;
+        row_group.close()?;
+        writer.close()?;
+
+        let mut writer = create_parquet(
+            &dir.join("server_players.parquet"),
+            "message server_players {
+                required binary ip (UTF8);
+                required int32 port;
+                required binary uuid (UTF8);
+            }",
+        )?;
+        let mut row_group = writer.next_row_group()?;
+        let rows = &self.sightings;
+        write_parquet_column::<ByteArrayType>(
+            &mut row_group,
+            rows.iter()
+                .map(|row| ByteArray::from(row.ip.as_str()))
+                .collect(),
+        )?;
+        write_parquet_column::<Int32Type>(
+            &mut row_group,
+            rows.iter().map(|row| row.port as i32).collect(),
+        )?;
+        write_parquet_column::<ByteArrayType>(
+            &mut row_group,
+            rows.iter()
+                .map(|row| ByteArray::from(row.uuid.to_string().as_str()))
+                .collect(),
+        )?;
+        row_group.close()?;
+        writer.close()?;
+
+        Ok(())
+    }
+}
+
+/// Writes `header` explicitly so that empty tables still have one.
+fn write_csv_table<T: Serialize>(
+    path: &Path,
+    header: &[&str],
+    rows: &[T],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_path(path)?;
+    writer.write_record(header)?;
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(feature = "parquet")]
+fn create_parquet(
+    path: &Path,
+    schema: &str,
+) -> Result<parquet::file::writer::SerializedFileWriter<std::fs::File>, Box<dyn Error + Send + Sync>>
+{
+    use parquet::file::properties::WriterProperties;
+    use parquet::schema::parser::parse_message_type;
+
+    let schema = std::sync::Arc::new(parse_message_type(schema)?);
+    let props = std::sync::Arc::new(WriterProperties::builder().build());
+    let file = std::fs::File::create(path)?;
+    Ok(parquet::file::writer::SerializedFileWriter::new(
+        file, schema, props,
+    )?)
+}
+
+#[cfg(feature = "parquet")]
+fn write_parquet_column<T: parquet::data_type::DataType>(
+    row_group: &mut parquet::file::writer::SerializedRowGroupWriter<'_, std::fs::File>,
+    values: Vec<T::T>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut column = row_group
+        .next_column()?
+        .ok_or("more columns than the schema")?;
+    column.typed::<T>().write_batch(&values, None, None)?;
+    column.close()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player_entry::Player;
+
+    #[test]
+    fn filters_follow_servers() {
+        let mut map = ServerMap::new();
+        let alice = Player::new("alice".to_string(), Uuid::from_u128(1));
+        let bob = Player::new("bob".to_string(), Uuid::from_u128(2));
+        map.insert("10.0.0.1:25565".parse().unwrap(), [alice.clone()])
+            .unwrap();
+        map.insert("11.0.0.1:25565".parse().unwrap(), [alice, bob])
+            .unwrap();
+        map.insert_player(Player::new("carol".to_string(), Uuid::from_u128(3)))
+            .unwrap();
+
+        let tables = Tables::collect(&map, &TableFilter::default());
+        assert_eq!(tables.servers.len(), 2);
+        assert_eq!(tables.players.len(), 3);
+        assert_eq!(tables.players[0].servers, 2);
+        assert_eq!(tables.sightings.len(), 3);
+
+        let filter = TableFilter {
+            cidrs: vec!["10.0.0.0/8".parse().unwrap()],
+            ..Default::default()
+        };
+        let tables = Tables::collect(&map, &filter);
+        assert_eq!(tables.servers.len(), 1);
+        assert_eq!(tables.servers[0].ip, "10.0.0.1");
+        assert_eq!(tables.players.len(), 1);
+        assert_eq!(tables.players[0].name, "alice");
+        // only the servers that were exported
+        assert_eq!(tables.players[0].servers, 1);
+        assert_eq!(tables.sightings.len(), 1);
+
+        let filter = TableFilter {
+            seen_after: Some(u64::MAX),
+            ..Default::default()
+        };
+        let tables = Tables::collect(&map, &filter);
+        assert!(tables.servers.is_empty());
+        assert!(tables.players.is_empty());
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn parquet_tables_are_readable() {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+        use parquet::record::RowAccessor;
+
+        let mut map = ServerMap::new();
+        let alice = Player::new("alice".to_string(), Uuid::from_u128(1));
+        map.insert("10.0.0.1:25565".parse().unwrap(), [alice])
+            .unwrap();
+        map.insert("11.0.0.1:25565".parse().unwrap(), []).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("mcdb-parquet-{}", std::process::id()));
+        Tables::collect(&map, &TableFilter::default())
+            .write_parquet(&dir)
+            .unwrap();
+
+        for (table, rows) in [("servers", 2), ("players", 1), ("server_players", 1)] {
+            let file = std::fs::File::open(dir.join(format!("{table}.parquet"))).unwrap();
+            let reader = SerializedFileReader::new(file).unwrap();
+            assert_eq!(reader.metadata().file_metadata().num_rows(), rows);
+        }
+        let file = std::fs::File::open(dir.join("servers.parquet")).unwrap();
+        let reader = SerializedFileReader::new(file).unwrap();
+        let first = reader.get_row_iter(None).unwrap().next().unwrap().unwrap();
+        assert_eq!(first.get_string(0).unwrap(), "10.0.0.1");
+        assert_eq!(first.get_int(1).unwrap(), 25565);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}